@@ -40,6 +40,38 @@ impl COSESign1Builder {
         self.external_aad = Some(external_aad);
     }
 
+    /// Sets the protected "address" header to the governance id of `credential` and
+    /// the algorithm to EdDSA as governance keys are always Ed25519 keys.
+    pub fn set_governance_credential(&mut self, credential: &GovernanceCredential, format: GovernanceIdFormat) {
+        let mut protected = self.headers.protected.deserialized_headers();
+        protected.set_algorithm_id(&AlgorithmId::EdDSA.into());
+        protected.other_headers.insert(
+            Label::new_text(String::from("address")),
+            CBORValue::new_bytes(credential.to_id_bytes(format)));
+        self.headers.protected = ProtectedHeaderMap::new(&protected);
+    }
+
+    /// Preset for signing with a DRep key. `public_key` is the 32-byte Ed25519 DRep key.
+    pub fn set_drep_key(&mut self, public_key: Vec<u8>, format: GovernanceIdFormat) -> Result<(), JsError> {
+        let credential = GovernanceCredential::from_public_key(GovernanceRole::DRep, public_key)?;
+        self.set_governance_credential(&credential, format);
+        Ok(())
+    }
+
+    /// Preset for signing with a Constitutional Committee hot key
+    pub fn set_cc_hot_key(&mut self, public_key: Vec<u8>, format: GovernanceIdFormat) -> Result<(), JsError> {
+        let credential = GovernanceCredential::from_public_key(GovernanceRole::CCHot, public_key)?;
+        self.set_governance_credential(&credential, format);
+        Ok(())
+    }
+
+    /// Preset for signing with a Constitutional Committee cold key
+    pub fn set_cc_cold_key(&mut self, public_key: Vec<u8>, format: GovernanceIdFormat) -> Result<(), JsError> {
+        let credential = GovernanceCredential::from_public_key(GovernanceRole::CCCold, public_key)?;
+        self.set_governance_credential(&credential, format);
+        Ok(())
+    }
+
     pub fn make_data_to_sign(&self) -> SigStructure {
         SigStructure::new(
             SigContext::Signature1,
@@ -113,13 +145,331 @@ impl COSESignBuilder {
     }
 }
 
-// TODO: copy the COSESign(1) builders for COSEEncrypt(1) if this seems like a good approach
+/// Builds a CWT (RFC 8392) with the claims set as the (attached) COSE_Sign1 payload
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct CWTBuilder(COSESign1Builder);
+
+#[wasm_bindgen]
+impl CWTBuilder {
+    pub fn new(headers: &Headers, claims: &CWTClaims) -> Self {
+        Self(COSESign1Builder::new(headers, claims.to_bytes(), false))
+    }
+
+    pub fn make_data_to_sign(&self) -> SigStructure {
+        self.0.make_data_to_sign()
+    }
+
+    pub fn build(&self, signed_sig_structure: Vec<u8>) -> CWT {
+        CWT::new(&self.0.build(signed_sig_structure))
+    }
+}
+
+/// Builds an SD-CWT: a CWT where some claims are replaced by salted hashes and the
+/// claims themselves are sent as disclosures in the unprotected headers
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct SDCWTBuilder {
+    headers: Headers,
+    payload: CBORObject,
+    disclosures: SDCWTDisclosures,
+}
+
+#[wasm_bindgen]
+impl SDCWTBuilder {
+    pub fn new(headers: &Headers, claims: &CWTClaims) -> Self {
+        Self {
+            headers: headers.clone(),
+            payload: claims.to_object(),
+            disclosures: SDCWTDisclosures::new(),
+        }
+    }
+
+    /// Redacts the top-level claim `key`. iss, aud, exp, nbf and iat can't be redacted.
+    /// # Arguments
+    /// * `salt` - at least 16 random bytes, different for every claim
+    pub fn redact_claim(&mut self, key: &CBORValue, salt: Vec<u8>) -> Result<SDCWTDisclosure, JsError> {
+        let disclosure = redact_claim(&mut self.payload, key, salt)?;
+        self.disclosures.add(&disclosure);
+        Ok(disclosure)
+    }
+
+    pub fn make_data_to_sign(&self) -> SigStructure {
+        self.sign1_builder().make_data_to_sign()
+    }
+
+    pub fn build(&self, signed_sig_structure: Vec<u8>) -> SDCWT {
+        SDCWT::new(&CWT::new(&self.sign1_builder().build(signed_sig_structure)))
+    }
+}
+
+impl SDCWTBuilder {
+    fn sign1_builder(&self) -> COSESign1Builder {
+        let mut headers = self.headers.clone();
+        set_disclosure_headers(&mut headers, &self.disclosures);
+        COSESign1Builder::new(&headers, self.payload.to_bytes(), false)
+    }
+}
+
+/// Builds a COSE_Encrypt0. Either encrypt with `encrypt()` or use an external AEAD implementation
+/// (e.g. a hardware key) over `make_data_to_encrypt()` as its additional data, then pass the ciphertext to `build()`.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEEncrypt0Builder {
+    headers: Headers,
+    plaintext: Vec<u8>,
+    external_aad: Option<Vec<u8>>,
+    is_ciphertext_external: bool,
+}
+
+#[wasm_bindgen]
+impl COSEEncrypt0Builder {
+    /// If neither an IV nor a partial IV is set in `headers` a random IV is put in the unprotected headers.
+    pub fn new(headers: &Headers, plaintext: Vec<u8>, is_ciphertext_external: bool) -> Result<COSEEncrypt0Builder, JsError> {
+        let mut headers = headers.clone();
+        encryption::generate_init_vector(&mut headers)?;
+        Ok(Self {
+            headers,
+            plaintext,
+            external_aad: None,
+            is_ciphertext_external,
+        })
+    }
+
+    pub fn set_external_aad(&mut self, external_aad: Vec<u8>) {
+        self.external_aad = Some(external_aad);
+    }
+
+    /// The Enc_structure to use as the additional data when encrypting externally
+    pub fn make_data_to_encrypt(&self) -> EncStructure {
+        EncStructure::new(
+            EncContext::Encrypt0,
+            &self.headers.protected,
+            self.external_aad.clone().unwrap_or_default())
+    }
+
+    /// Encrypts the plaintext with the symmetric `key` using the protected algorithm and returns the ciphertext
+    pub fn encrypt(&self, key: &COSEKey) -> Result<Vec<u8>, JsError> {
+        let alg = encryption::content_algorithm(&self.headers)?;
+        let k = encryption::symmetric_key(key, alg, KeyOperation::Encrypt)?;
+        let mut headers = self.headers.clone();
+        encryption::encrypt_content(&mut headers, EncContext::Encrypt0, &k, key.base_init_vector(), &self.plaintext, self.external_aad.clone())
+    }
+
+    pub fn build(&self, ciphertext: Vec<u8>) -> COSEEncrypt0 {
+        COSEEncrypt0::new(
+            &self.headers,
+            match self.is_ciphertext_external {
+                true => None,
+                false => Some(ciphertext),
+            })
+    }
+}
+
+/// Builds a COSE_Encrypt. The content key is chosen by the caller: create the recipient layers for it with
+/// `COSERecipient::for_key()` (or by hand) and add them, then either encrypt with `encrypt()` or use an external
+/// AEAD implementation over `make_data_to_encrypt()` as its additional data and pass the ciphertext to `build()`.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEEncryptBuilder {
+    headers: Headers,
+    plaintext: Vec<u8>,
+    external_aad: Option<Vec<u8>>,
+    is_ciphertext_external: bool,
+    recipients: COSERecipients,
+}
+
+#[wasm_bindgen]
+impl COSEEncryptBuilder {
+    /// If neither an IV nor a partial IV is set in `headers` a random IV is put in the unprotected headers.
+    pub fn new(headers: &Headers, plaintext: Vec<u8>, is_ciphertext_external: bool) -> Result<COSEEncryptBuilder, JsError> {
+        let mut headers = headers.clone();
+        encryption::generate_init_vector(&mut headers)?;
+        Ok(Self {
+            headers,
+            plaintext,
+            external_aad: None,
+            is_ciphertext_external,
+            recipients: COSERecipients::new(),
+        })
+    }
+
+    pub fn set_external_aad(&mut self, external_aad: Vec<u8>) {
+        self.external_aad = Some(external_aad);
+    }
+
+    pub fn add_recipient(&mut self, recipient: &COSERecipient) {
+        self.recipients.add(recipient);
+    }
+
+    /// The Enc_structure to use as the additional data when encrypting externally
+    pub fn make_data_to_encrypt(&self) -> EncStructure {
+        EncStructure::new(
+            EncContext::Encrypt,
+            &self.headers.protected,
+            self.external_aad.clone().unwrap_or_default())
+    }
+
+    /// Encrypts the plaintext with `content_key` using the protected algorithm and returns the ciphertext
+    pub fn encrypt(&self, content_key: Vec<u8>) -> Result<Vec<u8>, JsError> {
+        let mut headers = self.headers.clone();
+        encryption::encrypt_content(&mut headers, EncContext::Encrypt, &content_key, None, &self.plaintext, self.external_aad.clone())
+    }
+
+    pub fn build(&self, ciphertext: Vec<u8>) -> COSEEncrypt {
+        COSEEncrypt::new(
+            &self.headers,
+            match self.is_ciphertext_external {
+                true => None,
+                false => Some(ciphertext),
+            },
+            &self.recipients)
+    }
+}
+
+/// Builds a COSE_Mac0. Either compute the tag with `compute_tag()` or externally (e.g. with an HSM)
+/// over `make_data_to_mac()`, then pass it to `build()`.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEMac0Builder {
+    headers: Headers,
+    payload: Vec<u8>,
+    external_aad: Option<Vec<u8>>,
+    is_payload_external: bool,
+}
+
+#[wasm_bindgen]
+impl COSEMac0Builder {
+    pub fn new(headers: &Headers, payload: Vec<u8>, is_payload_external: bool) -> Self {
+        Self {
+            headers: headers.clone(),
+            payload,
+            external_aad: None,
+            is_payload_external,
+        }
+    }
+
+    pub fn set_external_aad(&mut self, external_aad: Vec<u8>) {
+        self.external_aad = Some(external_aad);
+    }
+
+    pub fn make_data_to_mac(&self) -> MacStructure {
+        MacStructure::new(
+            MacContext::Mac0,
+            &self.headers.protected,
+            self.external_aad.clone().unwrap_or_default(),
+            self.payload.clone())
+    }
+
+    /// The HMAC with the symmetric `key` using the protected algorithm (HMAC 256/256, 384/384 or 512/512)
+    pub fn compute_tag(&self, key: &COSEKey) -> Result<Vec<u8>, JsError> {
+        let alg = mac::mac_algorithm(&self.headers)?;
+        let k = encryption::symmetric_key(key, alg, KeyOperation::MACCreate)?;
+        mac::compute_tag(alg, &k, &self.make_data_to_mac().to_bytes())
+    }
+
+    pub fn build(&self, tag: Vec<u8>) -> COSEMac0 {
+        COSEMac0::new(
+            &self.headers,
+            match self.is_payload_external {
+                true => None,
+                false => Some(self.payload.clone()),
+            },
+            tag)
+    }
+}
+
+/// Builds a COSE_Mac. The MAC key is chosen by the caller (see `generate_mac_key()`) and distributed
+/// with recipient layers e.g. from `COSERecipient::for_key()`.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEMacBuilder {
+    headers: Headers,
+    payload: Vec<u8>,
+    external_aad: Option<Vec<u8>>,
+    is_payload_external: bool,
+    recipients: COSERecipients,
+}
+
+#[wasm_bindgen]
+impl COSEMacBuilder {
+    pub fn new(headers: &Headers, payload: Vec<u8>, is_payload_external: bool) -> Self {
+        Self {
+            headers: headers.clone(),
+            payload,
+            external_aad: None,
+            is_payload_external,
+            recipients: COSERecipients::new(),
+        }
+    }
+
+    pub fn set_external_aad(&mut self, external_aad: Vec<u8>) {
+        self.external_aad = Some(external_aad);
+    }
+
+    pub fn add_recipient(&mut self, recipient: &COSERecipient) {
+        self.recipients.add(recipient);
+    }
+
+    /// A random key of the hash output size of the protected algorithm
+    pub fn generate_mac_key(&self) -> Result<Vec<u8>, JsError> {
+        crypto::random_bytes(mac::mac_key_len(mac::mac_algorithm(&self.headers)?))
+    }
+
+    pub fn make_data_to_mac(&self) -> MacStructure {
+        MacStructure::new(
+            MacContext::Mac,
+            &self.headers.protected,
+            self.external_aad.clone().unwrap_or_default(),
+            self.payload.clone())
+    }
+
+    /// The HMAC with `mac_key` using the protected algorithm (HMAC 256/256, 384/384 or 512/512)
+    pub fn compute_tag(&self, mac_key: Vec<u8>) -> Result<Vec<u8>, JsError> {
+        mac::compute_tag(mac::mac_algorithm(&self.headers)?, &mac_key, &self.make_data_to_mac().to_bytes())
+    }
+
+    pub fn build(&self, tag: Vec<u8>) -> COSEMac {
+        COSEMac::new(
+            &self.headers,
+            match self.is_payload_external {
+                true => None,
+                false => Some(self.payload.clone()),
+            },
+            tag,
+            &self.recipients)
+    }
+}
 
 label_enum!(AlgorithmId {
     /// EdDSA (Pure EdDSA, not HashedEdDSA) - the algorithm used for Cardano addresses
     EdDSA = -8,
+    /// AES-GCM mode w/ 128-bit key, 128-bit tag
+    A128GCM = 1,
+    /// AES-GCM mode w/ 192-bit key, 128-bit tag
+    A192GCM = 2,
+    /// AES-GCM mode w/ 256-bit key, 128-bit tag
+    A256GCM = 3,
+    /// HMAC w/ SHA-256, 256-bit tag
+    HMAC256 = 5,
+    /// HMAC w/ SHA-384, 384-bit tag
+    HMAC384 = 6,
+    /// HMAC w/ SHA-512, 512-bit tag
+    HMAC512 = 7,
     /// ChaCha20/Poly1305 w/ 256-bit key, 128-bit tag
     ChaCha20Poly1305 = 24,
+    /// HPKE base mode w/ DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and ChaCha20/Poly1305 - draft-ietf-cose-hpke
+    HPKEX25519SHA256ChaCha20Poly1305 = 40,
+    /// AES Key Wrap w/ 128-bit key
+    A128KW = -3,
+    /// AES Key Wrap w/ 192-bit key
+    A192KW = -4,
+    /// AES Key Wrap w/ 256-bit key
+    A256KW = -5,
+    /// Direct use of the recipient's shared symmetric key as the content key
+    Direct = -6,
+    /// ECDH-ES w/ HKDF-SHA256 to derive the key that wraps the content key w/ A256KW
+    ECDHESA256KW = -31,
 });
 
 label_enum!(KeyType {
@@ -141,6 +491,11 @@ label_enum!(ECKey {
     D = -4,
 });
 
+label_enum!(SymmetricKeyParam {
+    // key value - RFC 8152 section 13.2
+    K = -1,
+});
+
 label_enum!(CurveType {
     P256 = 1,
     P384 = 2,
@@ -150,6 +505,8 @@ label_enum!(CurveType {
     // the EdDSA variant used for cardano addresses
     Ed25519 = 6,
     Ed448 = 7,
+    // the SECG curve used by Bitcoin/Ethereum - RFC 8812
+    Secp256k1 = 8,
 });
 
 label_enum!(KeyOperation {
@@ -169,6 +526,10 @@ label_enum!(KeyOperation {
     DeriveKey = 7,
     // The key is used for deriving bits not to be used as a key. Requires private key fields
     DeriveBits = 8,
+    // The key is used for creating MACs.
+    MACCreate = 9,
+    // The key is used for validating MACs.
+    MACVerify = 10,
 });
 
 #[wasm_bindgen]
@@ -195,6 +556,14 @@ impl EdDSA25519Key {
         self.prvkey_bytes = Some(private_key_bytes);
     }
 
+    pub fn public_key(&self) -> Vec<u8> {
+        self.pubkey_bytes.clone()
+    }
+
+    pub fn private_key(&self) -> Option<Vec<u8>> {
+        self.prvkey_bytes.clone()
+    }
+
     pub fn is_for_signing(&mut self) {
         self.for_signing = true;
     }
@@ -236,7 +605,514 @@ impl EdDSA25519Key {
     }
 }
 
-// TODO: a way to parse/check from COSEKey -> EdDSA25519 variant. Or should this be a wrapper not a builder?
+#[wasm_bindgen]
+impl EdDSA25519Key {
+    /// Parses an OKP Ed25519 key e.g. the COSE_Key returned by a CIP-30 wallet's signData().
+    /// Fails if the key is not an Ed25519 key or its algorithm (if present) isn't EdDSA.
+    pub fn from_cose_key(key: &COSEKey) -> Result<EdDSA25519Key, JsError> {
+        use std::convert::TryFrom;
+        if !matches!(KeyType::try_from(key.key_type()), Ok(KeyType::OKP)) {
+            return Err(JsError::from_str(&format!("Expected OKP key type, found: {:?}", key.key_type())));
+        }
+        if let Some(alg) = key.algorithm_id() {
+            if !matches!(AlgorithmId::try_from(alg.clone()), Ok(AlgorithmId::EdDSA)) {
+                return Err(JsError::from_str(&format!("Expected EdDSA algorithm, found: {:?}", alg)));
+            }
+        }
+        let crv = key.header(&ECKey::CRV.into())
+            .ok_or_else(|| JsError::from_str("Missing crv"))?;
+        if !matches!(value_to_label(&crv).and_then(CurveType::try_from), Ok(CurveType::Ed25519)) {
+            return Err(JsError::from_str(&format!("Expected Ed25519 curve, found: {:?}", crv)));
+        }
+        let pubkey_bytes = key.header(&ECKey::X.into())
+            .ok_or_else(|| JsError::from_str("Missing x (public key)"))
+            .and_then(|x| value_to_bytes(&x))?;
+        if pubkey_bytes.len() != 32 {
+            return Err(JsError::from_str(&format!("Ed25519 public key must be 32 bytes, found {}", pubkey_bytes.len())));
+        }
+        let prvkey_bytes = match key.header(&ECKey::D.into()) {
+            Some(d) => Some(value_to_bytes(&d)?),
+            None => None,
+        };
+        let (for_signing, for_verifying) = match key.key_ops() {
+            Some(ops) => (
+                ops.0.contains(&KeyOperation::Sign.into()),
+                ops.0.contains(&KeyOperation::Verify.into())),
+            None => (false, false),
+        };
+        Ok(Self {
+            pubkey_bytes,
+            prvkey_bytes,
+            for_signing,
+            for_verifying,
+        })
+    }
+}
+
+// The key length in bytes `alg` requires (None if any non-empty key will do) and the operations it can be used for
+fn symmetric_algorithm_use(alg: AlgorithmId) -> Result<(Option<usize>, &'static [KeyOperation]), JsError> {
+    const CONTENT_OPS: &[KeyOperation] = &[KeyOperation::Encrypt, KeyOperation::Decrypt];
+    const MAC_OPS: &[KeyOperation] = &[KeyOperation::MACCreate, KeyOperation::MACVerify];
+    const KW_OPS: &[KeyOperation] = &[KeyOperation::WrapKey, KeyOperation::UnwrapKey];
+    const DIRECT_OPS: &[KeyOperation] = &[KeyOperation::Encrypt, KeyOperation::Decrypt, KeyOperation::MACCreate, KeyOperation::MACVerify];
+    match alg {
+        AlgorithmId::A128GCM => Ok((Some(16), CONTENT_OPS)),
+        AlgorithmId::A192GCM => Ok((Some(24), CONTENT_OPS)),
+        AlgorithmId::A256GCM
+        | AlgorithmId::ChaCha20Poly1305 => Ok((Some(32), CONTENT_OPS)),
+        AlgorithmId::HMAC256
+        | AlgorithmId::HMAC384
+        | AlgorithmId::HMAC512 => Ok((None, MAC_OPS)),
+        AlgorithmId::A128KW => Ok((Some(16), KW_OPS)),
+        AlgorithmId::A192KW => Ok((Some(24), KW_OPS)),
+        AlgorithmId::A256KW => Ok((Some(32), KW_OPS)),
+        AlgorithmId::Direct => Ok((None, DIRECT_OPS)),
+        _ => Err(JsError::from_str(&format!("{:?} is not a symmetric key algorithm", alg))),
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct SymmetricKey {
+    k: Vec<u8>,
+    key_id: Option<Vec<u8>>,
+    algorithm_id: Option<AlgorithmId>,
+    key_ops: Vec<KeyOperation>,
+    base_init_vector: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl SymmetricKey {
+    pub fn new(k: Vec<u8>) -> Self {
+        Self {
+            k,
+            key_id: None,
+            algorithm_id: None,
+            key_ops: Vec::new(),
+            base_init_vector: None,
+        }
+    }
+
+    pub fn key(&self) -> Vec<u8> {
+        self.k.clone()
+    }
+
+    pub fn set_key_id(&mut self, key_id: Vec<u8>) {
+        self.key_id = Some(key_id);
+    }
+
+    pub fn key_id(&self) -> Option<Vec<u8>> {
+        self.key_id.clone()
+    }
+
+    /// Restricts the key to `alg`: an AES-GCM / ChaCha20-Poly1305 content algorithm, HMAC, AES key wrap or direct
+    pub fn set_algorithm_id(&mut self, alg: AlgorithmId) {
+        self.algorithm_id = Some(alg);
+    }
+
+    pub fn algorithm_id(&self) -> Option<Label> {
+        self.algorithm_id.map(Label::from)
+    }
+
+    /// Restricts the key to the operations added. Symmetric keys can be used to Encrypt/Decrypt,
+    /// MACCreate/MACVerify or WrapKey/UnwrapKey according to their algorithm.
+    pub fn add_key_op(&mut self, op: KeyOperation) {
+        if !self.key_ops.iter().any(|key_op| Label::from(*key_op) == op.into()) {
+            self.key_ops.push(op);
+        }
+    }
+
+    pub fn key_ops(&self) -> Labels {
+        Labels(self.key_ops.iter().copied().map(Label::from).collect())
+    }
+
+    /// Base IV (label 5) combined with the partial IV of each message as in RFC 8152 section 3.1
+    pub fn set_base_init_vector(&mut self, base_init_vector: Vec<u8>) {
+        self.base_init_vector = Some(base_init_vector);
+    }
+
+    pub fn base_init_vector(&self) -> Option<Vec<u8>> {
+        self.base_init_vector.clone()
+    }
+
+    /// Whether `op` is allowed i.e. there are no key operations or it is one of them
+    pub fn allows(&self, op: KeyOperation) -> bool {
+        self.key_ops.is_empty() || self.key_ops.iter().any(|key_op| Label::from(*key_op) == op.into())
+    }
+
+    /// Fails if the key length or the key operations don't fit the algorithm, or there are
+    /// operations that a symmetric key can't be used for.
+    pub fn build(&self) -> Result<COSEKey, JsError> {
+        self.validate()?;
+        let mut key = COSEKey::new(&KeyType::Symmetric.into());
+        key.other_headers.insert(
+            SymmetricKeyParam::K.into(),
+            CBORValue::new_bytes(self.k.clone()));
+        if let Some(kid) = &self.key_id {
+            key.set_key_id(kid.clone());
+        }
+        if let Some(alg) = self.algorithm_id {
+            key.set_algorithm_id(&alg.into());
+        }
+        if !self.key_ops.is_empty() {
+            key.set_key_ops(&self.key_ops());
+        }
+        if let Some(base_init_vector) = &self.base_init_vector {
+            key.set_base_init_vector(base_init_vector.clone());
+        }
+        Ok(key)
+    }
+
+    /// Parses a Symmetric key, validating it as `build()` does
+    pub fn from_cose_key(key: &COSEKey) -> Result<SymmetricKey, JsError> {
+        use std::convert::TryFrom;
+        if !matches!(KeyType::try_from(key.key_type()), Ok(KeyType::Symmetric)) {
+            return Err(JsError::from_str(&format!("Expected Symmetric key type, found: {:?}", key.key_type())));
+        }
+        let k = key.header(&SymmetricKeyParam::K.into())
+            .ok_or_else(|| JsError::from_str("Missing k (symmetric key value)"))
+            .and_then(|k| value_to_bytes(&k))?;
+        let algorithm_id = match key.algorithm_id() {
+            Some(alg) => Some(AlgorithmId::try_from(alg.clone())
+                .map_err(|_| JsError::from_str(&format!("Unsupported symmetric key algorithm: {:?}", alg)))?),
+            None => None,
+        };
+        let key_ops = match key.key_ops() {
+            Some(ops) => ops.0
+                .into_iter()
+                .map(KeyOperation::try_from)
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        let parsed = Self {
+            k,
+            key_id: key.key_id(),
+            algorithm_id,
+            key_ops,
+            base_init_vector: key.base_init_vector(),
+        };
+        parsed.validate()?;
+        Ok(parsed)
+    }
+}
+
+impl SymmetricKey {
+    fn validate(&self) -> Result<(), JsError> {
+        if self.k.is_empty() {
+            return Err(JsError::from_str("Symmetric key must not be empty"));
+        }
+        let allowed_ops = match self.algorithm_id {
+            Some(alg) => {
+                let (key_len, ops) = symmetric_algorithm_use(alg)?;
+                if let Some(key_len) = key_len {
+                    if self.k.len() != key_len {
+                        return Err(JsError::from_str(&format!("{:?} key must be {} bytes, found {}", alg, key_len, self.k.len())));
+                    }
+                }
+                ops
+            },
+            None => &[
+                KeyOperation::Encrypt,
+                KeyOperation::Decrypt,
+                KeyOperation::MACCreate,
+                KeyOperation::MACVerify,
+                KeyOperation::WrapKey,
+                KeyOperation::UnwrapKey,
+            ],
+        };
+        for op in &self.key_ops {
+            if !allowed_ops.iter().any(|allowed| Label::from(*allowed) == Label::from(*op)) {
+                return Err(JsError::from_str(&format!("Key operation {:?} can't be used with a {} symmetric key", op, match self.algorithm_id {
+                    Some(alg) => format!("{:?}", alg),
+                    None => String::from("general"),
+                })));
+            }
+        }
+        Ok(())
+    }
+
+    /// The key value if the key can be used for `op` with `alg`
+    pub (crate) fn key_for(&self, alg: AlgorithmId, op: KeyOperation) -> Result<Vec<u8>, JsError> {
+        if let Some(key_alg) = self.algorithm_id {
+            if Label::from(key_alg) != alg.into() {
+                return Err(JsError::from_str(&format!("Key is restricted to algorithm {:?}, not {:?}", key_alg, alg)));
+            }
+        }
+        if !self.allows(op) {
+            return Err(JsError::from_str(&format!("Key operations {:?} do not allow {:?}", self.key_ops, op)));
+        }
+        Ok(self.k.clone())
+    }
+}
+
+// the curve parameters of an EC2 curve
+fn ec2_curve(crv: CurveType) -> Result<&'static crypto::WeierstrassCurve, JsError> {
+    match crv {
+        CurveType::P256 => Ok(&crypto::P256),
+        CurveType::P384 => Ok(&crypto::P384),
+        CurveType::P521 => Ok(&crypto::P521),
+        CurveType::Secp256k1 => Ok(&crypto::SECP256K1),
+        _ => Err(JsError::from_str(&format!("{:?} is not an EC2 curve", crv))),
+    }
+}
+
+#[derive(Clone, Debug)]
+enum EC2YCoordinate {
+    Value(Vec<u8>),
+    // only the least significant bit of y - RFC 8152 section 13.1.1
+    Sign(bool),
+}
+
+/// EC2 (x, y) public key on P-256, P-384, P-521 or secp256k1 with y either in full or compressed to its sign bit
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct EC2Key {
+    curve: CurveType,
+    x: Vec<u8>,
+    y: EC2YCoordinate,
+    prvkey_bytes: Option<Vec<u8>>,
+    key_id: Option<Vec<u8>>,
+    algorithm_id: Option<Label>,
+    key_ops: Vec<KeyOperation>,
+}
+
+#[wasm_bindgen]
+impl EC2Key {
+    pub fn new(curve: CurveType, x: Vec<u8>, y: Vec<u8>) -> Self {
+        Self {
+            curve,
+            x,
+            y: EC2YCoordinate::Value(y),
+            prvkey_bytes: None,
+            key_id: None,
+            algorithm_id: None,
+            key_ops: Vec::new(),
+        }
+    }
+
+    /// The compressed form where `y_sign` is the least significant bit of y
+    pub fn new_compressed(curve: CurveType, x: Vec<u8>, y_sign: bool) -> Self {
+        Self {
+            curve,
+            x,
+            y: EC2YCoordinate::Sign(y_sign),
+            prvkey_bytes: None,
+            key_id: None,
+            algorithm_id: None,
+            key_ops: Vec::new(),
+        }
+    }
+
+    pub fn set_private_key(&mut self, private_key_bytes: Vec<u8>) {
+        self.prvkey_bytes = Some(private_key_bytes);
+    }
+
+    pub fn curve(&self) -> CurveType {
+        self.curve
+    }
+
+    pub fn x(&self) -> Vec<u8> {
+        self.x.clone()
+    }
+
+    /// None if compressed. See `decompress()`
+    pub fn y(&self) -> Option<Vec<u8>> {
+        match &self.y {
+            EC2YCoordinate::Value(y) => Some(y.clone()),
+            EC2YCoordinate::Sign(_) => None,
+        }
+    }
+
+    pub fn y_sign(&self) -> bool {
+        match &self.y {
+            EC2YCoordinate::Value(y) => matches!(y.last(), Some(byte) if byte & 1 == 1),
+            EC2YCoordinate::Sign(sign) => *sign,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.y, EC2YCoordinate::Sign(_))
+    }
+
+    pub fn private_key(&self) -> Option<Vec<u8>> {
+        self.prvkey_bytes.clone()
+    }
+
+    pub fn set_key_id(&mut self, key_id: Vec<u8>) {
+        self.key_id = Some(key_id);
+    }
+
+    pub fn key_id(&self) -> Option<Vec<u8>> {
+        self.key_id.clone()
+    }
+
+    /// Restricts the key to `alg` e.g. ES256 (-7). A label since the ECDSA algorithms aren't in AlgorithmId
+    pub fn set_algorithm_id(&mut self, alg: &Label) {
+        self.algorithm_id = Some(alg.clone());
+    }
+
+    pub fn algorithm_id(&self) -> Option<Label> {
+        self.algorithm_id.clone()
+    }
+
+    /// Restricts the key to the operations added. EC2 keys can be used to Verify or, with the
+    /// private key, to Sign, DeriveKey or DeriveBits.
+    pub fn add_key_op(&mut self, op: KeyOperation) {
+        if !self.key_ops.iter().any(|key_op| Label::from(*key_op) == op.into()) {
+            self.key_ops.push(op);
+        }
+    }
+
+    pub fn key_ops(&self) -> Labels {
+        Labels(self.key_ops.iter().copied().map(Label::from).collect())
+    }
+
+    /// Whether `op` is allowed i.e. there are no key operations or it is one of them
+    pub fn allows(&self, op: KeyOperation) -> bool {
+        self.key_ops.is_empty() || self.key_ops.iter().any(|key_op| Label::from(*key_op) == op.into())
+    }
+
+    pub fn compress(&self) -> EC2Key {
+        Self {
+            y: EC2YCoordinate::Sign(self.y_sign()),
+            ..self.clone()
+        }
+    }
+
+    /// Computes y from x and the sign bit. Fails if there is no such point on the curve.
+    pub fn decompress(&self) -> Result<EC2Key, JsError> {
+        let y = match &self.y {
+            EC2YCoordinate::Value(y) => y.clone(),
+            EC2YCoordinate::Sign(sign) => ec2_curve(self.curve)?
+                .decompress_y(&self.x, *sign)
+                .ok_or_else(|| JsError::from_str(&format!("x is not the coordinate of a point on {:?}", self.curve)))?,
+        };
+        Ok(Self {
+            y: EC2YCoordinate::Value(y),
+            ..self.clone()
+        })
+    }
+
+    /// Fails if (x, y) isn't a point on the curve, a coordinate or d isn't the length of the curve's
+    /// field elements, or there are operations that this EC2 key can't be used for.
+    pub fn build(&self) -> Result<COSEKey, JsError> {
+        self.validate()?;
+        let mut key = COSEKey::new(&KeyType::EC2.into());
+        // crv
+        key.other_headers.insert(
+            ECKey::CRV.into(),
+            CBORValue::from_label(&Label::from(self.curve)));
+        // x
+        key.other_headers.insert(
+            ECKey::X.into(),
+            CBORValue::new_bytes(self.x.clone()));
+        // y
+        key.other_headers.insert(
+            ECKey::Y.into(),
+            match &self.y {
+                EC2YCoordinate::Value(y) => CBORValue::new_bytes(y.clone()),
+                EC2YCoordinate::Sign(sign) => CBORValue::new_special(&CBORSpecial::new_bool(*sign)),
+            });
+        // d (privkey)
+        if let Some(d) = &self.prvkey_bytes {
+            key.other_headers.insert(
+                ECKey::D.into(),
+                CBORValue::new_bytes(d.clone()));
+        }
+        if let Some(kid) = &self.key_id {
+            key.set_key_id(kid.clone());
+        }
+        if let Some(alg) = &self.algorithm_id {
+            key.set_algorithm_id(alg);
+        }
+        if !self.key_ops.is_empty() {
+            key.set_key_ops(&self.key_ops());
+        }
+        Ok(key)
+    }
+
+    /// Parses an EC2 key, validating it as `build()` does. Coordinates (and d) must be the length
+    /// of the curve's field elements including any leading zeros.
+    pub fn from_cose_key(key: &COSEKey) -> Result<EC2Key, JsError> {
+        use std::convert::TryFrom;
+        if !matches!(KeyType::try_from(key.key_type()), Ok(KeyType::EC2)) {
+            return Err(JsError::from_str(&format!("Expected EC2 key type, found: {:?}", key.key_type())));
+        }
+        let curve = key.header(&ECKey::CRV.into())
+            .ok_or_else(|| JsError::from_str("Missing crv"))
+            .and_then(|crv| value_to_label(&crv))
+            .and_then(CurveType::try_from)?;
+        let x = key.header(&ECKey::X.into())
+            .ok_or_else(|| JsError::from_str("Missing x"))
+            .and_then(|x| value_to_bytes(&x))?;
+        let y = key.header(&ECKey::Y.into())
+            .ok_or_else(|| JsError::from_str("Missing y"))?;
+        let y = match y.as_special().and_then(|special| special.as_bool()) {
+            Some(sign) => EC2YCoordinate::Sign(sign),
+            None => EC2YCoordinate::Value(value_to_bytes(&y)?),
+        };
+        let prvkey_bytes = match key.header(&ECKey::D.into()) {
+            Some(d) => Some(value_to_bytes(&d)?),
+            None => None,
+        };
+        let key_ops = match key.key_ops() {
+            Some(ops) => ops.0
+                .into_iter()
+                .map(KeyOperation::try_from)
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        let parsed = Self {
+            curve,
+            x,
+            y,
+            prvkey_bytes,
+            key_id: key.key_id(),
+            algorithm_id: key.algorithm_id(),
+            key_ops,
+        };
+        parsed.validate()?;
+        Ok(parsed)
+    }
+}
+
+impl EC2Key {
+    fn validate(&self) -> Result<(), JsError> {
+        let params = ec2_curve(self.curve)?;
+        let len = params.coordinate_len();
+        if self.x.len() != len {
+            return Err(JsError::from_str(&format!("{:?} x must be {} bytes, found {}", self.curve, len, self.x.len())));
+        }
+        if let EC2YCoordinate::Value(y) = &self.y {
+            if y.len() != len {
+                return Err(JsError::from_str(&format!("{:?} y must be {} bytes, found {}", self.curve, len, y.len())));
+            }
+        }
+        if let Some(d) = &self.prvkey_bytes {
+            if d.len() != len {
+                return Err(JsError::from_str(&format!("{:?} private key must be {} bytes, found {}", self.curve, len, d.len())));
+            }
+        }
+        let on_curve = match &self.y {
+            EC2YCoordinate::Value(y) => params.is_on_curve(&self.x, y),
+            EC2YCoordinate::Sign(sign) => params.decompress_y(&self.x, *sign).is_some(),
+        };
+        if !on_curve {
+            return Err(JsError::from_str(&format!("Not a point on {:?}", self.curve)));
+        }
+        for op in &self.key_ops {
+            let needs_private_key = match op {
+                KeyOperation::Verify => false,
+                KeyOperation::Sign | KeyOperation::DeriveKey | KeyOperation::DeriveBits => true,
+                _ => return Err(JsError::from_str(&format!("Key operation {:?} can't be used with an EC2 key", op))),
+            };
+            if needs_private_key && self.prvkey_bytes.is_none() {
+                return Err(JsError::from_str(&format!("Key operation {:?} requires the private key", op)));
+            }
+        }
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -261,4 +1137,291 @@ mod tests {
         assert_eq!(key.header(&Label::new_int(&Int::new_i32(-1))).unwrap().as_int().unwrap().as_i32().unwrap(), 6);
         assert_eq!(key.header(&Label::new_int(&Int::new_i32(-2))).unwrap().as_bytes().unwrap(), xpub);
     }
+
+    #[test]
+    fn eddsa25519key_from_cose_key() {
+        let mut builder = EdDSA25519Key::new(vec![5; 32]);
+        builder.is_for_verifying();
+        let key = COSEKey::from_bytes(builder.build().to_bytes()).unwrap();
+        let parsed = EdDSA25519Key::from_cose_key(&key).unwrap();
+        assert_eq!(parsed.public_key(), vec![5; 32]);
+        assert!(parsed.for_verifying && !parsed.for_signing);
+
+        let mut wrong_curve = key.clone();
+        wrong_curve.set_header(&ECKey::CRV.into(), &CBORValue::from_label(&CurveType::X25519.into())).unwrap();
+        assert!(EdDSA25519Key::from_cose_key(&wrong_curve).is_err());
+        let mut wrong_alg = key;
+        wrong_alg.set_algorithm_id(&AlgorithmId::ChaCha20Poly1305.into());
+        assert!(EdDSA25519Key::from_cose_key(&wrong_alg).is_err());
+    }
+
+    #[test]
+    fn symmetric_key() {
+        let mut builder = SymmetricKey::new(vec![9u8; 16]);
+        builder.set_key_id(b"kid".to_vec());
+        builder.set_algorithm_id(AlgorithmId::A128GCM);
+        builder.add_key_op(KeyOperation::Decrypt);
+        builder.add_key_op(KeyOperation::Decrypt);
+        let key = COSEKey::from_bytes(builder.build().unwrap().to_bytes()).unwrap();
+        assert_eq!(key.header(&Label::new_int(&Int::new_i32(-1))).unwrap().as_bytes().unwrap(), vec![9u8; 16]);
+        assert_eq!(key.key_ops().unwrap().len(), 1);
+        let parsed = SymmetricKey::from_cose_key(&key).unwrap();
+        assert_eq!(parsed.key(), vec![9u8; 16]);
+        assert_eq!(parsed.key_id(), Some(b"kid".to_vec()));
+        assert!(parsed.allows(KeyOperation::Decrypt) && !parsed.allows(KeyOperation::Encrypt));
+        assert!(parsed.key_for(AlgorithmId::A128GCM, KeyOperation::Decrypt).is_ok());
+        assert!(parsed.key_for(AlgorithmId::A128GCM, KeyOperation::Encrypt).is_err());
+        assert!(parsed.key_for(AlgorithmId::A256GCM, KeyOperation::Decrypt).is_err());
+        assert!(SymmetricKey::new(vec![9u8; 16]).allows(KeyOperation::MACCreate));
+
+        // base IV for partial IVs survives parsing and re-building
+        let mut channel_key = SymmetricKey::new(vec![9u8; 16]);
+        channel_key.set_algorithm_id(AlgorithmId::A128GCM);
+        channel_key.set_base_init_vector(vec![4u8; 12]);
+        let channel_key = channel_key.build().unwrap();
+        assert_eq!(channel_key.base_init_vector(), Some(vec![4u8; 12]));
+        let parsed = SymmetricKey::from_cose_key(&COSEKey::from_bytes(channel_key.to_bytes()).unwrap()).unwrap();
+        assert_eq!(parsed.base_init_vector(), Some(vec![4u8; 12]));
+        assert_eq!(parsed.build().unwrap().to_bytes(), channel_key.to_bytes());
+        let mut unprotected = HeaderMap::new();
+        unprotected.set_partial_init_vector(vec![1u8]);
+        let headers = Headers::new(&encryption::alg_headers(AlgorithmId::A128GCM).protected, &unprotected);
+        let encrypted = COSEEncrypt0::encrypt(&headers, b"on a channel".to_vec(), &channel_key, None).unwrap();
+        assert_eq!(encrypted.decrypt(&channel_key, None).unwrap(), b"on a channel".to_vec());
+
+        // key operations must fit the algorithm
+        let mut mac_key = SymmetricKey::new(vec![1u8; 20]);
+        mac_key.set_algorithm_id(AlgorithmId::HMAC256);
+        mac_key.add_key_op(KeyOperation::MACVerify);
+        assert!(mac_key.build().is_ok());
+        mac_key.add_key_op(KeyOperation::Encrypt);
+        assert!(mac_key.build().is_err());
+        let mut kw_key = SymmetricKey::new(vec![1u8; 32]);
+        kw_key.add_key_op(KeyOperation::WrapKey);
+        kw_key.set_algorithm_id(AlgorithmId::A256KW);
+        assert!(kw_key.build().is_ok());
+        kw_key.set_algorithm_id(AlgorithmId::A128KW);
+        assert!(kw_key.build().is_err());
+        kw_key.set_algorithm_id(AlgorithmId::EdDSA);
+        assert!(kw_key.build().is_err());
+        let mut signing_key = SymmetricKey::new(vec![1u8; 32]);
+        signing_key.add_key_op(KeyOperation::Sign);
+        assert!(signing_key.build().is_err());
+        assert!(SymmetricKey::new(vec![]).build().is_err());
+
+        // parsing validates the same way
+        let mut wrong_len = key.clone();
+        wrong_len.set_header(&SymmetricKeyParam::K.into(), &CBORValue::new_bytes(vec![9u8; 32])).unwrap();
+        assert!(SymmetricKey::from_cose_key(&wrong_len).is_err());
+        let mut wrong_op = key;
+        let mut ops = Labels::new();
+        ops.add(&KeyOperation::WrapKey.into());
+        wrong_op.set_key_ops(&ops);
+        assert!(SymmetricKey::from_cose_key(&wrong_op).is_err());
+        assert!(SymmetricKey::from_cose_key(&EdDSA25519Key::new(vec![5; 32]).build()).is_err());
+    }
+
+    #[test]
+    fn ec2_key() {
+        // the generator of each curve
+        let points = [
+            (CurveType::P256,
+                "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296",
+                "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5"),
+            (CurveType::P384,
+                "aa87ca22be8b05378eb1c71ef320ad746e1d3b628ba79b9859f741e082542a385502f25dbf55296c3a545e3872760ab7",
+                "3617de4a96262c6f5d9e98bf9292dc29f8f41dbd289a147ce9da3113b5f0b8c00a60b1ce1d7e819d7a431d7c90ea0e5f"),
+            (CurveType::P521,
+                "00c6858e06b70404e9cd9e3ecb662395b4429c648139053fb521f828af606b4d3dbaa14b5e77efe75928fe1dc127a2ffa8de3348b3c1856a429bf97e7e31c2e5bd66",
+                "011839296a789a3bc0045c8a5fb42c7d1bd998f54449579b446817afbd17273e662c97ee72995ef42640c550b9013fad0761353c7086a272c24088be94769fd16650"),
+            (CurveType::Secp256k1,
+                "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                "483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8"),
+        ];
+        for (curve, x, y) in points.iter() {
+            let (x, y) = (hex::decode(x).unwrap(), hex::decode(y).unwrap());
+            let key = COSEKey::from_bytes(EC2Key::new(*curve, x.clone(), y.clone()).build().unwrap().to_bytes()).unwrap();
+            let parsed = EC2Key::from_cose_key(&key).unwrap();
+            assert_eq!(parsed.y(), Some(y.clone()));
+            assert!(!parsed.is_compressed());
+
+            let compressed = COSEKey::from_bytes(parsed.compress().build().unwrap().to_bytes()).unwrap();
+            assert_eq!(compressed.header(&ECKey::Y.into()).unwrap().as_special().unwrap().as_bool(), Some(parsed.y_sign()));
+            let parsed = EC2Key::from_cose_key(&compressed).unwrap();
+            assert!(parsed.is_compressed() && parsed.y().is_none());
+            assert_eq!(parsed.decompress().unwrap().y(), Some(y.clone()));
+            // the other root
+            let negated = EC2Key::new_compressed(*curve, x.clone(), !parsed.y_sign()).decompress().unwrap();
+            assert_ne!(negated.y(), Some(y.clone()));
+            assert!(EC2Key::from_cose_key(&negated.build().unwrap()).is_ok());
+
+            let mut off_curve = y.clone();
+            *off_curve.last_mut().unwrap() ^= 1;
+            assert!(EC2Key::new(*curve, x.clone(), off_curve.clone()).build().is_err());
+            let mut off_curve_key = key.clone();
+            off_curve_key.set_header(&ECKey::Y.into(), &CBORValue::new_bytes(off_curve)).unwrap();
+            assert!(EC2Key::from_cose_key(&off_curve_key).is_err());
+            // leading zeros are part of the coordinates
+            let mut short_y = y.clone();
+            short_y.remove(0);
+            assert!(EC2Key::new(*curve, x.clone(), short_y.clone()).build().is_err());
+            let mut short_y_key = key.clone();
+            short_y_key.set_header(&ECKey::Y.into(), &CBORValue::new_bytes(short_y)).unwrap();
+            assert!(EC2Key::from_cose_key(&short_y_key).is_err());
+            let mut with_private_key = EC2Key::new(*curve, x.clone(), y.clone());
+            with_private_key.set_private_key(vec![1u8; x.len() - 1]);
+            assert!(with_private_key.build().is_err());
+            let mut short_d_key = key.clone();
+            short_d_key.set_header(&ECKey::D.into(), &CBORValue::new_bytes(vec![1u8; x.len() - 1])).unwrap();
+            assert!(EC2Key::from_cose_key(&short_d_key).is_err());
+        }
+        // P-256's x^3 - 3x + b is not a square for x = 1
+        let mut x = vec![0u8; 32];
+        x[31] = 1;
+        assert!(EC2Key::new_compressed(CurveType::P256, x.clone(), false).decompress().is_err());
+        assert!(EC2Key::new_compressed(CurveType::P256, x, true).build().is_err());
+        let (x, y) = (hex::decode(points[0].1).unwrap(), hex::decode(points[0].2).unwrap());
+        let mut wrong_curve = EC2Key::new(CurveType::P256, x.clone(), y.clone()).build().unwrap();
+        wrong_curve.set_header(&ECKey::CRV.into(), &CBORValue::from_label(&CurveType::Ed25519.into())).unwrap();
+        assert!(EC2Key::from_cose_key(&wrong_curve).is_err());
+        assert!(EC2Key::from_cose_key(&EdDSA25519Key::new(vec![5; 32]).build()).is_err());
+        assert!(EC2Key::new(CurveType::Ed25519, x.clone(), y.clone()).build().is_err());
+
+        // kid, alg and key_ops
+        let es256 = Label::new_int(&Int::new_i32(-7));
+        let mut builder = EC2Key::new(CurveType::P256, x.clone(), y.clone());
+        builder.set_key_id(vec![1, 2, 3]);
+        builder.set_algorithm_id(&es256);
+        builder.add_key_op(KeyOperation::Verify);
+        let parsed = EC2Key::from_cose_key(&COSEKey::from_bytes(builder.build().unwrap().to_bytes()).unwrap()).unwrap();
+        assert_eq!(parsed.key_id(), Some(vec![1, 2, 3]));
+        assert_eq!(parsed.algorithm_id(), Some(es256));
+        assert_eq!(parsed.key_ops().0, vec![Label::from(KeyOperation::Verify)]);
+        assert!(parsed.allows(KeyOperation::Verify) && !parsed.allows(KeyOperation::Sign));
+        // signing needs the private key and encryption isn't an EC2 key operation
+        builder.add_key_op(KeyOperation::Sign);
+        assert!(builder.build().is_err());
+        builder.set_private_key(vec![1u8; 32]);
+        assert!(builder.build().is_ok());
+        builder.add_key_op(KeyOperation::Encrypt);
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn cose_encrypt0_builder() {
+        let key = encryption::symmetric_cose_key(vec![7u8; 32]);
+        let plaintext = b"built in two steps".to_vec();
+        let mut builder = COSEEncrypt0Builder::new(&encryption::alg_headers(AlgorithmId::ChaCha20Poly1305), plaintext.clone(), false).unwrap();
+        builder.set_external_aad(b"context".to_vec());
+        let encrypted = builder.build(builder.encrypt(&key).unwrap());
+        let iv = encrypted.headers().unprotected().init_vector().unwrap();
+        assert_eq!(iv.len(), 12);
+        assert_eq!(encrypted.decrypt(&key, Some(b"context".to_vec())).unwrap(), plaintext);
+        assert!(encrypted.decrypt(&key, None).is_err());
+
+        // the same message with the AEAD done elsewhere over the exposed Enc_structure
+        let aad = builder.make_data_to_encrypt().to_bytes();
+        let ciphertext = crypto::chacha20poly1305_encrypt(&[7u8; 32], &iv, &aad, &plaintext);
+        assert_eq!(builder.build(ciphertext).to_bytes(), encrypted.to_bytes());
+
+        // detached
+        let builder = COSEEncrypt0Builder::new(&encryption::alg_headers(AlgorithmId::A128GCM), plaintext.clone(), true).unwrap();
+        let key = encryption::symmetric_cose_key(vec![8u8; 16]);
+        let ciphertext = builder.encrypt(&key).unwrap();
+        let encrypted = COSEEncrypt0::from_bytes(builder.build(ciphertext.clone()).to_bytes()).unwrap();
+        assert_eq!(encrypted.ciphertext(), None);
+        assert!(encrypted.decrypt(&key, None).is_err());
+        assert_eq!(encrypted.decrypt_detached(&key, ciphertext.clone(), None).unwrap(), plaintext);
+        let attached = COSEEncrypt0::new(&encrypted.headers(), Some(ciphertext.clone()));
+        assert!(attached.decrypt_detached(&key, ciphertext, None).is_err());
+    }
+
+    #[test]
+    fn cose_encrypt_builder() {
+        let content_key = vec![9u8; 32];
+        let plaintext = b"to two recipients".to_vec();
+        let (x25519, x25519_private) = encryption::x25519_keypair(3);
+        let mut kw = encryption::symmetric_cose_key(vec![1u8; 16]);
+        kw.set_algorithm_id(&AlgorithmId::A128KW.into());
+
+        let mut builder = COSEEncryptBuilder::new(&encryption::alg_headers(AlgorithmId::A256GCM), plaintext.clone(), true).unwrap();
+        let mut recipient_protected = HeaderMap::new();
+        recipient_protected.set_party_u_identity(b"sender".to_vec());
+        let recipient_headers = Headers::new(&ProtectedHeaderMap::new(&recipient_protected), &HeaderMap::new());
+        builder.add_recipient(&COSERecipient::for_key(&x25519, content_key.clone(), &recipient_headers).unwrap());
+        // key wrap recipients can't have protected headers
+        assert!(COSERecipient::for_key(&kw, content_key.clone(), &recipient_headers).is_err());
+        let mut recipient_unprotected = HeaderMap::new();
+        recipient_unprotected.set_key_id(b"ops".to_vec());
+        let recipient_headers = Headers::new(&ProtectedHeaderMap::new_empty(), &recipient_unprotected);
+        builder.add_recipient(&COSERecipient::for_key(&kw, content_key.clone(), &recipient_headers).unwrap());
+
+        let ciphertext = builder.encrypt(content_key).unwrap();
+        let encrypted = COSEEncrypt::from_bytes(builder.build(ciphertext.clone()).to_bytes()).unwrap();
+        assert_eq!(encrypted.ciphertext(), None);
+        let layer = encrypted.recipients().get(0);
+        let layer_protected = layer.headers().protected().deserialized_headers();
+        assert_eq!(layer_protected.party_u_identity(), Some(b"sender".to_vec()));
+        assert_eq!(layer_protected.algorithm_id(), Some(AlgorithmId::ECDHESA256KW.into()));
+        assert_eq!(encrypted.recipients().get(1).headers().unprotected().key_id(), Some(b"ops".to_vec()));
+        assert_eq!(encrypted.decrypt_detached(&x25519_private, ciphertext.clone(), None).unwrap(), plaintext);
+        assert_eq!(encrypted.decrypt_detached(&kw, ciphertext, None).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn cose_mac0_builder() {
+        let mut key = encryption::symmetric_cose_key(vec![3u8; 32]);
+        let mut builder = COSEMac0Builder::new(&encryption::alg_headers(AlgorithmId::HMAC256), b"{\"event\":\"paid\"}".to_vec(), false);
+        builder.set_external_aad(b"POST /hooks".to_vec());
+        let mac0 = COSEMac0::from_bytes(builder.build(builder.compute_tag(&key).unwrap()).to_bytes()).unwrap();
+        assert!(mac0.verify(&key, Some(b"POST /hooks".to_vec()), None).unwrap());
+        assert!(!mac0.verify(&key, Some(b"POST /other".to_vec()), None).unwrap());
+        // the tag is the HMAC of the MAC_structure
+        assert_eq!(mac0.tag(), crypto::hmac_sha256(&[3u8; 32], &builder.make_data_to_mac().to_bytes()));
+
+        // detached payload
+        let builder = COSEMac0Builder::new(&encryption::alg_headers(AlgorithmId::HMAC512), b"large body".to_vec(), true);
+        let mac0 = builder.build(builder.compute_tag(&key).unwrap());
+        assert_eq!(mac0.payload(), None);
+        assert!(mac0.verify(&key, None, None).is_err());
+        assert!(mac0.verify(&key, None, Some(b"large body".to_vec())).unwrap());
+        assert!(!mac0.verify(&key, None, Some(b"other body".to_vec())).unwrap());
+
+        // key_ops are enforced
+        let mut ops = Labels::new();
+        ops.add(&KeyOperation::MACVerify.into());
+        key.set_key_ops(&ops);
+        assert!(builder.compute_tag(&key).is_err());
+        assert!(mac0.verify(&key, None, Some(b"large body".to_vec())).unwrap());
+    }
+
+    #[test]
+    fn cose_mac_builder() {
+        let mut kw = encryption::symmetric_cose_key(vec![1u8; 32]);
+        kw.set_algorithm_id(&AlgorithmId::A256KW.into());
+        kw.set_key_id(b"billing".to_vec());
+        let (x25519, x25519_private) = encryption::x25519_keypair(4);
+        let mut builder = COSEMacBuilder::new(&encryption::alg_headers(AlgorithmId::HMAC384), b"invoice".to_vec(), false);
+        let mac_key = builder.generate_mac_key().unwrap();
+        assert_eq!(mac_key.len(), 48);
+        let no_headers = Headers::new(&ProtectedHeaderMap::new_empty(), &HeaderMap::new());
+        builder.add_recipient(&COSERecipient::for_key(&kw, mac_key.clone(), &no_headers).unwrap());
+        builder.add_recipient(&COSERecipient::for_key(&x25519, mac_key.clone(), &no_headers).unwrap());
+        let mac = COSEMac::from_bytes(builder.build(builder.compute_tag(mac_key).unwrap()).to_bytes()).unwrap();
+        assert_eq!(mac.recipients().len(), 2);
+        assert!(mac.verify(&kw, None, None).unwrap());
+        assert!(mac.verify(&x25519_private, None, None).unwrap());
+        let forged = COSEMac::new(&mac.headers(), Some(b"invoice 2".to_vec()), mac.tag(), &mac.recipients());
+        assert!(!forged.verify(&kw, None, None).unwrap());
+        let mut other = encryption::symmetric_cose_key(vec![2u8; 32]);
+        other.set_algorithm_id(&AlgorithmId::A256KW.into());
+        // a wrong key is not authentic, same as for COSE_Mac0
+        assert!(!mac.verify(&other, None, None).unwrap());
+        let (_, other_x25519_private) = encryption::x25519_keypair(5);
+        assert!(!mac.verify(&other_x25519_private, None, None).unwrap());
+        // malformed input is still an error
+        assert!(COSEMac::new(&mac.headers(), None, mac.tag(), &mac.recipients()).verify(&kw, None, None).is_err());
+        let wrong_alg = COSEMac::new(&encryption::alg_headers(AlgorithmId::A256GCM), Some(b"invoice".to_vec()), mac.tag(), &mac.recipients());
+        assert!(wrong_alg.verify(&kw, None, None).is_err());
+    }
 }
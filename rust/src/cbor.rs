@@ -109,6 +109,10 @@ impl CBORObject {
         self.values.get(key).map(|v| v.clone())
     }
 
+    pub fn remove(&mut self, key: &CBORValue) -> Option<CBORValue> {
+        self.values.remove(key)
+    }
+
     pub fn keys(&self) -> CBORArray {
         self.values.iter().map(|(k, _v)| k.clone()).collect::<Vec<CBORValue>>().into()
     }
@@ -416,8 +420,7 @@ use std::io::{Seek, SeekFrom};
 
 impl cbor_event::se::Serialize for TaggedCBOR {
     fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
-        serializer.write_array(cbor_event::Len::Len(2))?;
-        self.tag.serialize(serializer)?;
+        serializer.write_tag(from_bignum(&self.tag))?;
         self.value.serialize(serializer)?;
         Ok(serializer)
     }
@@ -426,22 +429,12 @@ impl cbor_event::se::Serialize for TaggedCBOR {
 impl Deserialize for TaggedCBOR {
     fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
         (|| -> Result<_, DeserializeError> {
-            let len = raw.array()?;
-            let mut read_len = CBORReadLen::new(len);
-            read_len.read_elems(2)?;
             let tag = (|| -> Result<_, DeserializeError> {
-                Ok(BigNum::deserialize(raw)?)
+                Ok(to_bignum(raw.tag()?))
             })().map_err(|e| e.annotate("tag"))?;
             let value = (|| -> Result<_, DeserializeError> {
                 Ok(CBORValue::deserialize(raw)?)
             })().map_err(|e| e.annotate("value"))?;
-            match len {
-                cbor_event::Len::Len(_) => (),
-                cbor_event::Len::Indefinite => match raw.special()? {
-                    cbor_event::Special::Break => (),
-                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
-                },
-            }
             Ok(TaggedCBOR {
                 tag,
                 value,
@@ -514,11 +507,11 @@ impl Deserialize for CBORObject {
             let len = raw.map()?;
             let definite = len != cbor_event::Len::Indefinite;
             while match len { cbor_event::Len::Len(n) => table.len() < n as usize, cbor_event::Len::Indefinite => true, } {
-                if raw.cbor_type()? == cbor_event::Type::Special {
-                    assert_eq!(raw.special()?, cbor_event::Special::Break);
+                let key = CBORValue::deserialize(raw)?;
+                // special values such as simple(59) are valid keys so only stop on a Break in indefinite maps
+                if !definite && key.0 == CBORValueEnum::Special(CBORSpecial(CBORSpecialEnum::Break)) {
                     break;
                 }
-                let key = CBORValue::deserialize(raw)?;
                 let value = CBORValue::deserialize(raw)?;
                 if table.insert(key.clone(), value).is_some() {
                     return Err(DeserializeFailure::DuplicateKey(Key::Str(String::from("some complicated/unsupported type"))).into());
@@ -731,8 +724,8 @@ mod tests {
         assert_eq!(spec_arr_indef, spec_arr_from_ce_indef);
     }
 
+    #[test]
     fn cbor_other_object() {
-        type CES = cbor_event::Special;
         type CEV = cbor_event::Value;
         type CEK = cbor_event::ObjectKey;
 
@@ -1,15 +1,14 @@
-//use curve25519_dalek::FieldElement;
-
-// pub fn get_ed25519_x(xpub: &ed25519_bip32::XPub) -> Option<()> {
-    
-//     // let compressed_y = CompressedEdwardsY::from_slice(&bytes[..]);
-//     // let edwards_point = compressed_y.decompress()?;
-//     // let proj_point = edwards_point.to_projective();
-//     //let Y = FieldElement::from_bytes(bytes);
-//     None
-// }
-
+use super::error::JsError;
+use crypto_bigint::{Encoding, U256, U384, U576};
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
 use cryptoxide::blake2b::Blake2b;
+use cryptoxide::chacha20poly1305::ChaCha20Poly1305;
+use cryptoxide::curve25519::{curve25519, curve25519_base, ge_scalarmult_base, Fe, GeP3};
+use cryptoxide::hkdf::{hkdf_expand, hkdf_extract};
+use cryptoxide::hmac::Hmac;
+use cryptoxide::mac::Mac;
+use cryptoxide::digest::Digest;
+use cryptoxide::sha2::{Sha256, Sha384, Sha512};
 use pruefung::fnv::fnv32::Fnv32a;
 
 pub (crate) fn blake2b224(data: &[u8]) -> [u8; 28] {
@@ -18,6 +17,357 @@ pub (crate) fn blake2b224(data: &[u8]) -> [u8; 28] {
     out
 }
 
+pub (crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut out = [0; 32];
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result(&mut out);
+    out
+}
+
+pub (crate) fn pbkdf2_sha512(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    let mut mac = Hmac::new(Sha512::new(), password);
+    cryptoxide::pbkdf2::pbkdf2(&mut mac, salt, iterations, &mut out);
+    out
+}
+
+pub (crate) fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let mut prk = [0u8; 32];
+    hkdf_extract(Sha256::new(), salt, ikm, &mut prk);
+    let mut okm = vec![0u8; len];
+    hkdf_expand(Sha256::new(), &prk, info, &mut okm);
+    okm
+}
+
+pub (crate) fn hkdf_sha512(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let mut prk = [0u8; 64];
+    hkdf_extract(Sha512::new(), salt, ikm, &mut prk);
+    let mut okm = vec![0u8; len];
+    hkdf_expand(Sha512::new(), &prk, info, &mut okm);
+    okm
+}
+
+pub (crate) fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac(Hmac::new(Sha256::new(), key), data)
+}
+
+pub (crate) fn hmac_sha384(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac(Hmac::new(Sha384::new(), key), data)
+}
+
+pub (crate) fn hmac_sha512(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac(Hmac::new(Sha512::new(), key), data)
+}
+
+fn hmac<D: Digest>(mut mac: Hmac<D>, data: &[u8]) -> Vec<u8> {
+    mac.input(data);
+    let mut out = vec![0u8; mac.output_bytes()];
+    mac.raw_result(&mut out);
+    out
+}
+
+// for comparing MAC tags without leaking how many leading bytes matched
+pub (crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    cryptoxide::util::fixed_time_eq(a, b)
+}
+
+pub (crate) fn x25519_public_key(private_key: &[u8]) -> [u8; 32] {
+    curve25519_base(private_key)
+}
+
+// None if the shared secret is all zeros i.e. `public_key` was a small order point
+pub (crate) fn x25519(private_key: &[u8], public_key: &[u8]) -> Option<[u8; 32]> {
+    let shared = curve25519(private_key, public_key);
+    match cryptoxide::util::fixed_time_eq(&shared, &[0u8; 32]) {
+        true => None,
+        false => Some(shared),
+    }
+}
+
+const FE_ZERO: Fe = Fe([0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+const FE_ONE: Fe = Fe([1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+const FE_121666: Fe = Fe([121666, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+// RFC 7748 section 5 scalar clamping
+pub (crate) fn x25519_clamp(private_key: &[u8]) -> [u8; 32] {
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&private_key[..32]);
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+    scalar
+}
+
+/// X25519 with an already-computed scalar i.e. without clamping it first.
+/// BIP32-Ed25519 derived scalars (kL) don't necessarily have bit 254 set so clamping
+/// them would give a point other than the one their Ed25519 public key corresponds to.
+/// None if the result is all zeros i.e. `public_key` was a small order point
+pub (crate) fn x25519_scalar_mult(scalar: &[u8; 32], public_key: &[u8]) -> Option<[u8; 32]> {
+    let x1 = Fe::from_bytes(public_key);
+    let mut x2 = FE_ONE;
+    let mut z2 = FE_ZERO;
+    let mut x3 = x1.clone();
+    let mut z3 = FE_ONE;
+    let mut swap = 0;
+    // Montgomery ladder as in RFC 7748 section 5 but over all 256 bits
+    for pos in (0usize..256).rev() {
+        let b = ((scalar[pos / 8] >> (pos & 7)) & 1) as i32;
+        swap ^= b;
+        x2.maybe_swap_with(&mut x3, swap);
+        z2.maybe_swap_with(&mut z3, swap);
+        swap = b;
+
+        let a = &x2 + &z2;
+        let aa = &a * &a;
+        let b = &x2 - &z2;
+        let bb = &b * &b;
+        let e = &aa - &bb;
+        let c = &x3 + &z3;
+        let d = &x3 - &z3;
+        let da = &d * &a;
+        let cb = &c * &b;
+        let sum = &da + &cb;
+        let difference = &da - &cb;
+        x3 = &sum * &sum;
+        z3 = &x1 * &(&difference * &difference);
+        x2 = &aa * &bb;
+        z2 = &e * &(&bb + &(&e * &FE_121666));
+    }
+    x2.maybe_swap_with(&mut x3, swap);
+    z2.maybe_swap_with(&mut z3, swap);
+    let shared = (&z2.invert() * &x2).to_bytes();
+    match cryptoxide::util::fixed_time_eq(&shared, &[0u8; 32]) {
+        true => None,
+        false => Some(shared),
+    }
+}
+
+/// Maps an Ed25519 public key to the X25519 public key of the same secret
+/// using the birational map u = (1 + y) / (1 - y) - RFC 7748 section 4.1.
+/// None if `public_key` isn't a point on the curve or is the identity
+pub (crate) fn ed25519_to_x25519_public_key(public_key: &[u8]) -> Option<[u8; 32]> {
+    if public_key.len() != 32 {
+        return None;
+    }
+    GeP3::from_bytes_negate_vartime(public_key)?;
+    let y = Fe::from_bytes(public_key);
+    let denominator = &FE_ONE - &y;
+    if denominator.to_bytes() == [0u8; 32] {
+        return None;
+    }
+    Some((&(&FE_ONE + &y) * &denominator.invert()).to_bytes())
+}
+
+/// The X25519 scalar of an Ed25519 private key, which is either a 32-byte RFC 8032 seed
+/// (hashed with SHA-512 and clamped) or a 64-byte BIP32-Ed25519 extended key (kL || kR)
+/// as held by Cardano wallets, where kL already is the scalar.
+pub (crate) fn ed25519_to_x25519_scalar(private_key: &[u8]) -> Option<[u8; 32]> {
+    match private_key.len() {
+        32 => {
+            let mut hash = [0u8; 64];
+            let mut hasher = Sha512::new();
+            hasher.input(private_key);
+            hasher.result(&mut hash);
+            Some(x25519_clamp(&hash[..32]))
+        },
+        64 => {
+            let mut scalar = [0u8; 32];
+            scalar.copy_from_slice(&private_key[..32]);
+            Some(scalar)
+        },
+        _ => None,
+    }
+}
+
+/// A short Weierstrass curve y^2 = x^3 + ax + b over the prime field p, a being either -3 or 0.
+pub (crate) struct WeierstrassCurve {
+    p: U576,
+    a_is_minus_3: bool,
+    b: U576,
+    // coordinates are as long as p
+    coordinate_len: usize,
+}
+
+pub (crate) const P256: WeierstrassCurve = WeierstrassCurve {
+    p: U256::from_be_hex("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff").resize(),
+    a_is_minus_3: true,
+    b: U256::from_be_hex("5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b").resize(),
+    coordinate_len: 32,
+};
+
+pub (crate) const P384: WeierstrassCurve = WeierstrassCurve {
+    p: U384::from_be_hex("fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffeffffffff0000000000000000ffffffff").resize(),
+    a_is_minus_3: true,
+    b: U384::from_be_hex("b3312fa7e23ee7e4988e056be3f82d19181d9c6efe8141120314088f5013875ac656398d8a2ed19d2a85c8edd3ec2aef").resize(),
+    coordinate_len: 48,
+};
+
+// the 66 byte field elements left-padded to the 72 bytes of a U576
+pub (crate) const P521: WeierstrassCurve = WeierstrassCurve {
+    p: U576::from_be_hex("00000000000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"),
+    a_is_minus_3: true,
+    b: U576::from_be_hex("0000000000000051953eb9618e1c9a1f929a21a0b68540eea2da725b99b315f3b8b489918ef109e156193951ec7e937b1652c0bd3bb1bf073573df883d2c34f1ef451fd46b503f00"),
+    coordinate_len: 66,
+};
+
+pub (crate) const SECP256K1: WeierstrassCurve = WeierstrassCurve {
+    p: U256::from_be_hex("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f").resize(),
+    a_is_minus_3: false,
+    b: U256::from_be_hex("0000000000000000000000000000000000000000000000000000000000000007").resize(),
+    coordinate_len: 32,
+};
+
+// big enough for all of the above (P-521 being the largest)
+fn to_uint(bytes: &[u8]) -> U576 {
+    let mut padded = [0u8; 72];
+    padded[72 - bytes.len()..].copy_from_slice(bytes);
+    U576::from_be_slice(&padded)
+}
+
+impl WeierstrassCurve {
+    /// Length in bytes of the x and y coordinates
+    pub (crate) fn coordinate_len(&self) -> usize {
+        self.coordinate_len
+    }
+
+    // x^3 + ax + b, None if x isn't a field element of the right length
+    fn rhs(&self, params: DynResidueParams<{ U576::LIMBS }>, x: &[u8]) -> Option<DynResidue<{ U576::LIMBS }>> {
+        if x.len() != self.coordinate_len || to_uint(x) >= self.p {
+            return None;
+        }
+        let x = DynResidue::new(&to_uint(x), params);
+        let b = DynResidue::new(&self.b, params);
+        let mut rhs = x.square().mul(&x).add(&b);
+        if self.a_is_minus_3 {
+            rhs = rhs.sub(&x.add(&x).add(&x));
+        }
+        Some(rhs)
+    }
+
+    /// Whether (x, y) is a point on the curve with both coordinates being field elements of `coordinate_len()` bytes
+    pub (crate) fn is_on_curve(&self, x: &[u8], y: &[u8]) -> bool {
+        let params = DynResidueParams::new(&self.p);
+        match self.rhs(params, x) {
+            Some(rhs) => {
+                y.len() == self.coordinate_len
+                    && to_uint(y) < self.p
+                    && DynResidue::new(&to_uint(y), params).square().retrieve() == rhs.retrieve()
+            },
+            None => false,
+        }
+    }
+
+    /// The y coordinate for `x` whose least significant bit is `sign` (RFC 8152 section 13.1.1 point compression).
+    /// None if there is no such point. All of the curves have p = 3 mod 4 so the square root is rhs^((p + 1) / 4).
+    pub (crate) fn decompress_y(&self, x: &[u8], sign: bool) -> Option<Vec<u8>> {
+        let params = DynResidueParams::new(&self.p);
+        let rhs = self.rhs(params, x)?;
+        let root = rhs.pow(&self.p.wrapping_add(&U576::ONE).shr_vartime(2));
+        if root.square().retrieve() != rhs.retrieve() {
+            return None;
+        }
+        let mut y = root.retrieve();
+        if (y.to_be_bytes()[71] & 1 == 1) != sign {
+            if y == U576::ZERO {
+                return None;
+            }
+            y = root.neg().retrieve();
+        }
+        Some(y.to_be_bytes()[72 - self.coordinate_len..].to_vec())
+    }
+}
+
+// RFC 3394 AES key wrap. The KEK length picks AES-128/192/256.
+pub (crate) fn aes_key_wrap(kek: &[u8], key: &[u8]) -> Result<Vec<u8>, JsError> {
+    use aes_kw::{KekAes128, KekAes192, KekAes256};
+    let mut out = vec![0u8; key.len() + 8];
+    let result = match kek.len() {
+        16 => KekAes128::new(kek.into()).wrap(key, &mut out),
+        24 => KekAes192::new(kek.into()).wrap(key, &mut out),
+        32 => KekAes256::new(kek.into()).wrap(key, &mut out),
+        other => return Err(JsError::from_str(&format!("Invalid AES key wrap KEK length: {}", other))),
+    };
+    result.map_err(|e| JsError::from_str(&format!("AES key wrap failed: {}", e)))?;
+    Ok(out)
+}
+
+// None if the integrity check failed
+pub (crate) fn aes_key_unwrap(kek: &[u8], wrapped: &[u8]) -> Option<Vec<u8>> {
+    use aes_kw::{KekAes128, KekAes192, KekAes256};
+    let mut out = vec![0u8; wrapped.len().checked_sub(8)?];
+    let result = match kek.len() {
+        16 => KekAes128::new(kek.into()).unwrap(wrapped, &mut out),
+        24 => KekAes192::new(kek.into()).unwrap(wrapped, &mut out),
+        32 => KekAes256::new(kek.into()).unwrap(wrapped, &mut out),
+        _ => return None,
+    };
+    result.ok().map(|_| out)
+}
+
+type Aes192Gcm = aes_gcm::AesGcm<aes_gcm::aes::Aes192, aes_gcm::aead::consts::U12>;
+
+// returns the ciphertext with the 16-byte tag appended as COSE expects. The key length picks AES-128/192/256.
+pub (crate) fn aes_gcm_encrypt(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, JsError> {
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    let nonce = aes_gcm::Nonce::from_slice(nonce);
+    let payload = Payload { msg: plaintext, aad };
+    let result = match key.len() {
+        16 => aes_gcm::Aes128Gcm::new(key.into()).encrypt(nonce, payload),
+        24 => Aes192Gcm::new(key.into()).encrypt(nonce, payload),
+        32 => aes_gcm::Aes256Gcm::new(key.into()).encrypt(nonce, payload),
+        other => return Err(JsError::from_str(&format!("Invalid AES-GCM key length: {}", other))),
+    };
+    result.map_err(|_| JsError::from_str("AES-GCM encryption failed"))
+}
+
+// None if the tag didn't match
+pub (crate) fn aes_gcm_decrypt(key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    let nonce = aes_gcm::Nonce::from_slice(nonce);
+    let payload = Payload { msg: ciphertext, aad };
+    let result = match key.len() {
+        16 => aes_gcm::Aes128Gcm::new(key.into()).decrypt(nonce, payload),
+        24 => Aes192Gcm::new(key.into()).decrypt(nonce, payload),
+        32 => aes_gcm::Aes256Gcm::new(key.into()).decrypt(nonce, payload),
+        _ => return None,
+    };
+    result.ok()
+}
+
+pub (crate) fn random_bytes(len: usize) -> Result<Vec<u8>, JsError> {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| JsError::from_str(&format!("Could not generate random bytes: {}", e)))?;
+    Ok(bytes)
+}
+
+// returns the ciphertext with the 16-byte tag appended as COSE expects
+pub (crate) fn chacha20poly1305_encrypt(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; plaintext.len() + 16];
+    let (ciphertext, tag) = out.split_at_mut(plaintext.len());
+    ChaCha20Poly1305::new(key, nonce, aad).encrypt(plaintext, ciphertext, tag);
+    out
+}
+
+pub (crate) fn chacha20poly1305_decrypt(key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    if ciphertext.len() < 16 {
+        return None;
+    }
+    let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - 16);
+    let mut plaintext = vec![0u8; ciphertext.len()];
+    match ChaCha20Poly1305::new(key, nonce, aad).decrypt(ciphertext, &mut plaintext, tag) {
+        true => Some(plaintext),
+        false => None,
+    }
+}
+
+pub (crate) fn ed25519_verify(message: &[u8], public_key: &[u8], signature: &[u8]) -> bool {
+    public_key.len() == 32
+        && signature.len() == 64
+        && cryptoxide::ed25519::verify(message, public_key, signature)
+}
+
 pub (crate) fn fnv32a(data: &[u8]) -> u32 {
     use core::hash::Hasher;
     let mut hasher = Fnv32a::default();
@@ -25,22 +375,107 @@ pub (crate) fn fnv32a(data: &[u8]) -> u32 {
     hasher.finish() as u32
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn x_coord() {
-//         let pub_bytes = [
-//             0xd7, 0x5a, 0x98, 0x01, 0x82, 0xb1, 0x0a, 0xb7, 0xd5, 0x4b, 0xfe, 0xd3, 0xc9, 0x64, 0x07, 0x3a,
-//             0x0e, 0xe1, 0x72, 0xf3, 0xda, 0xa6, 0x23, 0x25, 0xaf, 0x02, 0x1a, 0x68, 0xf7, 0x07, 0x51, 0x1a
-//         ];
-//         let compr_y = curve25519_dalek::edwards::CompressedEdwardsY::from_slice(&pub_bytes[..]);
-//         let x_computed = compr_y.compute_x_as_bytes().unwrap();
-//         let x_expected = base64_url::decode("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo").unwrap();
-//         println!("pub_bytes = {:?}", pub_bytes);
-//         println!("x_expected = {:?}", x_expected);
-//         println!("x_computed = {:?}", x_computed);
-//         assert_eq!(pub_bytes, x_expected.as_ref());
-//     }
-// }
\ No newline at end of file
+// 8 * y + x where y is the 28-byte truncated left half of the derivation hash (BIP32-Ed25519 V2)
+fn add_28_mul8(x: &[u8], y: &[u8]) -> [u8; 32] {
+    let mut carry: u16 = 0;
+    let mut out = [0u8; 32];
+    for i in 0..28 {
+        let r = x[i] as u16 + ((y[i] as u16) << 3) + carry;
+        out[i] = (r & 0xff) as u8;
+        carry = r >> 8;
+    }
+    for i in 28..32 {
+        let r = x[i] as u16 + carry;
+        out[i] = (r & 0xff) as u8;
+        carry = r >> 8;
+    }
+    out
+}
+
+fn point_plus(p1: &[u8], p2: &[u8]) -> Option<[u8; 32]> {
+    // both points come out negated, so we flip the sign of the (negated) sum back at the end
+    let a = GeP3::from_bytes_negate_vartime(p1)?;
+    let b = GeP3::from_bytes_negate_vartime(p2)?;
+    let mut r = (a + b.to_cached()).to_p2().to_bytes();
+    r[31] ^= 0x80;
+    Some(r)
+}
+
+/// Soft (non-hardened) BIP32-Ed25519 public key derivation as used by Cardano (V2 scheme).
+/// `xpub` is the 32-byte public key followed by the 32-byte chain code.
+/// Returns None for hardened indices or if `xpub` is not a valid extended public key.
+pub (crate) fn derive_xpub_soft(xpub: &[u8], index: u32) -> Option<[u8; 64]> {
+    if xpub.len() != 64 || index >= 0x80000000 {
+        return None;
+    }
+    let (pk, cc) = xpub.split_at(32);
+    let index_bytes = index.to_le_bytes();
+
+    let mut z = [0u8; 64];
+    let mut zmac = Hmac::new(Sha512::new(), cc);
+    zmac.input(&[0x02]);
+    zmac.input(pk);
+    zmac.input(&index_bytes);
+    zmac.raw_result(&mut z);
+
+    let mut i = [0u8; 64];
+    let mut imac = Hmac::new(Sha512::new(), cc);
+    imac.input(&[0x03]);
+    imac.input(pk);
+    imac.input(&index_bytes);
+    imac.raw_result(&mut i);
+
+    let zl8 = add_28_mul8(&[0u8; 32], &z[0..28]);
+    let child_pk = point_plus(pk, &ge_scalarmult_base(&zl8).to_bytes())?;
+
+    let mut out = [0u8; 64];
+    out[..32].copy_from_slice(&child_pk);
+    out[32..].copy_from_slice(&i[32..]);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x25519_scalar_mult_matches_clamped() {
+        let private_key = [7u8; 32];
+        let mut base = [0u8; 32];
+        base[0] = 9;
+        assert_eq!(
+            x25519_scalar_mult(&x25519_clamp(&private_key), &base).unwrap(),
+            x25519_public_key(&private_key));
+    }
+
+    #[test]
+    fn ed25519_to_x25519() {
+        // RFC 8032 section 7.1 test 1
+        let seed = hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+        let public_key = hex::decode("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a").unwrap();
+        let scalar = ed25519_to_x25519_scalar(&seed).unwrap();
+        assert_eq!(
+            ed25519_to_x25519_public_key(&public_key).unwrap(),
+            x25519_public_key(&scalar));
+
+        // a BIP32-Ed25519 style scalar with bit 254 unset which clamping would change
+        let mut extended = [3u8; 64];
+        extended[0] = 8;
+        extended[31] = 0x20;
+        let scalar = ed25519_to_x25519_scalar(&extended).unwrap();
+        let public_key = ge_scalarmult_base(&scalar).to_bytes();
+        let mut base = [0u8; 32];
+        base[0] = 9;
+        assert_eq!(
+            ed25519_to_x25519_public_key(&public_key).unwrap(),
+            x25519_scalar_mult(&scalar, &base).unwrap());
+        assert_ne!(
+            ed25519_to_x25519_public_key(&public_key).unwrap(),
+            x25519_public_key(&scalar));
+
+        // identity
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        assert!(ed25519_to_x25519_public_key(&identity).is_none());
+    }
+}
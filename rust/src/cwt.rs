@@ -0,0 +1,352 @@
+// CBOR Web Tokens - RFC 8392
+
+use super::*;
+use std::io::Seek;
+
+// claim keys - RFC 8392 section 4
+const ISS: u64 = 1;
+const SUB: u64 = 2;
+const AUD: u64 = 3;
+const EXP: u64 = 4;
+const NBF: u64 = 5;
+const IAT: u64 = 6;
+const CTI: u64 = 7;
+
+// CWT CBOR tag - RFC 8392 section 6
+const CWT_TAG: u64 = 61;
+const COSE_SIGN1_TAG: u64 = 18;
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CWTClaims {
+    issuer: Option<String>,
+    subject: Option<String>,
+    audience: Option<String>,
+    expiration: Option<BigNum>,
+    not_before: Option<BigNum>,
+    issued_at: Option<BigNum>,
+    cwt_id: Option<Vec<u8>>,
+    custom_claims: CBORObject,
+}
+
+to_from_bytes!(CWTClaims);
+
+#[wasm_bindgen]
+impl CWTClaims {
+    pub fn new() -> Self {
+        Self {
+            issuer: None,
+            subject: None,
+            audience: None,
+            expiration: None,
+            not_before: None,
+            issued_at: None,
+            cwt_id: None,
+            custom_claims: CBORObject::new(),
+        }
+    }
+
+    pub fn set_issuer(&mut self, issuer: String) {
+        self.issuer = Some(issuer);
+    }
+
+    pub fn issuer(&self) -> Option<String> {
+        self.issuer.clone()
+    }
+
+    pub fn set_subject(&mut self, subject: String) {
+        self.subject = Some(subject);
+    }
+
+    pub fn subject(&self) -> Option<String> {
+        self.subject.clone()
+    }
+
+    pub fn set_audience(&mut self, audience: String) {
+        self.audience = Some(audience);
+    }
+
+    pub fn audience(&self) -> Option<String> {
+        self.audience.clone()
+    }
+
+    /// Seconds since the unix epoch
+    pub fn set_expiration(&mut self, expiration: &BigNum) {
+        self.expiration = Some(*expiration);
+    }
+
+    pub fn expiration(&self) -> Option<BigNum> {
+        self.expiration
+    }
+
+    /// Seconds since the unix epoch
+    pub fn set_not_before(&mut self, not_before: &BigNum) {
+        self.not_before = Some(*not_before);
+    }
+
+    pub fn not_before(&self) -> Option<BigNum> {
+        self.not_before
+    }
+
+    /// Seconds since the unix epoch
+    pub fn set_issued_at(&mut self, issued_at: &BigNum) {
+        self.issued_at = Some(*issued_at);
+    }
+
+    pub fn issued_at(&self) -> Option<BigNum> {
+        self.issued_at
+    }
+
+    pub fn set_cwt_id(&mut self, cwt_id: Vec<u8>) {
+        self.cwt_id = Some(cwt_id);
+    }
+
+    pub fn cwt_id(&self) -> Option<Vec<u8>> {
+        self.cwt_id.clone()
+    }
+
+    /// Any claims besides the registered ones above. Keys 1 to 7 are reserved for those.
+    pub fn set_custom_claims(&mut self, custom_claims: &CBORObject) -> Result<(), JsError> {
+        if let Some(key) = custom_claims.keys().values.iter().find(|key| registered_claim(key).is_some()) {
+            return Err(JsError::from_str(&format!("Custom claim key {:?} is a registered claim", key)));
+        }
+        self.custom_claims = custom_claims.clone();
+        Ok(())
+    }
+
+    pub fn custom_claims(&self) -> CBORObject {
+        self.custom_claims.clone()
+    }
+}
+
+impl Default for CWTClaims {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CWTClaims {
+    // the expiration is mandatory for our tokens
+    fn check_times(&self, now: u64, clock_skew: u64) -> Result<(), ValidityError> {
+        check_times(
+            self.issued_at.as_ref().map(from_bignum),
+            self.not_before.as_ref().map(from_bignum),
+            self.expiration.as_ref().map(from_bignum),
+            now,
+            clock_skew)
+    }
+
+    // the claims set as a CBOR map, in the same order as it is serialized
+    pub (crate) fn to_object(&self) -> CBORObject {
+        let mut object = CBORObject::new();
+        let key = |key: u64| CBORValue::new_int(&Int::new(&to_bignum(key)));
+        for (claim, text) in [(ISS, &self.issuer), (SUB, &self.subject), (AUD, &self.audience)].iter() {
+            if let Some(text) = text {
+                object.insert(&key(*claim), &CBORValue::new_text(text.clone()));
+            }
+        }
+        for (claim, time) in [(EXP, &self.expiration), (NBF, &self.not_before), (IAT, &self.issued_at)].iter() {
+            if let Some(time) = time {
+                object.insert(&key(*claim), &CBORValue::new_int(&Int::new(time)));
+            }
+        }
+        if let Some(cwt_id) = &self.cwt_id {
+            object.insert(&key(CTI), &CBORValue::new_bytes(cwt_id.clone()));
+        }
+        for claim in self.custom_claims.keys().values.iter() {
+            object.insert(claim, &self.custom_claims.get(claim).unwrap());
+        }
+        object
+    }
+}
+
+fn registered_claim(key: &CBORValue) -> Option<u64> {
+    key.as_int()
+        .and_then(|key| key.as_positive())
+        .map(|key| from_bignum(&key))
+        .filter(|key| (ISS..=CTI).contains(key))
+}
+
+/// A CWT signed with a single key: COSE_Sign1 with the claims set as payload
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct CWT {
+    cose_sign1: COSESign1,
+}
+
+to_from_bytes!(CWT);
+
+#[wasm_bindgen]
+impl CWT {
+    pub fn new(cose_sign1: &COSESign1) -> Self {
+        Self {
+            cose_sign1: cose_sign1.clone(),
+        }
+    }
+
+    pub fn cose_sign1(&self) -> COSESign1 {
+        self.cose_sign1.clone()
+    }
+
+    pub fn claims(&self) -> Result<CWTClaims, JsError> {
+        let hashed = self.cose_sign1.headers.unprotected
+            .header(&Label::new_text(String::from("hashed")))
+            .and_then(|hashed| hashed.as_special())
+            .and_then(|hashed| hashed.as_bool())
+            .unwrap_or(false);
+        if hashed {
+            return Err(JsError::from_str("CWT payload must not be hashed"));
+        }
+        let payload = self.cose_sign1.payload
+            .as_ref()
+            .ok_or_else(|| JsError::from_str("CWT payload must not be detached"))?;
+        Ok(CWTClaims::from_bytes(payload.clone())?)
+    }
+
+    /// Checks the signature against `key`, that the audience claim is `audience`
+    /// and that the token is valid at `now` (seconds since the unix epoch) give or take `clock_skew` seconds.
+    /// Tokens without an expiration are rejected.
+    /// Returns the claims set if all checks pass.
+    pub fn validate(&self, key: &COSEKey, audience: &str, now: &BigNum, clock_skew: &BigNum) -> Result<CWTClaims, JsError> {
+        if !self.cose_sign1.verify(key, None, None)? {
+            return Err(JsError::from_str("Invalid CWT signature"));
+        }
+        let claims = self.claims()?;
+        match &claims.audience {
+            Some(aud) if aud == audience => (),
+            Some(aud) => return Err(JsError::from_str(&format!("CWT audience mismatch: expected \"{}\", found \"{}\"", audience, aud))),
+            None => return Err(JsError::from_str("CWT has no audience")),
+        }
+        claims.check_times(from_bignum(now), from_bignum(clock_skew))?;
+        Ok(claims)
+    }
+}
+
+// serialization
+
+impl cbor_event::se::Serialize for CWTClaims {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        self.to_object().serialize(serializer)
+    }
+}
+
+impl Deserialize for CWTClaims {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            let object = CBORObject::deserialize(raw)?;
+            let mut claims = CWTClaims::new();
+            for key in object.keys().values.iter() {
+                let value = object.get(key).unwrap();
+                let claim = match registered_claim(key) {
+                    Some(claim) => claim,
+                    None => {
+                        claims.custom_claims.insert(key, &value);
+                        continue;
+                    },
+                };
+                let invalid = || DeserializeError::from(cbor_event::Error::CustomError(format!("invalid value {:?}", value))).annotate(format!("{}", claim));
+                match claim {
+                    ISS => claims.issuer = Some(value.as_text().ok_or_else(invalid)?),
+                    SUB => claims.subject = Some(value.as_text().ok_or_else(invalid)?),
+                    AUD => claims.audience = Some(value.as_text().ok_or_else(invalid)?),
+                    CTI => claims.cwt_id = Some(value.as_bytes().ok_or_else(invalid)?),
+                    _ => {
+                        let time = value.as_int().and_then(|time| time.as_positive()).ok_or_else(invalid)?;
+                        match claim {
+                            EXP => claims.expiration = Some(time),
+                            NBF => claims.not_before = Some(time),
+                            _ => claims.issued_at = Some(time),
+                        }
+                    },
+                }
+            }
+            Ok(claims)
+        })().map_err(|e| e.annotate("CWTClaims"))
+    }
+}
+
+impl cbor_event::se::Serialize for CWT {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_tag(CWT_TAG)?;
+        serializer.write_tag(COSE_SIGN1_TAG)?;
+        self.cose_sign1.serialize(serializer)
+    }
+}
+
+impl Deserialize for CWT {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            // the outer CWT tag is optional but the COSE message itself must be tagged
+            let mut tag = raw.tag()?;
+            if tag == CWT_TAG {
+                tag = raw.tag()?;
+            }
+            if tag != COSE_SIGN1_TAG {
+                return Err(DeserializeFailure::TagMismatch{ found: tag, expected: COSE_SIGN1_TAG }.into());
+            }
+            let cose_sign1 = COSESign1::deserialize(raw)?;
+            Ok(CWT {
+                cose_sign1,
+            })
+        })().map_err(|e| e.annotate("CWT"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> CWTClaims {
+        let mut claims = CWTClaims::new();
+        claims.set_issuer(String::from("stake_test1uqehkck0lajq8gr28t9uxnuvgcqrc6070x3k9r8048z8y5gssrtvn"));
+        claims.set_audience(String::from("https://api.example.com"));
+        claims.set_issued_at(&to_bignum(1000));
+        claims.set_expiration(&to_bignum(1600));
+        claims.set_cwt_id(vec![1, 2, 3, 4]);
+        let mut custom = CBORObject::new();
+        custom.insert(&CBORValue::new_text(String::from("scope")), &CBORValue::new_text(String::from("read")));
+        claims.set_custom_claims(&custom).unwrap();
+        claims
+    }
+
+    #[test]
+    fn claims_round_trip() {
+        let claims = claims();
+        assert_eq!(CWTClaims::from_bytes(claims.to_bytes()).unwrap(), claims);
+        // RFC 8392 A.1 example claims set (iss, sub, aud, exp, nbf, iat, cti)
+        let rfc = hex::decode("a70175636f61703a2f2f61732e6578616d706c652e636f6d02656572696b77037818636f61703a2f2f6c696768742e6578616d706c652e636f6d041a5612aeb0051a5610d9f0061a5610d9f007420b71").unwrap();
+        let parsed = CWTClaims::from_bytes(rfc.clone()).unwrap();
+        assert_eq!(parsed.issuer(), Some(String::from("coap://as.example.com")));
+        assert_eq!(parsed.expiration(), Some(to_bignum(1444064944)));
+        assert_eq!(parsed.cwt_id(), Some(vec![0x0b, 0x71]));
+        assert_eq!(parsed.to_bytes(), rfc);
+
+        let mut reserved = CBORObject::new();
+        reserved.insert(&CBORValue::new_int(&Int::new_i32(4)), &CBORValue::new_int(&Int::new_i32(0)));
+        assert!(CWTClaims::new().set_custom_claims(&reserved).is_err());
+    }
+
+    #[test]
+    fn sign_and_validate() {
+        let (sk, pk) = cryptoxide::ed25519::keypair(&[42u8; 32]);
+        let key = EdDSA25519Key::new(pk.to_vec()).build();
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::EdDSA.into());
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        let builder = CWTBuilder::new(&headers, &claims());
+        let signature = cryptoxide::ed25519::signature(&builder.make_data_to_sign().to_bytes(), &sk);
+        let bytes = builder.build(signature.to_vec()).to_bytes();
+        assert_eq!(&bytes[..3], &[0xd8, 0x3d, 0xd2]);
+
+        let cwt = CWT::from_bytes(bytes).unwrap();
+        let audience = "https://api.example.com";
+        let validated = cwt.validate(&key, audience, &to_bignum(1200), &to_bignum(0)).unwrap();
+        assert_eq!(validated, claims());
+
+        assert!(cwt.validate(&key, "https://other.example.com", &to_bignum(1200), &to_bignum(0)).is_err());
+        assert!(cwt.validate(&key, audience, &to_bignum(1600), &to_bignum(0)).is_err());
+        assert!(cwt.validate(&key, audience, &to_bignum(900), &to_bignum(0)).is_err());
+        let other_key = EdDSA25519Key::new(cryptoxide::ed25519::keypair(&[1u8; 32]).1.to_vec()).build();
+        assert!(cwt.validate(&other_key, audience, &to_bignum(1200), &to_bignum(0)).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use super::*;
+use std::io::Seek;
+
+// the bit that marks an index as hardened in BIP32 paths
+const HARDENED: u32 = 0x80000000;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DerivationPath {
+    purpose: u32,
+    coin_type: u32,
+    account: u32,
+    role: u32,
+    index: u32,
+}
+
+to_from_bytes!(DerivationPath);
+
+#[wasm_bindgen]
+impl DerivationPath {
+    /// Raw path indices - hardened indices must already have the 0x80000000 bit set
+    pub fn new(purpose: u32, coin_type: u32, account: u32, role: u32, index: u32) -> Self {
+        Self {
+            purpose,
+            coin_type,
+            account,
+            role,
+            index,
+        }
+    }
+
+    /// m / 1852' / 1815' / account' / role / index
+    /// # Arguments
+    /// * `account` - account number without the hardened bit. It is hardened here as CIP-1852 requires.
+    /// * `role` - 0 = external chain, 1 = internal chain (change), 2 = staking key
+    pub fn new_cip1852(account: u32, role: u32, index: u32) -> Self {
+        Self::new(1852 | HARDENED, 1815 | HARDENED, account | HARDENED, role, index)
+    }
+
+    pub fn purpose(&self) -> u32 {
+        self.purpose
+    }
+
+    pub fn coin_type(&self) -> u32 {
+        self.coin_type
+    }
+
+    pub fn account(&self) -> u32 {
+        self.account
+    }
+
+    pub fn role(&self) -> u32 {
+        self.role
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Checks that `public_key` is the key found at this path's role/index under `account_xpub`.
+    /// The hardened purpose/coin_type/account levels can't be derived from the public side
+    /// so it is up to the caller to make sure `account_xpub` belongs to the account in this path.
+    /// # Arguments
+    /// * `account_xpub` - 64 bytes: the account public key followed by its chain code
+    /// * `public_key` - 32 byte Ed25519 public key that signed the message
+    pub fn verify_public_key(&self, account_xpub: Vec<u8>, public_key: Vec<u8>) -> Result<bool, JsError> {
+        if account_xpub.len() != 64 {
+            return Err(JsError::from_str(&format!("Account xpub must be 64 bytes, found {}", account_xpub.len())));
+        }
+        if public_key.len() != 32 {
+            return Err(JsError::from_str(&format!("Public key must be 32 bytes, found {}", public_key.len())));
+        }
+        if self.role >= HARDENED || self.index >= HARDENED {
+            return Err(JsError::from_str("Role and index must be soft (non-hardened) to derive from an account xpub"));
+        }
+        let role_xpub = crypto::derive_xpub_soft(&account_xpub, self.role)
+            .ok_or_else(|| JsError::from_str("Invalid account xpub"))?;
+        let key_xpub = crypto::derive_xpub_soft(&role_xpub, self.index)
+            .ok_or_else(|| JsError::from_str("Invalid account xpub"))?;
+        Ok(key_xpub[..32] == public_key[..])
+    }
+}
+
+impl DerivationPath {
+    fn indices(&self) -> [u32; 5] {
+        [self.purpose, self.coin_type, self.account, self.role, self.index]
+    }
+
+    pub (crate) fn to_value(self) -> CBORValue {
+        let values = self.indices()
+            .iter()
+            .map(|i| CBORValue::new_int(&Int::new(&to_bignum(*i as u64))))
+            .collect::<Vec<CBORValue>>();
+        CBORValue::new_array(&values.into())
+    }
+
+    pub (crate) fn from_value(value: &CBORValue) -> Result<Self, JsError> {
+        use std::convert::TryFrom;
+        let indices = value.as_array()
+            .ok_or_else(|| JsError::from_str(&format!("Expected array of 5 path indices, found: {:?}", value)))?
+            .values
+            .iter()
+            .map(|v| v.as_int().and_then(|i| u32::try_from(i.0).ok()))
+            .collect::<Option<Vec<u32>>>()
+            .ok_or_else(|| JsError::from_str(&format!("Invalid path index in: {:?}", value)))?;
+        match indices.as_slice() {
+            [purpose, coin_type, account, role, index] => Ok(Self::new(*purpose, *coin_type, *account, *role, *index)),
+            _ => Err(JsError::from_str(&format!("Expected 5 path indices, found {}", indices.len()))),
+        }
+    }
+}
+
+impl cbor_event::se::Serialize for DerivationPath {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(5))?;
+        for i in self.indices().iter() {
+            serializer.write_unsigned_integer(*i as u64)?;
+        }
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for DerivationPath {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(5)?;
+            let mut indices = [0u32; 5];
+            for (i, field) in ["purpose", "coin_type", "account", "role", "index"].iter().enumerate() {
+                indices[i] = (|| -> Result<_, DeserializeError> {
+                    let index = raw.unsigned_integer()?;
+                    if index > u32::MAX as u64 {
+                        return Err(cbor_event::Error::CustomError(format!("path index out of range: {}", index)).into());
+                    }
+                    Ok(index as u32)
+                })().map_err(|e| e.annotate(*field))?;
+            }
+            match len {
+                cbor_event::Len::Len(_) => read_len.finish()?,
+                cbor_event::Len::Indefinite => match raw.special()? {
+                    cbor_event::Special::Break => (),
+                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
+                },
+            }
+            Ok(Self::new(indices[0], indices[1], indices[2], indices[3], indices[4]))
+        })().map_err(|e| e.annotate("DerivationPath"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptoxide::curve25519::ge_scalarmult_base;
+    use cryptoxide::hmac::Hmac;
+    use cryptoxide::mac::Mac;
+    use cryptoxide::sha2::Sha512;
+
+    // private-side soft derivation of the scalar so we have something independent to check against
+    fn derive_scalar(kl: &[u8; 32], xpub: &[u8], index: u32) -> [u8; 32] {
+        let mut z = [0u8; 64];
+        let mut mac = Hmac::new(Sha512::new(), &xpub[32..]);
+        mac.input(&[0x02]);
+        mac.input(&xpub[..32]);
+        mac.input(&index.to_le_bytes());
+        mac.raw_result(&mut z);
+        let mut out = [0u8; 32];
+        let mut carry = 0u16;
+        for i in 0..32 {
+            let zl8 = if i < 28 { (z[i] as u16) << 3 } else { 0 };
+            let r = kl[i] as u16 + zl8 + carry;
+            out[i] = r as u8;
+            carry = r >> 8;
+        }
+        out
+    }
+
+    #[test]
+    fn derivation_path_header() {
+        let path = DerivationPath::new_cip1852(0, 2, 7);
+        let mut header_map = HeaderMap::new();
+        header_map.set_derivation_path(&path);
+        let header_map = HeaderMap::from_bytes(header_map.to_bytes()).unwrap();
+        assert_eq!(header_map.derivation_path(), Some(path));
+        assert_eq!(path.purpose(), 0x8000073c);
+        assert_eq!(path.coin_type(), 0x80000717);
+        assert_eq!(path.account(), 0x80000000);
+        assert_eq!(DerivationPath::from_bytes(path.to_bytes()).unwrap(), path);
+    }
+
+    #[test]
+    fn verify_public_key() {
+        let kl = [
+            0x08, 0x3f, 0x5c, 0x91, 0x2a, 0x77, 0x10, 0xee, 0x4b, 0x02, 0xd1, 0x6c, 0x93, 0x05, 0xa8, 0x3e,
+            0x71, 0x5d, 0x20, 0xc4, 0x9f, 0x36, 0xb2, 0x8a, 0x11, 0xe0, 0x5f, 0x7c, 0x24, 0x69, 0xd3, 0x48,
+        ];
+        let mut account_xpub = ge_scalarmult_base(&kl).to_bytes().to_vec();
+        account_xpub.extend_from_slice(&[0x5a; 32]);
+
+        let role_xpub = crypto::derive_xpub_soft(&account_xpub, 0).unwrap();
+        let key_kl = derive_scalar(&derive_scalar(&kl, &account_xpub, 0), &role_xpub, 3);
+        let public_key = ge_scalarmult_base(&key_kl).to_bytes().to_vec();
+
+        let path = DerivationPath::new_cip1852(0, 0, 3);
+        assert!(path.verify_public_key(account_xpub.clone(), public_key.clone()).unwrap());
+        assert!(!DerivationPath::new_cip1852(0, 0, 4).verify_public_key(account_xpub.clone(), public_key.clone()).unwrap());
+        assert!(!DerivationPath::new_cip1852(0, 1, 3).verify_public_key(account_xpub.clone(), public_key.clone()).unwrap());
+        assert!(path.verify_public_key(account_xpub.clone(), public_key[..31].to_vec()).is_err());
+        assert!(path.verify_public_key(account_xpub.clone(), [&public_key[..], &[0]].concat()).is_err());
+        assert!(DerivationPath::new_cip1852(0, 0, 3 | HARDENED).verify_public_key(account_xpub, public_key).is_err());
+    }
+}
@@ -0,0 +1,1125 @@
+// COSE encryption - RFC 8152 section 5
+
+use super::*;
+
+// all supported content encryption algorithms (AES-GCM and ChaCha20/Poly1305) use 96-bit nonces
+const CONTENT_NONCE_LEN: usize = 12;
+
+// ephemeral key recipient header parameter - RFC 8152 section 12.4.1
+const EPHEMERAL_KEY: i32 = -1;
+const PASSWORD_SALT_LEN: usize = 32;
+// PBKDF2 iteration bounds accepted when decrypting so a crafted message can't make us spin forever
+const MIN_PASSWORD_ITERATIONS: u32 = 1_000;
+const MAX_PASSWORD_ITERATIONS: u32 = 10_000_000;
+/// PBKDF2-HMAC-SHA512 iterations used by `PasswordEncryption::encrypt()` - the same as EMIP-3
+pub const DEFAULT_PASSWORD_ITERATIONS: u32 = 19_162;
+
+pub (crate) fn symmetric_cose_key(k: Vec<u8>) -> COSEKey {
+    let mut key = COSEKey::new(&KeyType::Symmetric.into());
+    key.other_headers.insert(SymmetricKeyParam::K.into(), CBORValue::new_bytes(k));
+    key
+}
+
+/// Extracts the key value of a symmetric COSE_Key checking that it can be used for `alg` / `op`
+pub (crate) fn symmetric_key(key: &COSEKey, alg: AlgorithmId, op: KeyOperation) -> Result<Vec<u8>, JsError> {
+    SymmetricKey::from_cose_key(key)?.key_for(alg, op)
+}
+
+pub (crate) fn content_algorithm(headers: &Headers) -> Result<AlgorithmId, JsError> {
+    use std::convert::TryFrom;
+    let alg = headers.protected
+        .deserialized_headers()
+        .algorithm_id()
+        .ok_or_else(|| JsError::from_str("The algorithm must be set in the protected headers"))?;
+    match AlgorithmId::try_from(alg.clone()) {
+        Ok(alg @ AlgorithmId::A128GCM)
+        | Ok(alg @ AlgorithmId::A192GCM)
+        | Ok(alg @ AlgorithmId::A256GCM)
+        | Ok(alg @ AlgorithmId::ChaCha20Poly1305) => Ok(alg),
+        _ => Err(JsError::from_str(&format!("Unsupported content encryption algorithm: {:?}", alg))),
+    }
+}
+
+// key length in bytes of a content encryption algorithm
+fn content_key_len(alg: AlgorithmId) -> usize {
+    match alg {
+        AlgorithmId::A128GCM => 16,
+        AlgorithmId::A192GCM => 24,
+        _ => 32,
+    }
+}
+
+// looks in the protected headers first, then the unprotected ones
+pub (crate) fn header_param<T>(headers: &Headers, get: impl Fn(&HeaderMap) -> Option<T>) -> Option<T> {
+    get(&headers.protected.deserialized_headers()).or_else(|| get(&headers.unprotected))
+}
+
+fn init_vector(headers: &Headers) -> Option<Vec<u8>> {
+    header_param(headers, HeaderMap::init_vector)
+}
+
+pub (crate) fn check_content_key(alg: AlgorithmId, cek: &[u8]) -> Result<(), JsError> {
+    if cek.len() != content_key_len(alg) {
+        return Err(JsError::from_str(&format!("{:?} key must be {} bytes, found {}", alg, content_key_len(alg), cek.len())));
+    }
+    Ok(())
+}
+
+pub (crate) fn check_init_vector(iv: &[u8]) -> Result<(), JsError> {
+    if iv.len() != CONTENT_NONCE_LEN {
+        return Err(JsError::from_str(&format!("IV must be {} bytes, found {}", CONTENT_NONCE_LEN, iv.len())));
+    }
+    Ok(())
+}
+
+/// Derives the IV from a key's base IV and a message's partial IV - RFC 8152 section 3.1:
+/// the partial IV is left-padded with zeros to the length of the base IV and XOR'd with it.
+pub fn combine_init_vector(base_init_vector: Vec<u8>, partial_init_vector: Vec<u8>) -> Result<Vec<u8>, JsError> {
+    if partial_init_vector.len() > base_init_vector.len() {
+        return Err(JsError::from_str(&format!(
+            "Partial IV ({} bytes) can't be longer than the base IV ({} bytes)",
+            partial_init_vector.len(),
+            base_init_vector.len())));
+    }
+    let offset = base_init_vector.len() - partial_init_vector.len();
+    let mut iv = base_init_vector;
+    for (byte, partial_byte) in iv[offset..].iter_mut().zip(partial_init_vector.iter()) {
+        *byte ^= partial_byte;
+    }
+    Ok(iv)
+}
+
+// The IV of a layer: either the full IV or the partial IV combined with the key's base IV.
+// Setting both is rejected since a sender doing so could end up reusing a nonce.
+pub (crate) fn layer_init_vector(headers: &Headers, base_init_vector: Option<Vec<u8>>) -> Result<Option<Vec<u8>>, JsError> {
+    match (init_vector(headers), header_param(headers, HeaderMap::partial_init_vector)) {
+        (Some(_), Some(_)) => Err(JsError::from_str("The IV and partial IV must not both be set")),
+        (Some(iv), None) => Ok(Some(iv)),
+        (None, Some(partial_init_vector)) => {
+            let base_init_vector = base_init_vector
+                .ok_or_else(|| JsError::from_str("A partial IV requires a key with a base IV"))?;
+            combine_init_vector(base_init_vector, partial_init_vector).map(Some)
+        },
+        (None, None) => Ok(None),
+    }
+}
+
+// Puts a random IV in the unprotected headers if there is neither an IV nor a partial IV
+pub (crate) fn generate_init_vector(headers: &mut Headers) -> Result<(), JsError> {
+    if init_vector(headers).is_none() && header_param(headers, HeaderMap::partial_init_vector).is_none() {
+        headers.unprotected.set_init_vector(crypto::random_bytes(CONTENT_NONCE_LEN)?);
+    }
+    Ok(())
+}
+
+// Encrypts the body of a COSE_Encrypt(0) with the content encryption key using the protected algorithm.
+// Generates the IV if there is neither an IV nor a partial IV.
+pub (crate) fn encrypt_content(headers: &mut Headers, context: EncContext, cek: &[u8], base_init_vector: Option<Vec<u8>>, plaintext: &[u8], external_aad: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+    let alg = content_algorithm(headers)?;
+    check_content_key(alg, cek)?;
+    generate_init_vector(headers)?;
+    let iv = layer_init_vector(headers, base_init_vector)?.ok_or_else(|| JsError::from_str("Missing IV"))?;
+    check_init_vector(&iv)?;
+    let aad = EncStructure::new(context, &headers.protected, external_aad.unwrap_or_default()).to_bytes();
+    aead_encrypt(alg, cek, &iv, &aad, plaintext)
+}
+
+// a single AEAD operation with a content encryption algorithm. The tag is appended to the ciphertext.
+pub (crate) fn aead_encrypt(alg: AlgorithmId, key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, JsError> {
+    match alg {
+        AlgorithmId::ChaCha20Poly1305 => Ok(crypto::chacha20poly1305_encrypt(key, nonce, aad, plaintext)),
+        _ => crypto::aes_gcm_encrypt(key, nonce, aad, plaintext),
+    }
+}
+
+pub (crate) fn aead_decrypt(alg: AlgorithmId, key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    match alg {
+        AlgorithmId::ChaCha20Poly1305 => crypto::chacha20poly1305_decrypt(key, nonce, aad, ciphertext),
+        _ => crypto::aes_gcm_decrypt(key, nonce, aad, ciphertext),
+    }
+}
+
+fn decrypt_content(headers: &Headers, context: EncContext, cek: &[u8], base_init_vector: Option<Vec<u8>>, ciphertext: Option<&Vec<u8>>, external_aad: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+    let alg = content_algorithm(headers)?;
+    check_content_key(alg, cek)?;
+    let iv = layer_init_vector(headers, base_init_vector)?.ok_or_else(|| JsError::from_str("Missing IV"))?;
+    check_init_vector(&iv)?;
+    let ciphertext = ciphertext.ok_or_else(detached_ciphertext_error)?;
+    let aad = EncStructure::new(context, &headers.protected, external_aad.unwrap_or_default()).to_bytes();
+    aead_decrypt(alg, cek, &iv, &aad, ciphertext).ok_or_else(|| JsError::from_str("Decryption failed: ciphertext or headers were tampered with or the key is wrong"))
+}
+
+fn detached_ciphertext_error() -> JsError {
+    JsError::from_str("The ciphertext is detached: use decrypt_detached() instead")
+}
+
+// the ciphertext field with the detached `ciphertext` attached so it can be decrypted as usual
+fn attach_ciphertext(attached: &Option<Vec<u8>>, ciphertext: Vec<u8>) -> Result<Option<Vec<u8>>, JsError> {
+    match attached {
+        Some(_) => Err(JsError::from_str("The ciphertext is already attached: use decrypt() instead")),
+        None => Ok(Some(ciphertext)),
+    }
+}
+
+fn is_hpke(headers: &Headers) -> bool {
+    use std::convert::TryFrom;
+    matches!(
+        headers.protected.deserialized_headers().algorithm_id().map(AlgorithmId::try_from),
+        Some(Ok(AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305)))
+}
+
+// COSE-HPKE integrated encryption: the plaintext is sealed directly to the single recipient
+fn hpke_encrypt0(headers: &Headers, plaintext: &[u8], recipient_key: &COSEKey, external_aad: Option<Vec<u8>>) -> Result<COSEEncrypt0, JsError> {
+    if init_vector(headers).is_some() || header_param(headers, HeaderMap::partial_init_vector).is_some() {
+        return Err(JsError::from_str("HPKE derives its own nonce so neither the IV nor the partial IV may be set"));
+    }
+    let (public_key, _) = x25519_key(recipient_key)?;
+    let aad = EncStructure::new(EncContext::Encrypt0, &headers.protected, external_aad.unwrap_or_default()).to_bytes();
+    let (encapsulated_key, ciphertext) = hpke::seal_base(&public_key, &[], &aad, plaintext)?;
+    let mut headers = headers.clone();
+    headers.unprotected.set_encapsulated_key(encapsulated_key);
+    if let (None, Some(kid)) = (header_param(&headers, HeaderMap::key_id), recipient_key.key_id()) {
+        headers.unprotected.set_key_id(kid);
+    }
+    Ok(COSEEncrypt0::new(&headers, Some(ciphertext)))
+}
+
+#[wasm_bindgen]
+impl COSEEncrypt0 {
+    /// Encrypts `plaintext` with a symmetric key using AES-GCM (A128GCM, A192GCM, A256GCM) or ChaCha20/Poly1305
+    /// depending on the algorithm, which must be set in the protected headers.
+    /// With the HPKE algorithm `key` is instead the recipient's X25519 (or Ed25519) OKP public key: the plaintext
+    /// is sealed directly to it (integrated encryption) and the encapsulated key is put in the unprotected headers.
+    /// If the key has a base IV and a partial IV is set the IV is derived from them (RFC 8152 section 3.1).
+    /// If neither an IV nor a partial IV is set in either header map a random IV is generated and put in the
+    /// unprotected headers. Setting both is an error.
+    /// # Arguments
+    /// * `external_aad` - External application data - see RFC 8152 section 4.3. Set to None if not using this.
+    pub fn encrypt(headers: &Headers, plaintext: Vec<u8>, key: &COSEKey, external_aad: Option<Vec<u8>>) -> Result<COSEEncrypt0, JsError> {
+        if is_hpke(headers) {
+            return hpke_encrypt0(headers, &plaintext, key, external_aad);
+        }
+        let alg = content_algorithm(headers)?;
+        let k = symmetric_key(key, alg, KeyOperation::Encrypt)?;
+        let mut headers = headers.clone();
+        let ciphertext = encrypt_content(&mut headers, EncContext::Encrypt0, &k, key.base_init_vector(), &plaintext, external_aad)?;
+        Ok(Self::new(&headers, Some(ciphertext)))
+    }
+
+    /// Authenticates and decrypts the ciphertext. For HPKE `key` is the recipient's OKP key including the private key (d).
+    /// See `encrypt()` for the arguments.
+    pub fn decrypt(&self, key: &COSEKey, external_aad: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+        if is_hpke(&self.headers) {
+            let (_, private_key) = x25519_key(key)?;
+            let private_key = private_key.ok_or_else(|| JsError::from_str("Missing d (private key)"))?;
+            let encapsulated_key = header_param(&self.headers, HeaderMap::encapsulated_key)
+                .ok_or_else(|| JsError::from_str("Missing HPKE encapsulated key"))?;
+            let ciphertext = self.ciphertext.as_ref().ok_or_else(detached_ciphertext_error)?;
+            let aad = EncStructure::new(EncContext::Encrypt0, &self.headers.protected, external_aad.unwrap_or_default()).to_bytes();
+            return hpke::open_base(&encapsulated_key, &private_key, &[], &aad, ciphertext)
+                .ok_or_else(|| JsError::from_str("Decryption failed: ciphertext or headers were tampered with or the key is wrong"));
+        }
+        let alg = content_algorithm(&self.headers)?;
+        let k = symmetric_key(key, alg, KeyOperation::Decrypt)?;
+        decrypt_content(&self.headers, EncContext::Encrypt0, &k, key.base_init_vector(), self.ciphertext.as_ref(), external_aad)
+    }
+
+    /// Decrypts a message whose ciphertext was sent separately (see `COSEEncrypt0Builder`).
+    /// See `decrypt()` for the other arguments.
+    pub fn decrypt_detached(&self, key: &COSEKey, ciphertext: Vec<u8>, external_aad: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+        let mut attached = self.clone();
+        attached.ciphertext = attach_ciphertext(&self.ciphertext, ciphertext)?;
+        attached.decrypt(key, external_aad)
+    }
+}
+
+/// The X25519 public key and private scalar (if d is present) of an OKP COSE_Key.
+/// Ed25519 keys e.g. the COSE_Key returned by a CIP-30 wallet's signData() are converted
+/// so that messages can be encrypted to the same key a wallet signs with.
+fn x25519_key(key: &COSEKey) -> Result<(Vec<u8>, Option<[u8; 32]>), JsError> {
+    use std::convert::TryFrom;
+    if !matches!(KeyType::try_from(key.key_type()), Ok(KeyType::OKP)) {
+        return Err(JsError::from_str(&format!("Expected OKP key type, found: {:?}", key.key_type())));
+    }
+    let crv = key.header(&ECKey::CRV.into())
+        .ok_or_else(|| JsError::from_str("Missing crv"))?;
+    match value_to_label(&crv).and_then(CurveType::try_from) {
+        Ok(CurveType::X25519) => (),
+        Ok(CurveType::Ed25519) => {
+            let ed25519_key = EdDSA25519Key::from_cose_key(key)?;
+            let x = crypto::ed25519_to_x25519_public_key(&ed25519_key.public_key())
+                .ok_or_else(|| JsError::from_str("Invalid Ed25519 public key"))?;
+            let scalar = match ed25519_key.private_key() {
+                Some(d) => Some(crypto::ed25519_to_x25519_scalar(&d)
+                    .ok_or_else(|| JsError::from_str(&format!("Ed25519 private key must be 32 or 64 bytes, found {}", d.len())))?),
+                None => None,
+            };
+            return Ok((x.to_vec(), scalar));
+        },
+        _ => return Err(JsError::from_str(&format!("Expected X25519 or Ed25519 curve, found: {:?}", crv))),
+    }
+    if let Some(alg) = key.algorithm_id() {
+        if !matches!(AlgorithmId::try_from(alg.clone()), Ok(AlgorithmId::ECDHESA256KW) | Ok(AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305)) {
+            return Err(JsError::from_str(&format!("Expected ECDH-ES + A256KW or HPKE algorithm, found: {:?}", alg)));
+        }
+    }
+    let x = key.header(&ECKey::X.into())
+        .ok_or_else(|| JsError::from_str("Missing x (public key)"))
+        .and_then(|x| value_to_bytes(&x))?;
+    if x.len() != 32 {
+        return Err(JsError::from_str(&format!("X25519 public key must be 32 bytes, found {}", x.len())));
+    }
+    let scalar = match key.header(&ECKey::D.into()) {
+        Some(d) => {
+            let d = value_to_bytes(&d)?;
+            if d.len() != 32 {
+                return Err(JsError::from_str(&format!("X25519 private key must be 32 bytes, found {}", d.len())));
+            }
+            Some(crypto::x25519_clamp(&d))
+        },
+        None => None,
+    };
+    Ok((x, scalar))
+}
+
+fn x25519_cose_key(public_key: &[u8]) -> COSEKey {
+    let mut key = COSEKey::new(&KeyType::OKP.into());
+    key.other_headers.insert(ECKey::CRV.into(), CBORValue::from_label(&CurveType::X25519.into()));
+    key.other_headers.insert(ECKey::X.into(), CBORValue::new_bytes(public_key.to_vec()));
+    key
+}
+
+// body headers with only `alg` in the protected headers for the tests
+#[cfg(test)]
+pub (crate) fn alg_headers(alg: AlgorithmId) -> Headers {
+    let mut protected = HeaderMap::new();
+    protected.set_algorithm_id(&alg.into());
+    Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new())
+}
+
+// (public, private) X25519 COSE_Keys from the private key [seed; 32] for the tests
+#[cfg(test)]
+pub (crate) fn x25519_keypair(seed: u8) -> (COSEKey, COSEKey) {
+    let private_key = [seed; 32];
+    let public_key = crypto::x25519_public_key(&private_key);
+    let public = x25519_cose_key(&public_key);
+    let mut private = public.clone();
+    private.other_headers.insert(ECKey::D.into(), CBORValue::new_bytes(private_key.to_vec()));
+    (public, private)
+}
+
+#[wasm_bindgen]
+impl COSEKDFContext {
+    /// Derives a key of `supp_pub_info().key_data_length()` bits from `secret` using HKDF-SHA256 (RFC 5869)
+    /// with this context as the info - RFC 8152 section 11.1
+    /// # Arguments
+    /// * `salt` - HKDF salt e.g. from the salt (-20) header. None means no salt (all zeros).
+    pub fn hkdf_sha256(&self, secret: Vec<u8>, salt: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+        let len = self.key_data_len(32)?;
+        Ok(crypto::hkdf_sha256(&salt.unwrap_or_default(), &secret, &self.to_bytes(), len))
+    }
+
+    /// HKDF-SHA512 version of `hkdf_sha256()`
+    pub fn hkdf_sha512(&self, secret: Vec<u8>, salt: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+        let len = self.key_data_len(64)?;
+        Ok(crypto::hkdf_sha512(&salt.unwrap_or_default(), &secret, &self.to_bytes(), len))
+    }
+}
+
+impl COSEKDFContext {
+    // the key data length in bytes checked against the HKDF output limit of 255 hashes.
+    // % instead of is_multiple_of() which needs Rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
+    fn key_data_len(&self, hash_len: usize) -> Result<usize, JsError> {
+        let bits = self.supp_pub_info.key_data_length as usize;
+        if bits == 0 || bits % 8 != 0 || bits / 8 > 255 * hash_len {
+            return Err(JsError::from_str(&format!("Can't derive a key of {} bits with HKDF", bits)));
+        }
+        Ok(bits / 8)
+    }
+}
+
+// COSE_KDF_Context of a recipient layer with the party info taken from its headers - RFC 8152 section 11.2
+fn recipient_kdf_context(alg: AlgorithmId, key_data_length: u32, headers: &Headers) -> Result<COSEKDFContext, JsError> {
+    let mut party_u_info = PartyInfo::new();
+    if let Some(identity) = header_param(headers, HeaderMap::party_u_identity) {
+        party_u_info.set_identity(identity);
+    }
+    if let Some(nonce) = header_param(headers, HeaderMap::party_u_nonce) {
+        party_u_info.set_nonce(&nonce)?;
+    }
+    if let Some(other) = header_param(headers, HeaderMap::party_u_other) {
+        party_u_info.set_other(other);
+    }
+    let mut party_v_info = PartyInfo::new();
+    if let Some(identity) = header_param(headers, HeaderMap::party_v_identity) {
+        party_v_info.set_identity(identity);
+    }
+    if let Some(nonce) = header_param(headers, HeaderMap::party_v_nonce) {
+        party_v_info.set_nonce(&nonce)?;
+    }
+    if let Some(other) = header_param(headers, HeaderMap::party_v_other) {
+        party_v_info.set_other(other);
+    }
+    let supp_pub_info = SuppPubInfo::new(key_data_length, &headers.protected);
+    Ok(COSEKDFContext::new(&alg.into(), &party_u_info, &party_v_info, &supp_pub_info))
+}
+
+// The KEK shared with a recipient for ECDH-ES + A256KW - RFC 8152 section 12.5.1
+fn ecdh_es_a256kw_kek(shared_secret: &[u8], recipient_headers: &Headers) -> Result<Vec<u8>, JsError> {
+    recipient_kdf_context(AlgorithmId::A256KW, 256, recipient_headers)?
+        .hkdf_sha256(shared_secret.to_vec(), header_param(recipient_headers, HeaderMap::salt))
+}
+
+// copies the recipient key's id (if any) to the unprotected headers unless a key id is already set
+fn set_recipient_key_id(headers: &mut Headers, recipient_key: &COSEKey) {
+    if let (None, Some(kid)) = (header_param(headers, HeaderMap::key_id), recipient_key.key_id()) {
+        headers.unprotected.set_key_id(kid);
+    }
+}
+
+// the recipient layer headers with `alg` set in the protected headers on top of the caller's `headers`
+fn recipient_headers(headers: &Headers, alg: AlgorithmId, recipient_key: &COSEKey) -> Headers {
+    let mut protected = headers.protected.deserialized_headers();
+    protected.set_algorithm_id(&alg.into());
+    let mut headers = Headers::new(&ProtectedHeaderMap::new(&protected), &headers.unprotected);
+    set_recipient_key_id(&mut headers, recipient_key);
+    headers
+}
+
+fn ecdh_es_a256kw_recipient(recipient_key: &COSEKey, cek: &[u8], headers: &Headers) -> Result<COSERecipient, JsError> {
+    let (recipient_public_key, _) = x25519_key(recipient_key)?;
+    let ephemeral_private_key = crypto::random_bytes(32)?;
+    let ephemeral_public_key = crypto::x25519_public_key(&ephemeral_private_key);
+    let shared_secret = crypto::x25519(&ephemeral_private_key, &recipient_public_key)
+        .ok_or_else(|| JsError::from_str("Invalid X25519 recipient public key"))?;
+    let mut headers = recipient_headers(headers, AlgorithmId::ECDHESA256KW, recipient_key);
+    headers.unprotected.other_headers.insert(
+        Label::new_int(&Int::new_i32(EPHEMERAL_KEY)),
+        CBORValue::from_bytes(x25519_cose_key(&ephemeral_public_key).to_bytes())?);
+    let kek = ecdh_es_a256kw_kek(&shared_secret, &headers)?;
+    let wrapped_cek = crypto::aes_key_wrap(&kek, cek)?;
+    Ok(COSERecipient::new(&headers, Some(wrapped_cek)))
+}
+
+// the algorithm of a symmetric recipient key: AES key wrap or direct
+fn symmetric_recipient_algorithm(key: &COSEKey) -> Result<AlgorithmId, JsError> {
+    use std::convert::TryFrom;
+    let alg = key.algorithm_id()
+        .ok_or_else(|| JsError::from_str("Symmetric recipient keys must have their algorithm set (A128KW, A192KW, A256KW or direct)"))?;
+    match AlgorithmId::try_from(alg.clone()) {
+        Ok(alg @ AlgorithmId::A128KW)
+        | Ok(alg @ AlgorithmId::A192KW)
+        | Ok(alg @ AlgorithmId::A256KW)
+        | Ok(alg @ AlgorithmId::Direct) => Ok(alg),
+        _ => Err(JsError::from_str(&format!("Unsupported symmetric recipient algorithm: {:?}", alg))),
+    }
+}
+
+// the shared key of a symmetric recipient key, checked against its algorithm
+fn symmetric_recipient_key(key: &COSEKey, alg: AlgorithmId, op: KeyOperation) -> Result<Vec<u8>, JsError> {
+    let k = symmetric_key(key, alg, op)?;
+    let expected_len = match alg {
+        AlgorithmId::A128KW => Some(16),
+        AlgorithmId::A192KW => Some(24),
+        AlgorithmId::A256KW => Some(32),
+        // checked against the content algorithm instead
+        _ => None,
+    };
+    if let Some(expected_len) = expected_len {
+        if k.len() != expected_len {
+            return Err(JsError::from_str(&format!("{:?} key must be {} bytes, found {}", alg, expected_len, k.len())));
+        }
+    }
+    Ok(k)
+}
+
+// Key wrap (RFC 8152 section 12.3) or direct (section 12.1) recipient. Both have empty protected headers
+// with the algorithm in the unprotected ones. Direct recipients have an empty ciphertext.
+fn symmetric_recipient(recipient_key: &COSEKey, cek: &[u8], headers: &Headers) -> Result<COSERecipient, JsError> {
+    let alg = symmetric_recipient_algorithm(recipient_key)?;
+    if headers.protected.deserialized_headers().keys().len() != 0 {
+        return Err(JsError::from_str(&format!("{:?} recipients must have empty protected headers", alg)));
+    }
+    let ciphertext = match alg {
+        AlgorithmId::Direct => Vec::new(),
+        _ => crypto::aes_key_wrap(&symmetric_recipient_key(recipient_key, alg, KeyOperation::WrapKey)?, cek)?,
+    };
+    let mut headers = Headers::new(&ProtectedHeaderMap::new_empty(), &headers.unprotected);
+    headers.unprotected.set_algorithm_id(&alg.into());
+    set_recipient_key_id(&mut headers, recipient_key);
+    Ok(COSERecipient::new(&headers, Some(ciphertext)))
+}
+
+// COSE-HPKE key encryption: the content key is sealed to the recipient, whose layer has the
+// HPKE algorithm in its protected headers and the encapsulated key in its unprotected ones
+fn hpke_recipient(recipient_key: &COSEKey, cek: &[u8], headers: &Headers) -> Result<COSERecipient, JsError> {
+    let (public_key, _) = x25519_key(recipient_key)?;
+    let mut headers = recipient_headers(headers, AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305, recipient_key);
+    let aad = EncStructure::new(EncContext::EncRecipient, &headers.protected, Vec::new()).to_bytes();
+    let (encapsulated_key, wrapped_cek) = hpke::seal_base(&public_key, &[], &aad, cek)?;
+    headers.unprotected.set_encapsulated_key(encapsulated_key);
+    Ok(COSERecipient::new(&headers, Some(wrapped_cek)))
+}
+
+#[wasm_bindgen]
+impl COSERecipient {
+    /// Creates the recipient layer that gives the holder of `recipient_key` the content key:
+    /// * OKP keys with the HPKE algorithm: HPKE key encryption
+    /// * other OKP (X25519 or Ed25519) keys: ECDH-ES + A256KW
+    /// * symmetric keys with algorithm A128KW, A192KW or A256KW: AES key wrap
+    /// * symmetric keys with the direct algorithm: `content_key` must be the key itself
+    ///
+    /// `headers` are the recipient layer's own headers e.g. the KDF party identities, which are
+    /// used in the key derivation. The algorithm and the key-specific headers are added to them.
+    pub fn for_key(recipient_key: &COSEKey, content_key: Vec<u8>, headers: &Headers) -> Result<COSERecipient, JsError> {
+        use std::convert::TryFrom;
+        match KeyType::try_from(recipient_key.key_type()) {
+            Ok(KeyType::OKP) => match recipient_key.algorithm_id().map(AlgorithmId::try_from) {
+                Some(Ok(AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305)) => hpke_recipient(recipient_key, &content_key, headers),
+                _ => ecdh_es_a256kw_recipient(recipient_key, &content_key, headers),
+            },
+            Ok(KeyType::Symmetric) => {
+                if matches!(symmetric_recipient_algorithm(recipient_key)?, AlgorithmId::Direct)
+                    && symmetric_recipient_key(recipient_key, AlgorithmId::Direct, KeyOperation::Encrypt)? != content_key {
+                    return Err(JsError::from_str("The content key of a direct recipient must be the recipient's key"));
+                }
+                symmetric_recipient(recipient_key, &content_key, headers)
+            },
+            _ => Err(JsError::from_str(&format!("Unsupported recipient key type: {:?}", recipient_key.key_type()))),
+        }
+    }
+}
+
+impl COSERecipient {
+    fn algorithm(&self) -> Option<AlgorithmId> {
+        use std::convert::TryFrom;
+        header_param(&self.headers, HeaderMap::algorithm_id).and_then(|alg| AlgorithmId::try_from(alg).ok())
+    }
+
+    // the content key if this recipient layer is ECDH-ES + A256KW to the key with the X25519 scalar `private_key`
+    fn unwrap_ecdh_es_a256kw(&self, private_key: &[u8; 32]) -> Option<Vec<u8>> {
+        if !matches!(self.algorithm()?, AlgorithmId::ECDHESA256KW) {
+            return None;
+        }
+        let ephemeral_key = self.headers.unprotected.header(&Label::new_int(&Int::new_i32(EPHEMERAL_KEY)))?;
+        let (ephemeral_public_key, _) = x25519_key(&COSEKey::from_bytes(ephemeral_key.to_bytes()).ok()?).ok()?;
+        let shared_secret = crypto::x25519_scalar_mult(private_key, &ephemeral_public_key)?;
+        let kek = ecdh_es_a256kw_kek(&shared_secret, &self.headers).ok()?;
+        crypto::aes_key_unwrap(&kek, self.ciphertext.as_ref()?)
+    }
+
+    // the content key if this recipient layer is HPKE to the key with the X25519 scalar `private_key`
+    fn unwrap_hpke(&self, private_key: &[u8; 32]) -> Option<Vec<u8>> {
+        if !matches!(self.algorithm()?, AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305) {
+            return None;
+        }
+        let encapsulated_key = header_param(&self.headers, HeaderMap::encapsulated_key)?;
+        let aad = EncStructure::new(EncContext::EncRecipient, &self.headers.protected, Vec::new()).to_bytes();
+        hpke::open_base(&encapsulated_key, private_key, &[], &aad, self.ciphertext.as_ref()?)
+    }
+
+    // the content key if this recipient layer is `alg` (key wrap or direct) with the shared key `k`
+    fn unwrap_symmetric(&self, alg: AlgorithmId, k: &[u8]) -> Option<Vec<u8>> {
+        let recipient_alg = self.algorithm()?;
+        if Label::from(recipient_alg) != Label::from(alg) {
+            return None;
+        }
+        match alg {
+            AlgorithmId::Direct => Some(k.to_vec()),
+            _ => crypto::aes_key_unwrap(k, self.ciphertext.as_ref()?),
+        }
+    }
+}
+
+// the content key and, for a direct key, its base IV
+pub (crate) type ContentKey = (Vec<u8>, Option<Vec<u8>>);
+
+// The content key (and the base IV of a direct key) from the first of `recipients` that `key` opens.
+// Only layers whose key id matches the key's (if both have one) are tried.
+// `direct_op` is the operation a direct key must allow as it's used on the content itself.
+pub (crate) fn recipients_content_key(recipients: &COSERecipients, key: &COSEKey, direct_op: KeyOperation) -> Result<ContentKey, JsError> {
+    open_recipients(recipients, key, direct_op)?
+        .ok_or_else(|| JsError::from_str("No recipient layer could be opened with this key"))
+}
+
+// as recipients_content_key() but None if the key is usable yet opens no recipient layer
+pub (crate) fn open_recipients(recipients: &COSERecipients, key: &COSEKey, direct_op: KeyOperation) -> Result<Option<ContentKey>, JsError> {
+    use std::convert::TryFrom;
+    let key_id = key.key_id();
+    let mut recipients = recipients.0
+        .iter()
+        .filter(|recipient| match (&key_id, recipient.headers.unprotected.key_id()) {
+            (Some(ours), Some(theirs)) => *ours == theirs,
+            _ => true,
+        });
+    // only a direct key can carry a base IV for the content layer
+    let mut base_init_vector = None;
+    let content_key = match KeyType::try_from(key.key_type()) {
+        Ok(KeyType::Symmetric) => {
+            let alg = symmetric_recipient_algorithm(key)?;
+            let op = match alg {
+                AlgorithmId::Direct => {
+                    base_init_vector = key.base_init_vector();
+                    direct_op
+                },
+                _ => KeyOperation::UnwrapKey,
+            };
+            let k = symmetric_recipient_key(key, alg, op)?;
+            recipients.find_map(|recipient| recipient.unwrap_symmetric(alg, &k))
+        },
+        _ => {
+            let (_, private_key) = x25519_key(key)?;
+            let private_key = private_key.ok_or_else(|| JsError::from_str("Missing d (private key)"))?;
+            recipients.find_map(|recipient| recipient.unwrap_ecdh_es_a256kw(&private_key)
+                .or_else(|| recipient.unwrap_hpke(&private_key)))
+        },
+    };
+    Ok(content_key.map(|content_key| (content_key, base_init_vector)))
+}
+
+#[wasm_bindgen]
+impl COSEEncrypt {
+    /// Encrypts `plaintext` with AES-GCM or ChaCha20/Poly1305 depending on the protected algorithm
+    /// and adds a recipient layer for every key in `recipients` depending on its type:
+    /// * OKP (X25519 or Ed25519 e.g. from CIP-30 signData()) public keys use ECDH-ES + A256KW: each recipient
+    ///   gets a fresh ephemeral X25519 key in its unprotected headers and the content key wrapped with a key
+    ///   derived from the shared secret via HKDF-SHA256 and the COSE_KDF_Context.
+    /// * OKP keys with the HPKE algorithm get the content key sealed to them with HPKE (key encryption mode)
+    ///   with the encapsulated key in their unprotected headers.
+    /// * Symmetric keys with algorithm A128KW, A192KW or A256KW get the content key wrapped with AES key wrap.
+    /// * A symmetric key with the direct algorithm is used as the content key. It must be the only recipient.
+    ///
+    /// Otherwise the content key is random.
+    /// The recipients' key ids (if any) are copied to their headers to help them find their layer.
+    /// # Arguments
+    /// * `headers` - body headers. The algorithm must be set in the protected headers. The IV is generated if absent.
+    /// * `external_aad` - External application data - see RFC 8152 section 4.3. Set to None if not using this.
+    pub fn encrypt(headers: &Headers, plaintext: Vec<u8>, recipients: &COSEKeySet, external_aad: Option<Vec<u8>>) -> Result<COSEEncrypt, JsError> {
+        use std::convert::TryFrom;
+        if recipients.is_empty() {
+            return Err(JsError::from_str("At least one recipient is needed"));
+        }
+        let alg = content_algorithm(headers)?;
+        let direct_key = recipients.0
+            .iter()
+            .find(|key| matches!(key.algorithm_id().map(AlgorithmId::try_from), Some(Ok(AlgorithmId::Direct))));
+        let (cek, base_init_vector) = match direct_key {
+            Some(direct_key) => {
+                if recipients.len() != 1 {
+                    return Err(JsError::from_str("A direct recipient must be the only recipient"));
+                }
+                (symmetric_recipient_key(direct_key, AlgorithmId::Direct, KeyOperation::Encrypt)?, direct_key.base_init_vector())
+            },
+            None => (crypto::random_bytes(content_key_len(alg))?, None),
+        };
+        let mut headers = headers.clone();
+        let ciphertext = encrypt_content(&mut headers, EncContext::Encrypt, &cek, base_init_vector, &plaintext, external_aad)?;
+        let mut cose_recipients = COSERecipients::new();
+        for recipient_key in recipients.0.iter() {
+            cose_recipients.add(&COSERecipient::for_key(recipient_key, cek.clone(), &Headers::new(&ProtectedHeaderMap::new_empty(), &HeaderMap::new()))?);
+        }
+        Ok(Self::new(&headers, Some(ciphertext), &cose_recipients))
+    }
+
+    /// Decrypts as one of the recipients. `key` is either:
+    /// * the recipient's X25519 or Ed25519 OKP key including the private key (d).
+    ///   Ed25519 private keys can be 32-byte seeds or 64-byte BIP32-Ed25519 extended keys (kL || kR).
+    /// * a symmetric key with algorithm A128KW, A192KW, A256KW or direct.
+    ///
+    /// See `encrypt()` for the other arguments.
+    pub fn decrypt(&self, key: &COSEKey, external_aad: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+        let (cek, base_init_vector) = recipients_content_key(&self.recipients, key, KeyOperation::Decrypt)?;
+        decrypt_content(&self.headers, EncContext::Encrypt, &cek, base_init_vector, self.ciphertext.as_ref(), external_aad)
+    }
+
+    /// Decrypts a message whose ciphertext was sent separately (see `COSEEncryptBuilder`).
+    /// See `decrypt()` for the other arguments.
+    pub fn decrypt_detached(&self, key: &COSEKey, ciphertext: Vec<u8>, external_aad: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+        let mut attached = self.clone();
+        attached.ciphertext = attach_ciphertext(&self.ciphertext, ciphertext)?;
+        attached.decrypt(key, external_aad)
+    }
+}
+
+#[wasm_bindgen]
+impl PubKeyEncryption {
+    /// Encrypts `plaintext` with ChaCha20/Poly1305 to all `recipients` (X25519 or Ed25519 OKP public keys).
+    /// See `COSEEncrypt::encrypt()` for details.
+    pub fn encrypt(plaintext: Vec<u8>, recipients: &COSEKeySet) -> Result<PubKeyEncryption, JsError> {
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::ChaCha20Poly1305.into());
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        Ok(Self(COSEEncrypt::encrypt(&headers, plaintext, recipients, None)?))
+    }
+
+    pub fn decrypt(&self, key: &COSEKey) -> Result<Vec<u8>, JsError> {
+        self.0.decrypt(key, None)
+    }
+}
+
+#[wasm_bindgen]
+impl PasswordEncryption {
+    /// Encrypts `plaintext` with a key derived from `password` using PBKDF2-HMAC-SHA512 and
+    /// ChaCha20/Poly1305. The random salt and the iteration count are put in the protected headers
+    /// (salt under -20 and the count under "iterations") so they are authenticated too.
+    pub fn encrypt(password: Vec<u8>, plaintext: Vec<u8>) -> Result<PasswordEncryption, JsError> {
+        Self::encrypt_with_iterations(password, plaintext, DEFAULT_PASSWORD_ITERATIONS)
+    }
+
+    pub fn encrypt_with_iterations(password: Vec<u8>, plaintext: Vec<u8>, iterations: u32) -> Result<PasswordEncryption, JsError> {
+        check_password_iterations(iterations)?;
+        let salt = crypto::random_bytes(PASSWORD_SALT_LEN)?;
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::ChaCha20Poly1305.into());
+        protected.set_salt(salt.clone());
+        protected.other_headers.insert(
+            Label::new_text(String::from("iterations")),
+            CBORValue::new_int(&Int::new(&to_bignum(iterations as u64))));
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        let key = symmetric_cose_key(crypto::pbkdf2_sha512(&password, &salt, iterations).to_vec());
+        Ok(Self(COSEEncrypt0::encrypt(&headers, plaintext, &key, None)?))
+    }
+
+    pub fn decrypt(&self, password: Vec<u8>) -> Result<Vec<u8>, JsError> {
+        use std::convert::TryFrom;
+        let protected = self.0.headers.protected.deserialized_headers();
+        let salt = protected.salt()
+            .ok_or_else(|| JsError::from_str("Missing salt in protected headers"))?;
+        let iterations = protected.header(&Label::new_text(String::from("iterations")))
+            .and_then(|iterations| iterations.as_int())
+            .and_then(|iterations| iterations.as_positive())
+            .and_then(|iterations| u32::try_from(from_bignum(&iterations)).ok())
+            .ok_or_else(|| JsError::from_str("Missing or invalid iterations in protected headers"))?;
+        check_password_iterations(iterations)?;
+        let key = symmetric_cose_key(crypto::pbkdf2_sha512(&password, &salt, iterations).to_vec());
+        self.0.decrypt(&key, None)
+    }
+}
+
+fn check_password_iterations(iterations: u32) -> Result<(), JsError> {
+    if !(MIN_PASSWORD_ITERATIONS..=MAX_PASSWORD_ITERATIONS).contains(&iterations) {
+        return Err(JsError::from_str(&format!(
+            "PBKDF2 iterations must be between {} and {}, found {}",
+            MIN_PASSWORD_ITERATIONS,
+            MAX_PASSWORD_ITERATIONS,
+            iterations)));
+    }
+    Ok(())
+}
+
+/// Generates partial IVs from a message counter for channels that send many messages under one key
+/// with a base IV (RFC 8152 section 3.1). Each partial IV is the counter as the shortest big-endian
+/// byte string so every message gets a distinct IV as long as the generator state isn't reused.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct PartialIVGenerator {
+    next: Option<u64>,
+}
+
+#[wasm_bindgen]
+impl PartialIVGenerator {
+    /// Starts counting at 0
+    pub fn new() -> Self {
+        Self {
+            next: Some(0),
+        }
+    }
+
+    /// Resumes counting at `next` e.g. after restoring a persisted `counter()`
+    pub fn starting_at(next: &BigNum) -> Self {
+        Self {
+            next: Some(from_bignum(next)),
+        }
+    }
+
+    /// The counter value the next partial IV will be generated from. None once exhausted.
+    pub fn counter(&self) -> Option<BigNum> {
+        self.next.map(to_bignum)
+    }
+
+    /// Returns the next partial IV. Fails once the counter is exhausted rather than wrapping around.
+    pub fn next_partial_iv(&mut self) -> Result<Vec<u8>, JsError> {
+        let counter = self.next.ok_or_else(|| JsError::from_str("Partial IV counter exhausted"))?;
+        self.next = counter.checked_add(1);
+        let bytes = counter.to_be_bytes();
+        let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count().min(bytes.len() - 1);
+        Ok(bytes[leading_zeros..].to_vec())
+    }
+}
+
+impl Default for PartialIVGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt0_round_trip() {
+        let key = symmetric_cose_key(vec![0x42; 32]);
+        let note = b"wallet note: rent is due on the 1st".to_vec();
+        let encrypted = COSEEncrypt0::encrypt(&alg_headers(AlgorithmId::ChaCha20Poly1305), note.clone(), &key, Some(b"note-id-1".to_vec())).unwrap();
+        let iv = encrypted.headers().unprotected().init_vector().unwrap();
+        assert_eq!(iv.len(), 12);
+        assert_eq!(encrypted.ciphertext().unwrap().len(), note.len() + 16);
+
+        let parsed = COSEEncrypt0::from_bytes(encrypted.to_bytes()).unwrap();
+        assert_eq!(parsed.decrypt(&key, Some(b"note-id-1".to_vec())).unwrap(), note);
+        // wrong external_aad, wrong key
+        assert!(parsed.decrypt(&key, None).is_err());
+        assert!(parsed.decrypt(&symmetric_cose_key(vec![0x43; 32]), Some(b"note-id-1".to_vec())).is_err());
+        // tampered unprotected IV
+        let mut unprotected = parsed.headers().unprotected();
+        unprotected.set_init_vector(vec![0u8; 12]);
+        let tampered = COSEEncrypt0::new(&Headers::new(&parsed.headers().protected(), &unprotected), parsed.ciphertext());
+        assert!(tampered.decrypt(&key, Some(b"note-id-1".to_vec())).is_err());
+    }
+
+    #[test]
+    fn encrypt0_rfc8439_vector() {
+        // RFC 8439 section 2.8.2 AEAD test vector for the primitive
+        let k = hex::decode("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f").unwrap();
+        let iv = hex::decode("070000004041424344454647").unwrap();
+        let aad = hex::decode("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let ciphertext = crypto::chacha20poly1305_encrypt(&k, &iv, &aad, plaintext);
+        assert_eq!(
+            hex::encode(&ciphertext[..plaintext.len()]),
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b6116");
+        assert_eq!(hex::encode(&ciphertext[plaintext.len()..]), "1ae10b594f09e26a7e902ecbd0600691");
+
+        // the same key and IV with the Enc_structure ["Encrypt0", h'A1011818', h''] as the AAD
+        let mut unprotected = HeaderMap::new();
+        unprotected.set_init_vector(iv.clone());
+        let headers = Headers::new(&alg_headers(AlgorithmId::ChaCha20Poly1305).protected(), &unprotected);
+        let encrypted = COSEEncrypt0::encrypt(&headers, b"Ladies and Gentlemen".to_vec(), &symmetric_cose_key(k), None).unwrap();
+        assert_eq!(
+            hex::encode(encrypted.to_bytes()),
+            "8344a1011818a1054c0700000040414243444546475824d31a8d34648e60db7b86afbc53ef7ec2a4aded51efcf9e58bce37b3985dc3362cc1e2287");
+    }
+
+    #[test]
+    fn encrypt0_key_checks() {
+        let plaintext = vec![1u8; 10];
+        // alg must be in the protected headers
+        let mut unprotected = HeaderMap::new();
+        unprotected.set_algorithm_id(&AlgorithmId::ChaCha20Poly1305.into());
+        let unprotected_alg = Headers::new(&ProtectedHeaderMap::new_empty(), &unprotected);
+        assert!(COSEEncrypt0::encrypt(&unprotected_alg, plaintext.clone(), &symmetric_cose_key(vec![0u8; 32]), None).is_err());
+        assert!(COSEEncrypt0::encrypt(&alg_headers(AlgorithmId::ChaCha20Poly1305), plaintext.clone(), &symmetric_cose_key(vec![0u8; 16]), None).is_err());
+        let mut decrypt_only = symmetric_cose_key(vec![0u8; 32]);
+        let mut ops = Labels::new();
+        ops.add(&KeyOperation::Decrypt.into());
+        decrypt_only.set_key_ops(&ops);
+        assert!(COSEEncrypt0::encrypt(&alg_headers(AlgorithmId::ChaCha20Poly1305), plaintext, &decrypt_only, None).is_err());
+    }
+
+    #[test]
+    fn password_encryption() {
+        let export = b"{\"accounts\":[0,1]}".to_vec();
+        let encrypted = PasswordEncryption::encrypt_with_iterations(b"correct horse".to_vec(), export.clone(), 1_000).unwrap();
+        let bytes = encrypted.to_bytes();
+        // tag 16
+        assert_eq!(bytes[0], 0xd0);
+        let parsed = PasswordEncryption::from_bytes(bytes).unwrap();
+        assert_eq!(parsed.decrypt(b"correct horse".to_vec()).unwrap(), export);
+        assert!(parsed.decrypt(b"battery staple".to_vec()).is_err());
+        let protected = parsed.0.headers().protected().deserialized_headers();
+        assert_eq!(protected.salt().unwrap().len(), PASSWORD_SALT_LEN);
+        assert!(PasswordEncryption::encrypt_with_iterations(b"pw".to_vec(), export, 10).is_err());
+    }
+
+    #[test]
+    fn x25519_rfc7748() {
+        let alice = hex::decode("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a").unwrap();
+        let bob_public = hex::decode("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f").unwrap();
+        assert_eq!(
+            hex::encode(crypto::x25519_public_key(&alice)),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a");
+        assert_eq!(
+            hex::encode(crypto::x25519(&alice, &bob_public).unwrap()),
+            "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert!(crypto::x25519(&alice, &[0u8; 32]).is_none());
+    }
+
+    #[test]
+    fn pub_key_encryption_multi_recipient() {
+        let (alice, alice_private) = x25519_keypair(1);
+        let (mut bob, mut bob_private) = x25519_keypair(2);
+        bob.set_key_id(b"bob".to_vec());
+        bob_private.set_key_id(b"bob".to_vec());
+        let (_, eve_private) = x25519_keypair(3);
+        let mut recipients = COSEKeySet::new();
+        recipients.add(&alice);
+        recipients.add(&bob);
+
+        let message = b"meet at the usual place".to_vec();
+        let encrypted = PubKeyEncryption::encrypt(message.clone(), &recipients).unwrap();
+        let bytes = encrypted.to_bytes();
+        // tag 96
+        assert_eq!(&bytes[..2], &[0xd8, 0x60]);
+        let parsed = PubKeyEncryption::from_bytes(bytes).unwrap();
+        let cose_recipients = parsed.0.recipients();
+        assert_eq!(cose_recipients.len(), 2);
+        assert_eq!(cose_recipients.get(1).headers().unprotected().key_id(), Some(b"bob".to_vec()));
+        // 32 byte content key + 8 bytes of AES key wrap integrity check
+        assert_eq!(cose_recipients.get(0).ciphertext().unwrap().len(), 40);
+
+        assert_eq!(parsed.decrypt(&alice_private).unwrap(), message);
+        assert_eq!(parsed.decrypt(&bob_private).unwrap(), message);
+        assert!(parsed.decrypt(&eve_private).is_err());
+        // public keys can't decrypt
+        assert!(parsed.decrypt(&alice).is_err());
+    }
+
+    #[test]
+    fn pub_key_encryption_to_ed25519() {
+        // RFC 8032 section 7.1 test 1 as a COSE_Key
+        let seed = hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+        let mut wallet = EdDSA25519Key::new(hex::decode("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a").unwrap());
+        let wallet_public = wallet.build();
+        wallet.set_private_key(seed);
+        let wallet_private = wallet.build();
+
+        // BIP32-Ed25519 extended private key whose scalar doesn't survive X25519 clamping
+        let mut extended = vec![3u8; 64];
+        extended[0] = 8;
+        extended[31] = 0x20;
+        let mut derived = EdDSA25519Key::new(cryptoxide::curve25519::ge_scalarmult_base(&extended[..32]).to_bytes().to_vec());
+        let derived_public = derived.build();
+        derived.set_private_key(extended);
+        let derived_private = derived.build();
+
+        let mut recipients = COSEKeySet::new();
+        recipients.add(&wallet_public);
+        recipients.add(&derived_public);
+        let message = b"wallet to wallet".to_vec();
+        let encrypted = PubKeyEncryption::encrypt(message.clone(), &recipients).unwrap();
+        assert_eq!(encrypted.decrypt(&wallet_private).unwrap(), message);
+        assert_eq!(encrypted.decrypt(&derived_private).unwrap(), message);
+
+        let (_, x25519_private) = x25519_keypair(4);
+        assert!(encrypted.decrypt(&x25519_private).is_err());
+    }
+
+    #[test]
+    fn kdf_context_hkdf() {
+        let context = COSEKDFContext::new(
+            &AlgorithmId::A256KW.into(),
+            &PartyInfo::new(),
+            &PartyInfo::new(),
+            &SuppPubInfo::new(256, &ProtectedHeaderMap::new_empty()));
+        let secret = vec![7u8; 32];
+        let key = context.hkdf_sha256(secret.clone(), None).unwrap();
+        assert_eq!(key, crypto::hkdf_sha256(&[], &secret, &context.to_bytes(), 32));
+        assert_ne!(key, context.hkdf_sha256(secret.clone(), Some(vec![1u8; 16])).unwrap());
+        assert_ne!(key, context.hkdf_sha512(secret.clone(), None).unwrap());
+
+        let mut party_u_info = PartyInfo::new();
+        party_u_info.set_identity(b"alice".to_vec());
+        let other_context = COSEKDFContext::new(
+            &AlgorithmId::A256KW.into(),
+            &party_u_info,
+            &PartyInfo::new(),
+            &SuppPubInfo::new(256, &ProtectedHeaderMap::new_empty()));
+        assert_ne!(key, other_context.hkdf_sha256(secret.clone(), None).unwrap());
+
+        let odd_length = COSEKDFContext::new(
+            &AlgorithmId::A256KW.into(),
+            &PartyInfo::new(),
+            &PartyInfo::new(),
+            &SuppPubInfo::new(255, &ProtectedHeaderMap::new_empty()));
+        assert!(odd_length.hkdf_sha256(secret, None).is_err());
+    }
+
+    #[test]
+    fn hkdf_rfc5869() {
+        // RFC 5869 A.1
+        let ikm = vec![0x0bu8; 22];
+        let salt = hex::decode("000102030405060708090a0b0c").unwrap();
+        let info = hex::decode("f0f1f2f3f4f5f6f7f8f9").unwrap();
+        assert_eq!(
+            hex::encode(crypto::hkdf_sha256(&salt, &ikm, &info, 42)),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865");
+    }
+
+    fn recipient_key(alg: AlgorithmId, k: Vec<u8>, kid: &[u8]) -> COSEKey {
+        let mut key = symmetric_cose_key(k);
+        key.set_algorithm_id(&alg.into());
+        key.set_key_id(kid.to_vec());
+        key
+    }
+
+    #[test]
+    fn aes_gcm_and_key_wrap_vectors() {
+        // GCM spec (McGrew & Viega) test case 2
+        assert_eq!(
+            hex::encode(crypto::aes_gcm_encrypt(&[0u8; 16], &[0u8; 12], &[], &[0u8; 16]).unwrap()),
+            "0388dace60b6a392f328c2b971b2fe78ab6e47d42cec13bdf53a67b21257bddf");
+        // RFC 3394 section 4.1
+        let kek = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let wrapped = crypto::aes_key_wrap(&kek, &hex::decode("00112233445566778899aabbccddeeff").unwrap()).unwrap();
+        assert_eq!(hex::encode(&wrapped), "1fa68b0a8112b447aef34bd8fb5a7b829d3e862371d2cfe5");
+        assert_eq!(hex::encode(crypto::aes_key_unwrap(&kek, &wrapped).unwrap()), "00112233445566778899aabbccddeeff");
+        assert!(crypto::aes_key_unwrap(&[1u8; 16], &wrapped).is_none());
+    }
+
+    #[test]
+    fn encrypt0_aes_gcm() {
+        let message = b"interop".to_vec();
+        for (alg, len) in [(AlgorithmId::A128GCM, 16), (AlgorithmId::A192GCM, 24), (AlgorithmId::A256GCM, 32)].iter() {
+            let encrypted = COSEEncrypt0::encrypt(&alg_headers(*alg), message.clone(), &symmetric_cose_key(vec![9u8; *len]), None).unwrap();
+            let parsed = COSEEncrypt0::from_bytes(encrypted.to_bytes()).unwrap();
+            assert_eq!(parsed.decrypt(&symmetric_cose_key(vec![9u8; *len]), None).unwrap(), message);
+            assert!(parsed.decrypt(&symmetric_cose_key(vec![8u8; *len]), None).is_err());
+            assert!(COSEEncrypt0::encrypt(&alg_headers(*alg), message.clone(), &symmetric_cose_key(vec![9u8; *len + 8]), None).is_err());
+        }
+        // not a content encryption algorithm
+        assert!(COSEEncrypt0::encrypt(&alg_headers(AlgorithmId::A128KW), message, &symmetric_cose_key(vec![9u8; 16]), None).is_err());
+    }
+
+    #[test]
+    fn encrypt_key_wrap_and_direct_recipients() {
+        let message = b"to the ops team".to_vec();
+        let a128kw = recipient_key(AlgorithmId::A128KW, vec![1u8; 16], b"ops-1");
+        let a256kw = recipient_key(AlgorithmId::A256KW, vec![2u8; 32], b"ops-2");
+        let (x25519, x25519_private) = x25519_keypair(5);
+        let mut recipients = COSEKeySet::new();
+        recipients.add(&a128kw);
+        recipients.add(&a256kw);
+        recipients.add(&x25519);
+        let encrypted = COSEEncrypt::encrypt(&alg_headers(AlgorithmId::A192GCM), message.clone(), &recipients, None).unwrap();
+        let parsed = COSEEncrypt::from_bytes(encrypted.to_bytes()).unwrap();
+        let layer = parsed.recipients().get(0);
+        assert_eq!(layer.headers().protected().deserialized_headers().keys().len(), 0);
+        assert_eq!(layer.headers().unprotected().algorithm_id(), Some(AlgorithmId::A128KW.into()));
+        // 192-bit content key + 8 bytes of AES key wrap integrity check
+        assert_eq!(layer.ciphertext().unwrap().len(), 32);
+        assert_eq!(parsed.decrypt(&a128kw, None).unwrap(), message);
+        assert_eq!(parsed.decrypt(&a256kw, None).unwrap(), message);
+        assert_eq!(parsed.decrypt(&x25519_private, None).unwrap(), message);
+        assert!(parsed.decrypt(&recipient_key(AlgorithmId::A128KW, vec![3u8; 16], b"ops-1"), None).is_err());
+        // a key wrap key needs its algorithm to know how it's used
+        assert!(parsed.decrypt(&symmetric_cose_key(vec![1u8; 16]), None).is_err());
+
+        let direct = recipient_key(AlgorithmId::Direct, vec![4u8; 16], b"shared");
+        let mut recipients = COSEKeySet::new();
+        recipients.add(&direct);
+        let encrypted = COSEEncrypt::encrypt(&alg_headers(AlgorithmId::A128GCM), message.clone(), &recipients, None).unwrap();
+        assert_eq!(encrypted.recipients().get(0).ciphertext(), Some(vec![]));
+        assert_eq!(encrypted.decrypt(&direct, None).unwrap(), message);
+        // the direct key must fit the content algorithm and be the only recipient
+        assert!(COSEEncrypt::encrypt(&alg_headers(AlgorithmId::A256GCM), message.clone(), &recipients, None).is_err());
+        recipients.add(&a128kw);
+        assert!(COSEEncrypt::encrypt(&alg_headers(AlgorithmId::A128GCM), message, &recipients, None).is_err());
+    }
+
+    #[test]
+    fn hpke_integrated() {
+        let (mut recipient, mut recipient_private) = x25519_keypair(6);
+        recipient.set_key_id(b"hpke".to_vec());
+        recipient_private.set_key_id(b"hpke".to_vec());
+        let (_, other_private) = x25519_keypair(7);
+        let headers = alg_headers(AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305);
+        let message = b"sealed to one".to_vec();
+        let encrypted = COSEEncrypt0::encrypt(&headers, message.clone(), &recipient, Some(vec![1u8])).unwrap();
+        let parsed = COSEEncrypt0::from_bytes(encrypted.to_bytes()).unwrap();
+        assert_eq!(parsed.headers().unprotected().encapsulated_key().unwrap().len(), 32);
+        assert_eq!(parsed.headers().unprotected().key_id(), Some(b"hpke".to_vec()));
+        assert!(parsed.headers().unprotected().init_vector().is_none());
+        assert_eq!(parsed.decrypt(&recipient_private, Some(vec![1u8])).unwrap(), message);
+        assert!(parsed.decrypt(&recipient_private, None).is_err());
+        assert!(parsed.decrypt(&other_private, Some(vec![1u8])).is_err());
+        assert!(parsed.decrypt(&recipient, Some(vec![1u8])).is_err());
+
+        let mut unprotected = HeaderMap::new();
+        unprotected.set_init_vector(vec![0u8; 12]);
+        let with_iv = Headers::new(&headers.protected(), &unprotected);
+        assert!(COSEEncrypt0::encrypt(&with_iv, message, &recipient, None).is_err());
+    }
+
+    #[test]
+    fn hpke_key_encryption() {
+        let (mut hpke_key, mut hpke_private) = x25519_keypair(8);
+        hpke_key.set_algorithm_id(&AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305.into());
+        hpke_private.set_algorithm_id(&AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305.into());
+        let (ecdh_key, ecdh_private) = x25519_keypair(9);
+        let mut recipients = COSEKeySet::new();
+        recipients.add(&hpke_key);
+        recipients.add(&ecdh_key);
+        let message = b"sealed to many".to_vec();
+        let encrypted = COSEEncrypt::encrypt(&alg_headers(AlgorithmId::A256GCM), message.clone(), &recipients, None).unwrap();
+        let parsed = COSEEncrypt::from_bytes(encrypted.to_bytes()).unwrap();
+        let layer = parsed.recipients().get(0);
+        assert_eq!(
+            layer.headers().protected().deserialized_headers().algorithm_id(),
+            Some(AlgorithmId::HPKEX25519SHA256ChaCha20Poly1305.into()));
+        assert_eq!(layer.headers().unprotected().encapsulated_key().unwrap().len(), 32);
+        // 32 byte content key + 16 byte tag
+        assert_eq!(layer.ciphertext().unwrap().len(), 48);
+        assert_eq!(parsed.decrypt(&hpke_private, None).unwrap(), message);
+        assert_eq!(parsed.decrypt(&ecdh_private, None).unwrap(), message);
+        let (_, other_private) = x25519_keypair(10);
+        assert!(parsed.decrypt(&other_private, None).is_err());
+    }
+
+    #[test]
+    fn partial_init_vector() {
+        // RFC 8152 section 3.1 combination
+        assert_eq!(
+            combine_init_vector(vec![0xffu8; 12], vec![0x01, 0x02]).unwrap(),
+            [vec![0xffu8; 10], vec![0xfe, 0xfd]].concat());
+        assert!(combine_init_vector(vec![0u8; 12], vec![0u8; 13]).is_err());
+
+        let mut key = symmetric_cose_key(vec![3u8; 16]);
+        key.set_base_init_vector(hex::decode("89f52f65a1c580933b5261a7").unwrap());
+        let mut generator = PartialIVGenerator::new();
+        let message = b"message on a channel".to_vec();
+        let mut ciphertexts = Vec::new();
+        for _ in 0..3 {
+            let mut unprotected = HeaderMap::new();
+            unprotected.set_partial_init_vector(generator.next_partial_iv().unwrap());
+            let headers = Headers::new(&alg_headers(AlgorithmId::A128GCM).protected(), &unprotected);
+            let encrypted = COSEEncrypt0::encrypt(&headers, message.clone(), &key, None).unwrap();
+            assert!(encrypted.headers().unprotected().init_vector().is_none());
+            let parsed = COSEEncrypt0::from_bytes(encrypted.to_bytes()).unwrap();
+            assert_eq!(parsed.decrypt(&key, None).unwrap(), message);
+            ciphertexts.push(parsed.ciphertext().unwrap());
+        }
+        assert_ne!(ciphertexts[0], ciphertexts[1]);
+        assert_ne!(ciphertexts[1], ciphertexts[2]);
+        assert_eq!(from_bignum(&generator.counter().unwrap()), 3);
+
+        // a partial IV needs a base IV
+        let mut unprotected = HeaderMap::new();
+        unprotected.set_partial_init_vector(vec![0]);
+        let partial_only = Headers::new(&alg_headers(AlgorithmId::A128GCM).protected(), &unprotected);
+        assert!(COSEEncrypt0::encrypt(&partial_only, message.clone(), &symmetric_cose_key(vec![3u8; 16]), None).is_err());
+        // both IV and partial IV (in different buckets) are rejected
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::A128GCM.into());
+        protected.set_init_vector(vec![0u8; 12]);
+        let both = Headers::new(&ProtectedHeaderMap::new(&protected), &unprotected);
+        assert!(COSEEncrypt0::encrypt(&both, message.clone(), &key, None).is_err());
+        let forged = COSEEncrypt0::new(&both, Some(ciphertexts[0].clone()));
+        assert!(forged.decrypt(&key, None).is_err());
+    }
+
+    #[test]
+    fn partial_iv_generator() {
+        let mut generator = PartialIVGenerator::new();
+        assert_eq!(generator.next_partial_iv().unwrap(), vec![0x00]);
+        assert_eq!(generator.next_partial_iv().unwrap(), vec![0x01]);
+        let mut generator = PartialIVGenerator::starting_at(&to_bignum(0x1ff));
+        assert_eq!(generator.next_partial_iv().unwrap(), vec![0x01, 0xff]);
+        assert_eq!(generator.next_partial_iv().unwrap(), vec![0x02, 0x00]);
+        let mut generator = PartialIVGenerator::starting_at(&to_bignum(u64::MAX));
+        assert_eq!(generator.next_partial_iv().unwrap(), vec![0xff; 8]);
+        assert!(generator.counter().is_none());
+        assert!(generator.next_partial_iv().is_err());
+    }
+}
@@ -30,11 +30,13 @@ pub enum DeserializeFailure {
         expected: Key,
     },
     MandatoryFieldMissing(Key),
+    MissingTag,
     NoVariantMatched,
     TagMismatch{
         found: u64,
         expected: u64,
     },
+    UnexpectedTag(u64),
     UnknownKey(Key),
     UnexpectedKeyType(cbor_event::Type),
 }
@@ -83,8 +85,10 @@ impl std::fmt::Display for DeserializeError {
             DeserializeFailure::ExpectedNull => write!(f, "Expected null, found other type"),
             DeserializeFailure::FixedValueMismatch{ found, expected } => write!(f, "Expected fixed value {} found {}", expected, found),
             DeserializeFailure::MandatoryFieldMissing(key) => write!(f, "Mandatory field {} not found", key),
+            DeserializeFailure::MissingTag => write!(f, "Expected a tag, found none"),
             DeserializeFailure::NoVariantMatched => write!(f, "No variant matched"),
             DeserializeFailure::TagMismatch{ found, expected } => write!(f, "Expected tag {}, found {}", expected, found),
+            DeserializeFailure::UnexpectedTag(tag) => write!(f, "Found tag {} where tags are not allowed", tag),
             DeserializeFailure::UnknownKey(key) => write!(f, "Found unexpected key {}", key),
             DeserializeFailure::UnexpectedKeyType(ty) => write!(f, "Found unexpected key of CBOR type {:?}", ty),
         }
@@ -115,6 +119,44 @@ impl From<cbor_event::Error> for DeserializeError {
     }
 }
 
+// Why a message was rejected by the iat/nbf/exp checks. All times are seconds since the unix epoch.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValidityError {
+    Expired{
+        expiration: u64,
+        now: u64,
+    },
+    IssuedInFuture{
+        issued_at: u64,
+        now: u64,
+    },
+    // the label of the header that wasn't a tag 1 unsigned epoch time
+    Malformed(String),
+    MissingExpiration,
+    NotYetValid{
+        not_before: u64,
+        now: u64,
+    },
+}
+
+impl std::fmt::Display for ValidityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidityError::Expired{ expiration, now } => write!(f, "Message expired at {}, now {}", expiration, now),
+            ValidityError::IssuedInFuture{ issued_at, now } => write!(f, "Message issued in the future: issued at {}, now {}", issued_at, now),
+            ValidityError::Malformed(label) => write!(f, "Header \"{}\" is not a tag 1 epoch time", label),
+            ValidityError::MissingExpiration => write!(f, "Message has no expiration"),
+            ValidityError::NotYetValid{ not_before, now } => write!(f, "Message not valid yet: not before {}, now {}", not_before, now),
+        }
+    }
+}
+
+impl From<ValidityError> for JsError {
+    fn from(e: ValidityError) -> JsError {
+        JsError::from_str(&e.to_string())
+    }
+}
+
 // Generic string error that is replaced with JsError on wasm builds but still usable from non-wasm builds
 // since JsError panics when used for non-constants in non-wasm builds even just creating one
 
@@ -150,3 +192,6 @@ impl std::fmt::Display for JsError {
 
 #[cfg(not(all(target_arch = "wasm32", not(target_os = "emscripten"))))]
 impl std::error::Error for DeserializeError {}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "emscripten"))))]
+impl std::error::Error for ValidityError {}
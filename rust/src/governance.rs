@@ -0,0 +1,183 @@
+use super::*;
+
+// CIP-129 header byte: high nibble = governance key type, low nibble = credential type
+const CIP129_KEY_HASH: u8 = 0x02;
+const CIP129_SCRIPT_HASH: u8 = 0x03;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum GovernanceRole {
+    /// Delegated representative
+    DRep,
+    /// Constitutional Committee hot credential
+    CCHot,
+    /// Constitutional Committee cold credential
+    CCCold,
+}
+
+impl GovernanceRole {
+    fn cip129_key_type(&self) -> u8 {
+        match self {
+            GovernanceRole::CCHot => 0x00,
+            GovernanceRole::CCCold => 0x10,
+            GovernanceRole::DRep => 0x20,
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum GovernanceIdFormat {
+    /// 28-byte key hash only (CIP-105). The role is implied by context.
+    CIP105,
+    /// 1 header byte describing the role and credential type followed by the 28-byte hash (CIP-129)
+    CIP129,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct GovernanceCredential {
+    role: GovernanceRole,
+    key_hash: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl GovernanceCredential {
+    /// # Arguments
+    /// * `key_hash` - blake2b224 hash of the governance key
+    pub fn new(role: GovernanceRole, key_hash: Vec<u8>) -> Result<GovernanceCredential, JsError> {
+        if key_hash.len() != 28 {
+            return Err(JsError::from_str(&format!("Governance key hash must be 28 bytes, found {}", key_hash.len())));
+        }
+        Ok(Self {
+            role,
+            key_hash,
+        })
+    }
+
+    pub fn from_public_key(role: GovernanceRole, public_key: Vec<u8>) -> Result<GovernanceCredential, JsError> {
+        if public_key.len() != 32 {
+            return Err(JsError::from_str(&format!("Ed25519 public key must be 32 bytes, found {}", public_key.len())));
+        }
+        Self::new(role, crypto::blake2b224(&public_key).to_vec())
+    }
+
+    pub fn role(&self) -> GovernanceRole {
+        self.role
+    }
+
+    pub fn key_hash(&self) -> Vec<u8> {
+        self.key_hash.clone()
+    }
+
+    /// The governance id bytes as used in the "address" header
+    pub fn to_id_bytes(&self, format: GovernanceIdFormat) -> Vec<u8> {
+        match format {
+            GovernanceIdFormat::CIP105 => self.key_hash.clone(),
+            GovernanceIdFormat::CIP129 => {
+                let mut bytes = vec![self.role.cip129_key_type() | CIP129_KEY_HASH];
+                bytes.extend_from_slice(&self.key_hash);
+                bytes
+            },
+        }
+    }
+
+    /// Parses either a CIP-105 (28 bytes) or a CIP-129 (29 bytes) governance id.
+    /// For CIP-129 ids the role in the header byte must match `role`.
+    /// Script hash credentials are rejected as they can't be the signer of a message.
+    pub fn from_id_bytes(role: GovernanceRole, bytes: Vec<u8>) -> Result<GovernanceCredential, JsError> {
+        match bytes.len() {
+            28 => Self::new(role, bytes),
+            29 => {
+                let header = bytes[0];
+                if header & 0xf0 != role.cip129_key_type() {
+                    return Err(JsError::from_str(&format!("CIP-129 header {:#04x} does not match governance role {:?}", header, role)));
+                }
+                match header & 0x0f {
+                    CIP129_KEY_HASH => Self::new(role, bytes[1..].to_vec()),
+                    CIP129_SCRIPT_HASH => Err(JsError::from_str("Script hash governance credentials can't sign messages")),
+                    other => Err(JsError::from_str(&format!("Unknown CIP-129 credential type: {}", other))),
+                }
+            },
+            other => Err(JsError::from_str(&format!("Governance id must be 28 (CIP-105) or 29 (CIP-129) bytes, found {}", other))),
+        }
+    }
+
+    pub fn matches_public_key(&self, public_key: Vec<u8>) -> bool {
+        crypto::blake2b224(&public_key)[..] == self.key_hash[..]
+    }
+
+    /// Checks that the governance id in the protected "address" header of `cose_sign1` is the
+    /// hash of `public_key`. This does NOT verify the signature itself.
+    /// Returns the credential that was stated in the message if it matched.
+    pub fn verify_signer(cose_sign1: &COSESign1, role: GovernanceRole, public_key: Vec<u8>) -> Result<GovernanceCredential, JsError> {
+        let address = cose_sign1.headers.protected
+            .deserialized_headers()
+            .header(&Label::new_text(String::from("address")))
+            .ok_or_else(|| JsError::from_str("No address header found in protected headers"))?
+            .as_bytes()
+            .ok_or_else(|| JsError::from_str("Address header was not bytes"))?;
+        let credential = Self::from_id_bytes(role, address)?;
+        if !credential.matches_public_key(public_key) {
+            return Err(JsError::from_str("Signing key does not hash to the stated governance credential"));
+        }
+        Ok(credential)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign1_for(builder: &COSESign1Builder) -> COSESign1 {
+        // the signature itself isn't checked here so anything will do
+        builder.build(vec![0u8; 64])
+    }
+
+    #[test]
+    fn drep_cip105() {
+        let pk = vec![3u8; 32];
+        let headers = Headers::new(&ProtectedHeaderMap::new_empty(), &HeaderMap::new());
+        let mut builder = COSESign1Builder::new(&headers, b"vote statement".to_vec(), false);
+        builder.set_drep_key(pk.clone(), GovernanceIdFormat::CIP105).unwrap();
+        let cose_sign1 = sign1_for(&builder);
+        let protected = cose_sign1.headers().protected().deserialized_headers();
+        assert_eq!(protected.algorithm_id(), Some(AlgorithmId::EdDSA.into()));
+        assert_eq!(protected.header(&Label::new_text(String::from("address"))).unwrap().as_bytes().unwrap().len(), 28);
+
+        let credential = GovernanceCredential::verify_signer(&cose_sign1, GovernanceRole::DRep, pk).unwrap();
+        assert_eq!(credential.role(), GovernanceRole::DRep);
+        assert!(GovernanceCredential::verify_signer(&cose_sign1, GovernanceRole::DRep, vec![4u8; 32]).is_err());
+    }
+
+    #[test]
+    fn cc_cip129() {
+        let pk = vec![9u8; 32];
+        let headers = Headers::new(&ProtectedHeaderMap::new_empty(), &HeaderMap::new());
+        let mut builder = COSESign1Builder::new(&headers, b"ratified".to_vec(), false);
+        builder.set_cc_cold_key(pk.clone(), GovernanceIdFormat::CIP129).unwrap();
+        let cose_sign1 = sign1_for(&builder);
+        let address = cose_sign1.headers().protected().deserialized_headers()
+            .header(&Label::new_text(String::from("address"))).unwrap().as_bytes().unwrap();
+        assert_eq!(address[0], 0x12);
+        assert_eq!(address[1..], crypto::blake2b224(&pk));
+
+        assert!(GovernanceCredential::verify_signer(&cose_sign1, GovernanceRole::CCCold, pk.clone()).is_ok());
+        // the CIP-129 header byte pins the role
+        assert!(GovernanceCredential::verify_signer(&cose_sign1, GovernanceRole::CCHot, pk.clone()).is_err());
+        assert!(GovernanceCredential::verify_signer(&cose_sign1, GovernanceRole::DRep, pk).is_err());
+    }
+
+    #[test]
+    fn cip129_id_bytes() {
+        let hash = vec![7u8; 28];
+        let drep = GovernanceCredential::new(GovernanceRole::DRep, hash.clone()).unwrap();
+        assert_eq!(drep.to_id_bytes(GovernanceIdFormat::CIP129)[0], 0x22);
+        let hot = GovernanceCredential::new(GovernanceRole::CCHot, hash.clone()).unwrap();
+        assert_eq!(hot.to_id_bytes(GovernanceIdFormat::CIP129)[0], 0x02);
+        assert_eq!(GovernanceCredential::from_id_bytes(GovernanceRole::CCHot, hot.to_id_bytes(GovernanceIdFormat::CIP129)).unwrap(), hot);
+        let mut script = vec![0x23u8];
+        script.extend_from_slice(&hash);
+        assert!(GovernanceCredential::from_id_bytes(GovernanceRole::DRep, script).is_err());
+    }
+}
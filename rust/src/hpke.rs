@@ -0,0 +1,135 @@
+// HPKE (RFC 9180) base mode with DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and ChaCha20/Poly1305
+
+use super::crypto;
+use super::error::JsError;
+use cryptoxide::hkdf::{hkdf_expand, hkdf_extract};
+use cryptoxide::sha2::Sha256;
+
+const KEM_ID: u16 = 0x0020;
+const KDF_ID: u16 = 0x0001;
+const AEAD_ID: u16 = 0x0003;
+const MODE_BASE: u8 = 0x00;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SHARED_SECRET_LEN: usize = 32;
+
+fn kem_suite_id() -> Vec<u8> {
+    let mut suite_id = b"KEM".to_vec();
+    suite_id.extend_from_slice(&KEM_ID.to_be_bytes());
+    suite_id
+}
+
+fn hpke_suite_id() -> Vec<u8> {
+    let mut suite_id = b"HPKE".to_vec();
+    suite_id.extend_from_slice(&KEM_ID.to_be_bytes());
+    suite_id.extend_from_slice(&KDF_ID.to_be_bytes());
+    suite_id.extend_from_slice(&AEAD_ID.to_be_bytes());
+    suite_id
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> [u8; 32] {
+    let labeled_ikm = [b"HPKE-v1", suite_id, label, ikm].concat();
+    let mut prk = [0u8; 32];
+    hkdf_extract(Sha256::new(), salt, &labeled_ikm, &mut prk);
+    prk
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8], label: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let labeled_info = [&(len as u16).to_be_bytes()[..], b"HPKE-v1", suite_id, label, info].concat();
+    let mut okm = vec![0u8; len];
+    hkdf_expand(Sha256::new(), prk, &labeled_info, &mut okm);
+    okm
+}
+
+fn extract_and_expand(dh: &[u8], kem_context: &[u8]) -> Vec<u8> {
+    let suite_id = kem_suite_id();
+    let eae_prk = labeled_extract(&suite_id, &[], b"eae_prk", dh);
+    labeled_expand(&suite_id, &eae_prk, b"shared_secret", kem_context, SHARED_SECRET_LEN)
+}
+
+// (key, base_nonce) of the base mode key schedule
+fn key_schedule(shared_secret: &[u8], info: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let suite_id = hpke_suite_id();
+    let psk_id_hash = labeled_extract(&suite_id, &[], b"psk_id_hash", &[]);
+    let info_hash = labeled_extract(&suite_id, &[], b"info_hash", info);
+    let key_schedule_context = [&[MODE_BASE][..], &psk_id_hash, &info_hash].concat();
+    let secret = labeled_extract(&suite_id, shared_secret, b"secret", &[]);
+    let key = labeled_expand(&suite_id, &secret, b"key", &key_schedule_context, KEY_LEN);
+    let base_nonce = labeled_expand(&suite_id, &secret, b"base_nonce", &key_schedule_context, NONCE_LEN);
+    (key, base_nonce)
+}
+
+// (shared_secret, enc) for the recipient public key `pk_r` using the ephemeral private key `sk_e`
+fn encap(sk_e: &[u8], pk_r: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let dh = crypto::x25519(sk_e, pk_r)?;
+    let enc = crypto::x25519_public_key(sk_e);
+    let kem_context = [&enc[..], pk_r].concat();
+    Some((extract_and_expand(&dh, &kem_context), enc.to_vec()))
+}
+
+fn decap(enc: &[u8], sk_r: &[u8; 32]) -> Option<Vec<u8>> {
+    let mut base_point = [0u8; 32];
+    base_point[0] = 9;
+    let dh = crypto::x25519_scalar_mult(sk_r, enc)?;
+    let pk_r = crypto::x25519_scalar_mult(sk_r, &base_point)?;
+    let kem_context = [enc, &pk_r[..]].concat();
+    Some(extract_and_expand(&dh, &kem_context))
+}
+
+fn seal_with_ephemeral(sk_e: &[u8], pk_r: &[u8], info: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), JsError> {
+    let (shared_secret, enc) = encap(sk_e, pk_r)
+        .ok_or_else(|| JsError::from_str("Invalid X25519 HPKE recipient public key"))?;
+    let (key, base_nonce) = key_schedule(&shared_secret, info);
+    Ok((enc, crypto::chacha20poly1305_encrypt(&key, &base_nonce, aad, plaintext)))
+}
+
+/// Single-shot base mode SealBase(). Returns (enc, ciphertext) where the ciphertext has the tag appended.
+pub (crate) fn seal_base(pk_r: &[u8], info: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), JsError> {
+    if pk_r.len() != 32 {
+        return Err(JsError::from_str(&format!("X25519 public key must be 32 bytes, found {}", pk_r.len())));
+    }
+    let sk_e = crypto::random_bytes(32)?;
+    seal_with_ephemeral(&sk_e, pk_r, info, aad, plaintext)
+}
+
+/// Single-shot base mode OpenBase() for the recipient with the X25519 scalar `sk_r`.
+/// None if `enc` is invalid or the ciphertext doesn't authenticate.
+pub (crate) fn open_base(enc: &[u8], sk_r: &[u8; 32], info: &[u8], aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    if enc.len() != 32 {
+        return None;
+    }
+    let shared_secret = decap(enc, sk_r)?;
+    let (key, base_nonce) = key_schedule(&shared_secret, info);
+    crypto::chacha20poly1305_decrypt(&key, &base_nonce, aad, ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc9180_a_2_1() {
+        // DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20Poly1305 base mode
+        let info = hex::decode("4f6465206f6e2061204772656369616e2055726e").unwrap();
+        let sk_e = hex::decode("f4ec9b33b792c372c1d2c2063507b684ef925b8c75a42dbcbf57d63ccd381600").unwrap();
+        let sk_r = hex::decode("8057991eef8f1f1af18f4a9491d16a1ce333f695d4db8e38da75975c4478e0fb").unwrap();
+        let pk_r = hex::decode("4310ee97d88cc1f088a5576c77ab0cf5c3ac797f3d95139c6c84b5429c59662a").unwrap();
+        assert_eq!(crypto::x25519_public_key(&sk_r).to_vec(), pk_r);
+        let (shared_secret, enc) = encap(&sk_e, &pk_r).unwrap();
+        assert_eq!(hex::encode(&enc), "1afa08d3dec047a643885163f1180476fa7ddb54c6a8029ea33f95796bf2ac4a");
+        assert_eq!(hex::encode(&shared_secret), "0bbe78490412b4bbea4812666f7916932b828bba79942424abb65244930d69a7");
+        let (key, base_nonce) = key_schedule(&shared_secret, &info);
+        assert_eq!(hex::encode(&key), "ad2744de8e17f4ebba575b3f5f5a8fa1f69c2a07f6e7500bc60ca6e3e3ec1c91");
+        assert_eq!(hex::encode(&base_nonce), "5c4d98150661b848853b547f");
+
+        let sk_r = crypto::x25519_clamp(&sk_r);
+        assert_eq!(decap(&enc, &sk_r).unwrap(), shared_secret);
+        let aad = b"Count-0".to_vec();
+        let plaintext = b"Beauty is truth, truth beauty".to_vec();
+        let (enc, ciphertext) = seal_with_ephemeral(&sk_e, &pk_r, &info, &aad, &plaintext).unwrap();
+        // sequence number 0
+        assert_eq!(hex::encode(&ciphertext), "1c5250d8034ec2b784ba2cfd69dbdb8af406cfe3ff938e131f0def8c8b60b4db21993c62ce81883d2dd1b51a28");
+        assert_eq!(open_base(&enc, &sk_r, &info, &aad, &ciphertext).unwrap(), plaintext);
+        assert!(open_base(&enc, &sk_r, &[], &aad, &ciphertext).is_none());
+    }
+}
@@ -0,0 +1,273 @@
+// HTTP request signing: the method, path, selected headers and a digest of the body are
+// bound to a COSESign1 via its external_aad (RFC 8152 section 4.3) with a detached payload,
+// similar in purpose to HTTP Message Signatures (RFC 9421) but using wallet keys.
+
+use super::*;
+use std::collections::BTreeMap;
+use std::io::Seek;
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CanonicalRequest {
+    method: String,
+    path: String,
+    // lowercase name -> trimmed value. BTreeMap to keep the encoding deterministic
+    headers: BTreeMap<String, String>,
+    body_digest: Vec<u8>,
+}
+
+to_from_bytes!(CanonicalRequest);
+
+#[wasm_bindgen]
+impl CanonicalRequest {
+    /// # Arguments
+    /// * `method` - HTTP method. Case-insensitive.
+    /// * `path` - path and query string exactly as sent e.g. "/v1/orders?limit=10"
+    /// * `body` - the raw request body (empty for no body). Only its SHA-256 digest is kept.
+    pub fn new(method: String, path: String, body: Vec<u8>) -> Result<CanonicalRequest, JsError> {
+        Self::new_with_body_digest(method, path, crypto::sha256(&body).to_vec())
+    }
+
+    /// Same as `new()` but for when the SHA-256 digest of the body was already computed
+    /// e.g. when streaming large bodies.
+    pub fn new_with_body_digest(method: String, path: String, body_digest: Vec<u8>) -> Result<CanonicalRequest, JsError> {
+        let request = Self {
+            method: method.to_ascii_uppercase(),
+            path,
+            headers: BTreeMap::new(),
+            body_digest,
+        };
+        request.validate().map_err(|e| JsError::from_str(&e))?;
+        Ok(request)
+    }
+
+    /// Selects a header to be covered by the signature. Names are case-insensitive and values
+    /// are trimmed. Adding the same header twice joins the values with ", " as HTTP does.
+    pub fn add_header(&mut self, name: String, value: String) {
+        let value = value.trim();
+        self.headers
+            .entry(name.to_ascii_lowercase())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_owned());
+    }
+
+    pub fn method(&self) -> String {
+        self.method.clone()
+    }
+
+    pub fn path(&self) -> String {
+        self.path.clone()
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers.get(&name.to_ascii_lowercase()).cloned()
+    }
+
+    pub fn body_digest(&self) -> Vec<u8> {
+        self.body_digest.clone()
+    }
+
+    /// The bytes to pass to `COSESign1Builder::set_external_aad()`
+    pub fn external_aad(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+
+    /// Builder for signing this request. The payload is the body digest and is detached,
+    /// the canonical request is the external_aad and the names of the covered headers are
+    /// added to the protected headers as "http_headers" so the verifier can tell which were signed.
+    pub fn sign1_builder(&self, headers: &Headers) -> COSESign1Builder {
+        let mut headers = headers.clone();
+        let mut protected = headers.protected.deserialized_headers();
+        let names = self.headers
+            .keys()
+            .map(|name| CBORValue::new_text(name.clone()))
+            .collect::<Vec<CBORValue>>();
+        protected.other_headers.insert(
+            Label::new_text(String::from("http_headers")),
+            CBORValue::new_array(&names.into()));
+        headers.protected = ProtectedHeaderMap::new(&protected);
+        let mut builder = COSESign1Builder::new(&headers, self.body_digest.clone(), true);
+        builder.set_external_aad(self.external_aad());
+        builder
+    }
+
+    /// Verifies a signature made with `sign1_builder()` against this request as received.
+    /// Fails if the signature covers a different set of headers than this request selected.
+    pub fn verify(&self, cose_sign1: &COSESign1, key: &COSEKey) -> Result<bool, JsError> {
+        let signed_names = cose_sign1.headers.protected
+            .deserialized_headers()
+            .header(&Label::new_text(String::from("http_headers")))
+            .and_then(|names| names.as_array())
+            .ok_or_else(|| JsError::from_str("Missing http_headers protected header"))?
+            .values
+            .iter()
+            .map(|name| name.as_text())
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| JsError::from_str("http_headers must only contain header names"))?;
+        let names = self.headers.keys().cloned().collect::<Vec<String>>();
+        if signed_names != names {
+            return Err(JsError::from_str(&format!("Signature covers headers {:?} but request selected {:?}", signed_names, names)));
+        }
+        if cose_sign1.payload.is_some() {
+            return Err(JsError::from_str("Request signatures must have a detached payload"));
+        }
+        cose_sign1.verify(key, Some(self.external_aad()), Some(self.body_digest.clone()))
+    }
+}
+
+impl CanonicalRequest {
+    // the checks on the method, path and body digest done both when constructing and parsing
+    fn validate(&self) -> Result<(), String> {
+        if self.method.is_empty() || !self.method.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(format!("Invalid HTTP method: {:?}", self.method));
+        }
+        if !self.path.starts_with('/') {
+            return Err(format!("Path must start with '/': {:?}", self.path));
+        }
+        if self.body_digest.len() != 32 {
+            return Err(format!("Body digest must be 32 bytes (SHA-256), found {}", self.body_digest.len()));
+        }
+        Ok(())
+    }
+}
+
+impl cbor_event::se::Serialize for CanonicalRequest {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(4))?;
+        serializer.write_text(&self.method)?;
+        serializer.write_text(&self.path)?;
+        serializer.write_map(cbor_event::Len::Len(self.headers.len() as u64))?;
+        for (name, value) in &self.headers {
+            serializer.write_text(name)?;
+            serializer.write_text(value)?;
+        }
+        serializer.write_bytes(&self.body_digest)?;
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for CanonicalRequest {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(4)?;
+            let method = raw.text().map_err(|e| DeserializeError::from(e).annotate("method"))?;
+            let path = raw.text().map_err(|e| DeserializeError::from(e).annotate("path"))?;
+            let headers = (|| -> Result<_, DeserializeError> {
+                let map_len = raw.map()?;
+                let mut headers = BTreeMap::new();
+                while match map_len { cbor_event::Len::Len(n) => headers.len() < n as usize, cbor_event::Len::Indefinite => true, } {
+                    if raw.cbor_type()? == cbor_event::Type::Special {
+                        if raw.special()? != cbor_event::Special::Break {
+                            return Err(DeserializeFailure::EndingBreakMissing.into());
+                        }
+                        break;
+                    }
+                    let name = raw.text()?;
+                    let value = raw.text()?;
+                    if headers.insert(name.clone(), value).is_some() {
+                        return Err(DeserializeFailure::DuplicateKey(Key::Str(name)).into());
+                    }
+                }
+                Ok(headers)
+            })().map_err(|e| e.annotate("headers"))?;
+            let body_digest = raw.bytes().map_err(|e| DeserializeError::from(e).annotate("body_digest"))?;
+            match len {
+                cbor_event::Len::Len(_) => (),
+                cbor_event::Len::Indefinite => match raw.special()? {
+                    cbor_event::Special::Break => (),
+                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
+                },
+            }
+            let request = CanonicalRequest {
+                method,
+                path,
+                headers,
+                body_digest,
+            };
+            request.validate().map_err(cbor_event::Error::CustomError)?;
+            Ok(request)
+        })().map_err(|e| e.annotate("CanonicalRequest"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: &[u8]) -> CanonicalRequest {
+        let mut request = CanonicalRequest::new(String::from("post"), String::from("/v1/orders?dry_run=1"), body.to_vec()).unwrap();
+        request.add_header(String::from("Host"), String::from(" api.example.com "));
+        request.add_header(String::from("Content-Type"), String::from("application/json"));
+        request
+    }
+
+    #[test]
+    fn canonical_request_encoding() {
+        let mut req = request(b"{}");
+        assert_eq!(req.method(), "POST");
+        assert_eq!(req.header("HOST"), Some(String::from("api.example.com")));
+        req.add_header(String::from("accept"), String::from("a"));
+        req.add_header(String::from("Accept"), String::from("b"));
+        assert_eq!(req.header("accept"), Some(String::from("a, b")));
+        assert_eq!(CanonicalRequest::from_bytes(req.to_bytes()).unwrap(), req);
+        // header insertion order doesn't matter
+        let mut reordered = CanonicalRequest::new(String::from("POST"), String::from("/v1/orders?dry_run=1"), b"{}".to_vec()).unwrap();
+        reordered.add_header(String::from("accept"), String::from("a, b"));
+        reordered.add_header(String::from("content-type"), String::from("application/json"));
+        reordered.add_header(String::from("host"), String::from("api.example.com"));
+        assert_eq!(reordered.external_aad(), req.external_aad());
+
+        // [method, path, {headers}, digest] parsing checks the same as the constructor
+        let encode = |method: &str, path: &str, digest_len: usize| {
+            let mut bytes = vec![0x84, 0x60 + method.len() as u8];
+            bytes.extend_from_slice(method.as_bytes());
+            bytes.push(0x60 + path.len() as u8);
+            bytes.extend_from_slice(path.as_bytes());
+            bytes.extend_from_slice(&[0xa0, 0x58, digest_len as u8]);
+            bytes.extend_from_slice(&vec![0u8; digest_len]);
+            bytes
+        };
+        assert!(CanonicalRequest::from_bytes(encode("GET", "/", 32)).is_ok());
+        assert!(CanonicalRequest::from_bytes(encode("GET", "/", 31)).is_err());
+        assert!(CanonicalRequest::from_bytes(encode("GET", "x", 32)).is_err());
+        assert!(CanonicalRequest::from_bytes(encode("get", "/", 32)).is_err());
+        assert!(CanonicalRequest::from_bytes(encode("", "/", 32)).is_err());
+        // a special value other than break in the headers is an error, not a panic
+        let mut bytes = vec![0x84, 0x63];
+        bytes.extend_from_slice(b"GET");
+        bytes.extend_from_slice(&[0x61, b'/', 0xbf, 0xf5, 0xff, 0x58, 0x20]);
+        bytes.extend_from_slice(&[0u8; 32]);
+        assert!(CanonicalRequest::from_bytes(bytes).is_err());
+    }
+
+    #[test]
+    fn sign_and_verify_request() {
+        let (sk, pk) = cryptoxide::ed25519::keypair(&[7u8; 32]);
+        let key = EdDSA25519Key::new(pk.to_vec()).build();
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::EdDSA.into());
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+
+        let builder = request(b"{\"qty\":1}").sign1_builder(&headers);
+        let signature = cryptoxide::ed25519::signature(&builder.make_data_to_sign().to_bytes(), &sk);
+        let cose_sign1 = builder.build(signature.to_vec());
+        assert!(cose_sign1.payload().is_none());
+
+        assert!(request(b"{\"qty\":1}").verify(&cose_sign1, &key).unwrap());
+        // tampered body
+        assert!(!request(b"{\"qty\":9}").verify(&cose_sign1, &key).unwrap());
+        // tampered header value
+        let mut other_host = request(b"{\"qty\":1}");
+        other_host.headers.insert(String::from("host"), String::from("evil.example.com"));
+        assert!(!other_host.verify(&cose_sign1, &key).unwrap());
+        // different header selection
+        let mut extra_header = request(b"{\"qty\":1}");
+        extra_header.add_header(String::from("date"), String::from("Tue, 20 Oct 2026 10:00:00 GMT"));
+        assert!(extra_header.verify(&cose_sign1, &key).is_err());
+    }
+}
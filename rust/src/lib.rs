@@ -15,14 +15,29 @@ use cbor_event::{self, de::Deserializer, se::{Serialize, Serializer}};
 pub mod builders;
 pub mod cbor;
 mod crypto;
+pub mod cwt;
+pub mod derivation;
+pub mod encryption;
 pub mod error;
+pub mod governance;
+mod hpke;
+pub mod http;
+pub mod login;
+pub mod mac;
+pub mod nested;
+pub mod sd_cwt;
 mod serialization;
+pub mod stream;
 #[macro_use]
 pub mod utils;
 
 use builders::*;
 use cbor::*;
+use cwt::*;
+use derivation::*;
 use error::*;
+use governance::*;
+use sd_cwt::*;
 use utils::*;
 
 #[wasm_bindgen]
@@ -203,6 +218,17 @@ impl CounterSignature {
     }
 }
 
+// key derivation header parameters - RFC 8152 section 11.1 and 11.2
+const KDF_SALT: i32 = -20;
+const KDF_PARTY_U_IDENTITY: i32 = -21;
+const KDF_PARTY_U_NONCE: i32 = -22;
+const KDF_PARTY_U_OTHER: i32 = -23;
+const KDF_PARTY_V_IDENTITY: i32 = -24;
+const KDF_PARTY_V_NONCE: i32 = -25;
+const KDF_PARTY_V_OTHER: i32 = -26;
+// HPKE encapsulated key (ek) - draft-ietf-cose-hpke
+const HPKE_ENCAPSULATED_KEY: i32 = -4;
+
 #[wasm_bindgen]
 #[derive(Clone, Debug)]
 pub struct HeaderMap {
@@ -285,6 +311,119 @@ impl HeaderMap {
         self.counter_signature.as_ref().map(|sig| sig.deref().clone())
     }
 
+    /// CIP-1852 path of the key that signed this message. Stored under the "derivation_path" label.
+    pub fn set_derivation_path(&mut self, derivation_path: &DerivationPath) {
+        self.other_headers.insert(Label::new_text(String::from("derivation_path")), derivation_path.to_value());
+    }
+
+    pub fn derivation_path(&self) -> Option<DerivationPath> {
+        self.other_headers
+            .get(&Label::new_text(String::from("derivation_path")))
+            .and_then(|value| DerivationPath::from_value(value).ok())
+    }
+
+    /// Seconds since the unix epoch at which the message was signed.
+    /// Stored as a tag 1 (epoch-based date/time) under the "iat" label.
+    pub fn set_issued_at(&mut self, issued_at: &BigNum) {
+        self.set_epoch_time("iat", issued_at);
+    }
+
+    pub fn issued_at(&self) -> Option<BigNum> {
+        self.epoch_time("iat").ok().flatten().map(to_bignum)
+    }
+
+    /// Seconds since the unix epoch before which the message must not be accepted.
+    /// Stored as a tag 1 (epoch-based date/time) under the "nbf" label.
+    pub fn set_not_before(&mut self, not_before: &BigNum) {
+        self.set_epoch_time("nbf", not_before);
+    }
+
+    pub fn not_before(&self) -> Option<BigNum> {
+        self.epoch_time("nbf").ok().flatten().map(to_bignum)
+    }
+
+    /// Seconds since the unix epoch from which on the message must not be accepted.
+    /// Stored as a tag 1 (epoch-based date/time) under the "exp" label.
+    pub fn set_expiration(&mut self, expiration: &BigNum) {
+        self.set_epoch_time("exp", expiration);
+    }
+
+    pub fn expiration(&self) -> Option<BigNum> {
+        self.epoch_time("exp").ok().flatten().map(to_bignum)
+    }
+
+    /// Salt for the key derivation (-20) - RFC 8152 section 11.1
+    pub fn set_salt(&mut self, salt: Vec<u8>) {
+        self.set_bytes_header(KDF_SALT, salt);
+    }
+
+    pub fn salt(&self) -> Option<Vec<u8>> {
+        self.bytes_header(KDF_SALT)
+    }
+
+    /// PartyU identity (-21) of the COSE_KDF_Context - RFC 8152 section 11.2
+    pub fn set_party_u_identity(&mut self, identity: Vec<u8>) {
+        self.set_bytes_header(KDF_PARTY_U_IDENTITY, identity);
+    }
+
+    pub fn party_u_identity(&self) -> Option<Vec<u8>> {
+        self.bytes_header(KDF_PARTY_U_IDENTITY)
+    }
+
+    /// PartyU nonce (-22) of the COSE_KDF_Context. Must be bytes or an integer.
+    pub fn set_party_u_nonce(&mut self, nonce: &CBORValue) -> Result<(), JsError> {
+        self.set_nonce_header(KDF_PARTY_U_NONCE, nonce)
+    }
+
+    pub fn party_u_nonce(&self) -> Option<CBORValue> {
+        self.other_headers.get(&Label::new_int(&Int::new_i32(KDF_PARTY_U_NONCE))).cloned()
+    }
+
+    /// PartyU other (-23) of the COSE_KDF_Context
+    pub fn set_party_u_other(&mut self, other: Vec<u8>) {
+        self.set_bytes_header(KDF_PARTY_U_OTHER, other);
+    }
+
+    pub fn party_u_other(&self) -> Option<Vec<u8>> {
+        self.bytes_header(KDF_PARTY_U_OTHER)
+    }
+
+    /// PartyV identity (-24) of the COSE_KDF_Context
+    pub fn set_party_v_identity(&mut self, identity: Vec<u8>) {
+        self.set_bytes_header(KDF_PARTY_V_IDENTITY, identity);
+    }
+
+    pub fn party_v_identity(&self) -> Option<Vec<u8>> {
+        self.bytes_header(KDF_PARTY_V_IDENTITY)
+    }
+
+    /// PartyV nonce (-25) of the COSE_KDF_Context. Must be bytes or an integer.
+    pub fn set_party_v_nonce(&mut self, nonce: &CBORValue) -> Result<(), JsError> {
+        self.set_nonce_header(KDF_PARTY_V_NONCE, nonce)
+    }
+
+    pub fn party_v_nonce(&self) -> Option<CBORValue> {
+        self.other_headers.get(&Label::new_int(&Int::new_i32(KDF_PARTY_V_NONCE))).cloned()
+    }
+
+    /// PartyV other (-26) of the COSE_KDF_Context
+    pub fn set_party_v_other(&mut self, other: Vec<u8>) {
+        self.set_bytes_header(KDF_PARTY_V_OTHER, other);
+    }
+
+    pub fn party_v_other(&self) -> Option<Vec<u8>> {
+        self.bytes_header(KDF_PARTY_V_OTHER)
+    }
+
+    /// HPKE encapsulated key (-4) of the COSE-HPKE sender
+    pub fn set_encapsulated_key(&mut self, encapsulated_key: Vec<u8>) {
+        self.set_bytes_header(HPKE_ENCAPSULATED_KEY, encapsulated_key);
+    }
+
+    pub fn encapsulated_key(&self) -> Option<Vec<u8>> {
+        self.bytes_header(HPKE_ENCAPSULATED_KEY)
+    }
+
     pub fn header(&self, label: &Label) -> Option<CBORValue> {
         match label.0 {
             LabelEnum::Int(Int(1)) => self.algorithm_id.as_ref().map(label_to_value),
@@ -379,6 +518,69 @@ impl HeaderMap {
     }
 }
 
+impl HeaderMap {
+    fn set_bytes_header(&mut self, label: i32, bytes: Vec<u8>) {
+        self.other_headers.insert(Label::new_int(&Int::new_i32(label)), CBORValue::new_bytes(bytes));
+    }
+
+    fn bytes_header(&self, label: i32) -> Option<Vec<u8>> {
+        self.other_headers.get(&Label::new_int(&Int::new_i32(label))).and_then(|value| value.as_bytes())
+    }
+
+    fn set_nonce_header(&mut self, label: i32, nonce: &CBORValue) -> Result<(), JsError> {
+        check_party_nonce(nonce)?;
+        self.other_headers.insert(Label::new_int(&Int::new_i32(label)), nonce.clone());
+        Ok(())
+    }
+
+    fn set_epoch_time(&mut self, label: &str, time: &BigNum) {
+        let value = CBORValue::new_int(&Int::new(time));
+        self.other_headers.insert(Label::new_text(String::from(label)), CBORValue::new_tagged(&TaggedCBOR::new(&to_bignum(1), &value)));
+    }
+
+    fn epoch_time(&self, label: &str) -> Result<Option<u64>, ValidityError> {
+        let value = match self.other_headers.get(&Label::new_text(String::from(label))) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        value.as_tagged()
+            .filter(|tagged| from_bignum(&tagged.tag()) == 1)
+            .and_then(|tagged| tagged.value().as_int())
+            .and_then(|time| time.as_positive())
+            .map(|time| Some(from_bignum(&time)))
+            .ok_or_else(|| ValidityError::Malformed(String::from(label)))
+    }
+
+    /// Checks the "iat", "nbf" and "exp" headers against `now` (seconds since the unix epoch)
+    /// allowing for `clock_skew` seconds of difference between the signer's clock and ours.
+    /// The expiration is mandatory, the other two are only checked when present.
+    pub fn check_validity(&self, now: u64, clock_skew: u64) -> Result<(), ValidityError> {
+        let issued_at = self.epoch_time("iat")?;
+        let not_before = self.epoch_time("nbf")?;
+        let expiration = self.epoch_time("exp")?;
+        check_times(issued_at, not_before, expiration, now, clock_skew)
+    }
+}
+
+// shared by the validity headers and CWT claims. The expiration is mandatory.
+pub (crate) fn check_times(issued_at: Option<u64>, not_before: Option<u64>, expiration: Option<u64>, now: u64, clock_skew: u64) -> Result<(), ValidityError> {
+    let expiration = expiration.ok_or(ValidityError::MissingExpiration)?;
+    if let Some(issued_at) = issued_at {
+        if issued_at > now.saturating_add(clock_skew) {
+            return Err(ValidityError::IssuedInFuture { issued_at, now });
+        }
+    }
+    if let Some(not_before) = not_before {
+        if not_before > now.saturating_add(clock_skew) {
+            return Err(ValidityError::NotYetValid { not_before, now });
+        }
+    }
+    if now >= expiration.saturating_add(clock_skew) {
+        return Err(ValidityError::Expired { expiration, now });
+    }
+    Ok(())
+}
+
 #[wasm_bindgen]
 #[derive(Clone, Debug)]
 pub struct Headers {
@@ -441,7 +643,7 @@ pub struct COSESign1 {
     signature: Vec<u8>,
 }
 
-to_from_bytes!(COSESign1);
+tagged_to_from_bytes!(COSESign1);
 
 #[wasm_bindgen]
 impl COSESign1 {
@@ -472,6 +674,26 @@ impl COSESign1 {
             payload))
     }
 
+    /// Verifies the signature against the reconstructed SigStructure.
+    /// Only EdDSA (Ed25519) keys are supported. See `signed_data()` for the arguments.
+    pub fn verify(&self, key: &COSEKey, external_aad: Option<Vec<u8>>, external_payload: Option<Vec<u8>>) -> Result<bool, JsError> {
+        use std::convert::TryFrom;
+        let key = EdDSA25519Key::from_cose_key(key)?;
+        if let Some(alg) = self.headers.protected.deserialized_headers().algorithm_id() {
+            if !matches!(AlgorithmId::try_from(alg.clone()), Ok(AlgorithmId::EdDSA)) {
+                return Err(JsError::from_str(&format!("Unsupported signature algorithm: {:?}", alg)));
+            }
+        }
+        let data = self.signed_data(external_aad, external_payload)?.to_bytes();
+        Ok(crypto::ed25519_verify(&data, &key.public_key(), &self.signature))
+    }
+
+    /// Checks the issued-at, not-before and expiration protected headers against `now`.
+    /// See `HeaderMap::check_validity()` for the details.
+    pub fn validate_time(&self, now: &BigNum, clock_skew: &BigNum) -> Result<(), JsError> {
+        Ok(self.check_validity(from_bignum(now), from_bignum(clock_skew))?)
+    }
+
     pub fn new(headers: &Headers, payload: Option<Vec<u8>>, signature: Vec<u8>) -> Self {
         Self {
             headers: headers.clone(),
@@ -481,6 +703,12 @@ impl COSESign1 {
     }
 }
 
+impl COSESign1 {
+    pub fn check_validity(&self, now: u64, clock_skew: u64) -> Result<(), ValidityError> {
+        self.headers.protected.deserialized_headers().check_validity(now, clock_skew)
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Clone, Debug)]
 pub struct COSESign {
@@ -489,7 +717,7 @@ pub struct COSESign {
     signatures: COSESignatures,
 }
 
-to_from_bytes!(COSESign);
+tagged_to_from_bytes!(COSESign);
 
 #[wasm_bindgen]
 impl COSESign {
@@ -505,6 +733,38 @@ impl COSESign {
         self.signatures.clone()
     }
 
+    /// Whether any of the signatures verifies with `key`. Only EdDSA (Ed25519) keys are supported.
+    /// # Arguments
+    /// * `external_aad` - External application data - see RFC 8152 section 4.3. Set to None if not using this.
+    /// * `external_payload` - the payload if it is detached, otherwise None
+    pub fn verify(&self, key: &COSEKey, external_aad: Option<Vec<u8>>, external_payload: Option<Vec<u8>>) -> Result<bool, JsError> {
+        use std::convert::TryFrom;
+        let key = EdDSA25519Key::from_cose_key(key)?;
+        let payload = match external_payload {
+            Some(p) => p,
+            None => self.payload.clone().ok_or_else(|| JsError::from_str("Payload was not present but no external payload supplied"))?,
+        };
+        Ok(self.signatures.0.iter().any(|signature| {
+            let sign_protected = signature.headers.protected.deserialized_headers();
+            let is_eddsa = sign_protected.algorithm_id()
+                .map(|alg| matches!(AlgorithmId::try_from(alg), Ok(AlgorithmId::EdDSA)))
+                .unwrap_or(true);
+            let mut data = SigStructure::new(
+                SigContext::Signature,
+                &self.headers.protected,
+                external_aad.clone().unwrap_or_default(),
+                payload.clone());
+            data.set_sign_protected(&signature.headers.protected);
+            is_eddsa && crypto::ed25519_verify(&data.to_bytes(), &key.public_key(), &signature.signature)
+        }))
+    }
+
+    /// Checks the issued-at, not-before and expiration protected headers of the body against `now`.
+    /// See `HeaderMap::check_validity()` for the details.
+    pub fn validate_time(&self, now: &BigNum, clock_skew: &BigNum) -> Result<(), JsError> {
+        Ok(self.check_validity(from_bignum(now), from_bignum(clock_skew))?)
+    }
+
     pub fn new(headers: &Headers, payload: Option<Vec<u8>>, signatures: &COSESignatures) -> Self {
         Self {
             headers: headers.clone(),
@@ -514,6 +774,12 @@ impl COSESign {
     }
 }
 
+impl COSESign {
+    pub fn check_validity(&self, now: u64, clock_skew: u64) -> Result<(), ValidityError> {
+        self.headers.protected.deserialized_headers().check_validity(now, clock_skew)
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum SignedMessageKind {
@@ -531,7 +797,7 @@ enum SignedMessageEnum {
 #[derive(Clone, Debug)]
 pub struct SignedMessage(SignedMessageEnum);
 
-to_from_bytes!(SignedMessage);
+tagged_to_from_bytes!(SignedMessage);
 
 #[wasm_bindgen]
 impl SignedMessage {
@@ -543,43 +809,15 @@ impl SignedMessage {
         Self(SignedMessageEnum::COSESIGN1(cose_sign1.clone()))
     }
 
+    /// "cms_" followed by the base64url CBOR and its checksum. Encrypted and MAC'd messages have
+    /// their own prefixes (see `COSEMessage::to_user_facing_encoding()`) so they are rejected here.
     pub fn from_user_facing_encoding(s: &str) -> Result<SignedMessage, JsError> {
-        use std::io::Cursor;
-        use byteorder::{BigEndian, ReadBytesExt};
-
-        if !s.starts_with("cms_") {
-            return Err(JsError::from_str("SignedMessage user facing encoding must start with \"cms_\""));
-        }
-        let without_prefix = &s[4..];
-        // we need to (potentialy) strip the padding, if it exists on the checksum, in order to
-        // figure out which parts of the string are from the base64url of the checksum as this
-        // could be either 6 (no padding) or 8 (padding) to get the 4 bytes in the checksum
-        let without_checksum_padding = without_prefix.trim_end_matches('=');
-        // 6 for checksum base64url (4 bytes) + at least 2 for body base64url (at least 1 byte)
-        if without_checksum_padding.len() < 8 {
-            return Err(JsError::from_str("insufficient length - missing checksum"));
-        }
-        let (body_base64, checksum_base64) = without_checksum_padding.split_at(without_checksum_padding.len() - 6);
-        let body_bytes = base64_url::decode(body_base64)
-            .map_err(|e| JsError::from_str(&format!("Could not decode body from base64url: {:?}", e)))?;
-        let checksum_bytes = base64_url::decode(checksum_base64)
-            .map_err(|e| JsError::from_str(&format!("Could not decode checksum from base64url: {:?}", e)))?;
-        let expected_checksum = Cursor::new(checksum_bytes).read_u32::<BigEndian>().unwrap();
-        let computed_checksum = crypto::fnv32a(&body_bytes);
-        if expected_checksum != computed_checksum {
-            return Err(JsError::from_str(&format!("checksum does not match body. shown: {}, computed from body: {}", expected_checksum, computed_checksum)));
-        }
+        let body_bytes = decode_user_facing_encoding(s, SIGNED_MESSAGE_PREFIX, "SignedMessage")?;
         Self::from_bytes(body_bytes).map_err(|e| JsError::from_str(&format!("Invalid body: {:?}", e)))
     }
 
     pub fn to_user_facing_encoding(&self) -> String {
-        use byteorder::{BigEndian, WriteBytesExt};
-
-        let body_bytes = self.to_bytes();
-        let checksum = crypto::fnv32a(&body_bytes);
-        let mut checksum_bytes = vec![];
-        checksum_bytes.write_u32::<BigEndian>(checksum).unwrap();
-        format!("cms_{}{}", base64_url::encode(&body_bytes), base64_url::encode(&checksum_bytes))
+        encode_user_facing_encoding(SIGNED_MESSAGE_PREFIX, &self.to_bytes())
     }
 
     pub fn kind(&self) -> SignedMessageKind {
@@ -604,6 +842,230 @@ impl SignedMessage {
     }
 }
 
+// prefix + base64url(body) + base64url(4-byte big-endian FNV-1a 32 checksum of the body)
+fn encode_user_facing_encoding(prefix: &str, body_bytes: &[u8]) -> String {
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    let checksum = crypto::fnv32a(body_bytes);
+    let mut checksum_bytes = vec![];
+    checksum_bytes.write_u32::<BigEndian>(checksum).unwrap();
+    format!("{}{}{}", prefix, base64_url::encode(body_bytes), base64_url::encode(&checksum_bytes))
+}
+
+// the body bytes of a user facing encoding after checking the checksum. `name` is for error messages.
+fn decode_user_facing_encoding(s: &str, prefix: &str, name: &str) -> Result<Vec<u8>, JsError> {
+    use std::io::Cursor;
+    use byteorder::{BigEndian, ReadBytesExt};
+
+    if !s.starts_with(prefix) {
+        return Err(JsError::from_str(&format!("{} user facing encoding must start with \"{}\"", name, prefix)));
+    }
+    let without_prefix = &s[prefix.len()..];
+    // we need to (potentialy) strip the padding, if it exists on the checksum, in order to
+    // figure out which parts of the string are from the base64url of the checksum as this
+    // could be either 6 (no padding) or 8 (padding) to get the 4 bytes in the checksum
+    let without_checksum_padding = without_prefix.trim_end_matches('=');
+    // 6 for checksum base64url (4 bytes) + at least 2 for body base64url (at least 1 byte)
+    if without_checksum_padding.len() < 8 {
+        return Err(JsError::from_str("insufficient length - missing checksum"));
+    }
+    let (body_base64, checksum_base64) = without_checksum_padding.split_at(without_checksum_padding.len() - 6);
+    let body_bytes = base64_url::decode(body_base64)
+        .map_err(|e| JsError::from_str(&format!("Could not decode body from base64url: {:?}", e)))?;
+    let checksum_bytes = base64_url::decode(checksum_base64)
+        .map_err(|e| JsError::from_str(&format!("Could not decode checksum from base64url: {:?}", e)))?;
+    let expected_checksum = Cursor::new(checksum_bytes).read_u32::<BigEndian>().unwrap();
+    let computed_checksum = crypto::fnv32a(&body_bytes);
+    if expected_checksum != computed_checksum {
+        return Err(JsError::from_str(&format!("checksum does not match body. shown: {}, computed from body: {}", expected_checksum, computed_checksum)));
+    }
+    Ok(body_bytes)
+}
+
+// user facing encoding prefixes of signed, encrypted and MAC'd messages
+const SIGNED_MESSAGE_PREFIX: &str = "cms_";
+const ENCRYPTED_MESSAGE_PREFIX: &str = "cme_";
+const MACED_MESSAGE_PREFIX: &str = "cmm_";
+
+/// CBOR tags of the COSE messages - RFC 8152 section 2
+pub const COSE_SIGN_TAG: u64 = 98;
+pub const COSE_SIGN1_TAG: u64 = 18;
+pub const COSE_ENCRYPT_TAG: u64 = 96;
+pub const COSE_ENCRYPT0_TAG: u64 = 16;
+pub const COSE_MAC_TAG: u64 = 97;
+pub const COSE_MAC0_TAG: u64 = 17;
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum COSEMessageKind {
+    COSESIGN,
+    COSESIGN1,
+    COSEENCRYPT,
+    COSEENCRYPT0,
+    COSEMAC,
+    COSEMAC0,
+}
+
+#[derive(Clone, Debug)]
+enum COSEMessageEnum {
+    Sign(COSESign),
+    Sign1(COSESign1),
+    Encrypt(COSEEncrypt),
+    Encrypt0(COSEEncrypt0),
+    Mac(COSEMac),
+    Mac0(COSEMac0),
+}
+
+/// Any of the COSE message structures. When parsing the type is taken from the CBOR tag if there is
+/// one, otherwise from the structure: COSE_Sign1 and COSE_Mac0, and COSE_Sign and COSE_Encrypt, can have
+/// the same shape so those are told apart by the algorithm in the protected headers.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEMessage(COSEMessageEnum);
+
+tagged_to_from_bytes!(COSEMessage);
+
+#[wasm_bindgen]
+impl COSEMessage {
+    pub fn new_cose_sign(cose_sign: &COSESign) -> Self {
+        Self(COSEMessageEnum::Sign(cose_sign.clone()))
+    }
+
+    pub fn new_cose_sign1(cose_sign1: &COSESign1) -> Self {
+        Self(COSEMessageEnum::Sign1(cose_sign1.clone()))
+    }
+
+    pub fn new_cose_encrypt(cose_encrypt: &COSEEncrypt) -> Self {
+        Self(COSEMessageEnum::Encrypt(cose_encrypt.clone()))
+    }
+
+    pub fn new_cose_encrypt0(cose_encrypt0: &COSEEncrypt0) -> Self {
+        Self(COSEMessageEnum::Encrypt0(cose_encrypt0.clone()))
+    }
+
+    pub fn new_cose_mac(cose_mac: &COSEMac) -> Self {
+        Self(COSEMessageEnum::Mac(cose_mac.clone()))
+    }
+
+    pub fn new_cose_mac0(cose_mac0: &COSEMac0) -> Self {
+        Self(COSEMessageEnum::Mac0(cose_mac0.clone()))
+    }
+
+    pub fn from_signed_message(signed_message: &SignedMessage) -> Self {
+        match &signed_message.0 {
+            SignedMessageEnum::COSESIGN(x) => Self::new_cose_sign(x),
+            SignedMessageEnum::COSESIGN1(x) => Self::new_cose_sign1(x),
+        }
+    }
+
+    /// Signed messages use the "cms_" prefix of `SignedMessage` so they can be read by either.
+    /// Encrypted messages use "cme_" and MAC'd messages "cmm_", and the prefix must match the message.
+    pub fn from_user_facing_encoding(s: &str) -> Result<COSEMessage, JsError> {
+        let prefix = [SIGNED_MESSAGE_PREFIX, ENCRYPTED_MESSAGE_PREFIX, MACED_MESSAGE_PREFIX]
+            .iter()
+            .find(|prefix| s.starts_with(*prefix))
+            .ok_or_else(|| JsError::from_str(&format!(
+                "COSEMessage user facing encoding must start with \"{}\", \"{}\" or \"{}\"",
+                SIGNED_MESSAGE_PREFIX,
+                ENCRYPTED_MESSAGE_PREFIX,
+                MACED_MESSAGE_PREFIX)))?;
+        let body_bytes = decode_user_facing_encoding(s, prefix, "COSEMessage")?;
+        let message = Self::from_bytes(body_bytes).map_err(|e| JsError::from_str(&format!("Invalid body: {:?}", e)))?;
+        if message.user_facing_prefix() != *prefix {
+            return Err(JsError::from_str(&format!("{:?} must use the \"{}\" prefix, found \"{}\"", message.kind(), message.user_facing_prefix(), prefix)));
+        }
+        Ok(message)
+    }
+
+    pub fn to_user_facing_encoding(&self) -> String {
+        encode_user_facing_encoding(self.user_facing_prefix(), &self.to_bytes())
+    }
+
+    pub fn kind(&self) -> COSEMessageKind {
+        match &self.0 {
+            COSEMessageEnum::Sign(_) => COSEMessageKind::COSESIGN,
+            COSEMessageEnum::Sign1(_) => COSEMessageKind::COSESIGN1,
+            COSEMessageEnum::Encrypt(_) => COSEMessageKind::COSEENCRYPT,
+            COSEMessageEnum::Encrypt0(_) => COSEMessageKind::COSEENCRYPT0,
+            COSEMessageEnum::Mac(_) => COSEMessageKind::COSEMAC,
+            COSEMessageEnum::Mac0(_) => COSEMessageKind::COSEMAC0,
+        }
+    }
+
+    /// The body headers of whichever message this is
+    pub fn headers(&self) -> Headers {
+        match &self.0 {
+            COSEMessageEnum::Sign(x) => x.headers(),
+            COSEMessageEnum::Sign1(x) => x.headers(),
+            COSEMessageEnum::Encrypt(x) => x.headers(),
+            COSEMessageEnum::Encrypt0(x) => x.headers(),
+            COSEMessageEnum::Mac(x) => x.headers(),
+            COSEMessageEnum::Mac0(x) => x.headers(),
+        }
+    }
+
+    /// The COSE_Sign / COSE_Sign1 as a SignedMessage
+    pub fn as_signed_message(&self) -> Option<SignedMessage> {
+        match &self.0 {
+            COSEMessageEnum::Sign(x) => Some(SignedMessage::new_cose_sign(x)),
+            COSEMessageEnum::Sign1(x) => Some(SignedMessage::new_cose_sign1(x)),
+            _ => None,
+        }
+    }
+
+    pub fn as_cose_sign(&self) -> Option<COSESign> {
+        match &self.0 {
+            COSEMessageEnum::Sign(x) => Some(x.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn as_cose_sign1(&self) -> Option<COSESign1> {
+        match &self.0 {
+            COSEMessageEnum::Sign1(x) => Some(x.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn as_cose_encrypt(&self) -> Option<COSEEncrypt> {
+        match &self.0 {
+            COSEMessageEnum::Encrypt(x) => Some(x.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn as_cose_encrypt0(&self) -> Option<COSEEncrypt0> {
+        match &self.0 {
+            COSEMessageEnum::Encrypt0(x) => Some(x.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn as_cose_mac(&self) -> Option<COSEMac> {
+        match &self.0 {
+            COSEMessageEnum::Mac(x) => Some(x.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn as_cose_mac0(&self) -> Option<COSEMac0> {
+        match &self.0 {
+            COSEMessageEnum::Mac0(x) => Some(x.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl COSEMessage {
+    fn user_facing_prefix(&self) -> &'static str {
+        match &self.0 {
+            COSEMessageEnum::Sign(_) | COSEMessageEnum::Sign1(_) => SIGNED_MESSAGE_PREFIX,
+            COSEMessageEnum::Encrypt(_) | COSEMessageEnum::Encrypt0(_) => ENCRYPTED_MESSAGE_PREFIX,
+            COSEMessageEnum::Mac(_) | COSEMessageEnum::Mac0(_) => MACED_MESSAGE_PREFIX,
+        }
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum SigContext {
@@ -663,6 +1125,204 @@ impl SigStructure {
     }
 }
 
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum EncContext {
+    Encrypt,
+    Encrypt0,
+    EncRecipient,
+    MacRecipient,
+    RecRecipient,
+}
+
+// The AEAD additional authenticated data is this structure's to_bytes() serialization
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct EncStructure {
+    context: EncContext,
+    protected: ProtectedHeaderMap,
+    external_aad: Vec<u8>,
+}
+
+to_from_bytes!(EncStructure);
+
+#[wasm_bindgen]
+impl EncStructure {
+    pub fn context(&self) -> EncContext {
+        self.context
+    }
+
+    pub fn protected(&self) -> ProtectedHeaderMap {
+        self.protected.clone()
+    }
+
+    pub fn external_aad(&self) -> Vec<u8> {
+        self.external_aad.clone()
+    }
+
+    pub fn new(context: EncContext, protected: &ProtectedHeaderMap, external_aad: Vec<u8>) -> Self {
+        Self {
+            context,
+            protected: protected.clone(),
+            external_aad,
+        }
+    }
+}
+
+// PartyInfo nonces are either bytes or integers
+fn check_party_nonce(nonce: &CBORValue) -> Result<(), JsError> {
+    match nonce.as_bytes().is_some() || nonce.as_int().is_some() {
+        true => Ok(()),
+        false => Err(JsError::from_str(&format!("Expected bytes or int nonce, found: {:?}", nonce))),
+    }
+}
+
+// PartyUInfo / PartyVInfo of the COSE_KDF_Context - RFC 8152 section 11.2
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct PartyInfo {
+    identity: Option<Vec<u8>>,
+    nonce: Option<CBORValue>,
+    other: Option<Vec<u8>>,
+}
+
+to_from_bytes!(PartyInfo);
+
+#[wasm_bindgen]
+impl PartyInfo {
+    pub fn set_identity(&mut self, identity: Vec<u8>) {
+        self.identity = Some(identity)
+    }
+
+    pub fn identity(&self) -> Option<Vec<u8>> {
+        self.identity.clone()
+    }
+
+    /// Must be bytes or an integer
+    pub fn set_nonce(&mut self, nonce: &CBORValue) -> Result<(), JsError> {
+        check_party_nonce(nonce)?;
+        self.nonce = Some(nonce.clone());
+        Ok(())
+    }
+
+    pub fn nonce(&self) -> Option<CBORValue> {
+        self.nonce.clone()
+    }
+
+    pub fn set_other(&mut self, other: Vec<u8>) {
+        self.other = Some(other)
+    }
+
+    pub fn other(&self) -> Option<Vec<u8>> {
+        self.other.clone()
+    }
+
+    pub fn new() -> Self {
+        Self {
+            identity: None,
+            nonce: None,
+            other: None,
+        }
+    }
+}
+
+impl Default for PartyInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct SuppPubInfo {
+    // in bits
+    key_data_length: u32,
+    protected: ProtectedHeaderMap,
+    other: Option<Vec<u8>>,
+}
+
+to_from_bytes!(SuppPubInfo);
+
+#[wasm_bindgen]
+impl SuppPubInfo {
+    pub fn key_data_length(&self) -> u32 {
+        self.key_data_length
+    }
+
+    pub fn protected(&self) -> ProtectedHeaderMap {
+        self.protected.clone()
+    }
+
+    pub fn set_other(&mut self, other: Vec<u8>) {
+        self.other = Some(other)
+    }
+
+    pub fn other(&self) -> Option<Vec<u8>> {
+        self.other.clone()
+    }
+
+    /// * `key_data_length` - length of the derived key in bits
+    /// * `protected` - protected headers of the layer the key is derived for
+    pub fn new(key_data_length: u32, protected: &ProtectedHeaderMap) -> Self {
+        Self {
+            key_data_length,
+            protected: protected.clone(),
+            other: None,
+        }
+    }
+}
+
+// Key derivation functions take this structure's to_bytes() serialization as their context info
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEKDFContext {
+    algorithm_id: Label,
+    party_u_info: PartyInfo,
+    party_v_info: PartyInfo,
+    supp_pub_info: SuppPubInfo,
+    supp_priv_info: Option<Vec<u8>>,
+}
+
+to_from_bytes!(COSEKDFContext);
+
+#[wasm_bindgen]
+impl COSEKDFContext {
+    pub fn algorithm_id(&self) -> Label {
+        self.algorithm_id.clone()
+    }
+
+    pub fn party_u_info(&self) -> PartyInfo {
+        self.party_u_info.clone()
+    }
+
+    pub fn party_v_info(&self) -> PartyInfo {
+        self.party_v_info.clone()
+    }
+
+    pub fn supp_pub_info(&self) -> SuppPubInfo {
+        self.supp_pub_info.clone()
+    }
+
+    pub fn set_supp_priv_info(&mut self, supp_priv_info: Vec<u8>) {
+        self.supp_priv_info = Some(supp_priv_info)
+    }
+
+    pub fn supp_priv_info(&self) -> Option<Vec<u8>> {
+        self.supp_priv_info.clone()
+    }
+
+    /// * `algorithm_id` - algorithm the derived key will be used for
+    pub fn new(algorithm_id: &Label, party_u_info: &PartyInfo, party_v_info: &PartyInfo, supp_pub_info: &SuppPubInfo) -> Self {
+        Self {
+            algorithm_id: algorithm_id.clone(),
+            party_u_info: party_u_info.clone(),
+            party_v_info: party_v_info.clone(),
+            supp_pub_info: supp_pub_info.clone(),
+            supp_priv_info: None,
+        }
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Clone, Debug)]
 pub struct COSEEncrypt0 {
@@ -670,7 +1330,7 @@ pub struct COSEEncrypt0 {
     ciphertext: Option<Vec<u8>>,
 }
 
-to_from_bytes!(COSEEncrypt0);
+tagged_to_from_bytes!(COSEEncrypt0);
 
 #[wasm_bindgen]
 impl COSEEncrypt0 {
@@ -682,6 +1342,13 @@ impl COSEEncrypt0 {
         self.ciphertext.clone()
     }
 
+    /// The additional authenticated data to check the ciphertext with when decrypting
+    /// # Arguments
+    /// * `external_aad` - External application data - see RFC 8152 section 4.3. Set to None if not using this.
+    pub fn enc_structure(&self, external_aad: Option<Vec<u8>>) -> EncStructure {
+        EncStructure::new(EncContext::Encrypt0, &self.headers.protected, external_aad.unwrap_or_default())
+    }
+
     pub fn new(headers: &Headers, ciphertext: Option<Vec<u8>>) -> Self {
         Self {
             headers: headers.clone(),
@@ -736,7 +1403,7 @@ pub struct COSEEncrypt {
     recipients: COSERecipients,
 }
 
-to_from_bytes!(COSEEncrypt);
+tagged_to_from_bytes!(COSEEncrypt);
 
 #[wasm_bindgen]
 impl COSEEncrypt {
@@ -752,6 +1419,12 @@ impl COSEEncrypt {
         self.recipients.clone()
     }
 
+    /// The additional authenticated data to check the ciphertext with when decrypting.
+    /// See `COSEEncrypt0::enc_structure()` for the arguments.
+    pub fn enc_structure(&self, external_aad: Option<Vec<u8>>) -> EncStructure {
+        EncStructure::new(EncContext::Encrypt, &self.headers.protected, external_aad.unwrap_or_default())
+    }
+
     pub fn new(headers: &Headers, ciphertext: Option<Vec<u8>>, recipients: &COSERecipients) -> Self {
         Self {
             headers: headers.clone(),
@@ -780,6 +1453,12 @@ impl COSERecipient {
         self.ciphertext.clone()
     }
 
+    /// The additional authenticated data for the recipient's (wrapped) key. `context` is
+    /// EncRecipient, MacRecipient or RecRecipient depending on what the recipient's layer is used for.
+    pub fn enc_structure(&self, context: EncContext, external_aad: Option<Vec<u8>>) -> EncStructure {
+        EncStructure::new(context, &self.headers.protected, external_aad.unwrap_or_default())
+    }
+
     pub fn new(headers: &Headers, ciphertext: Option<Vec<u8>>) -> Self {
         Self {
             headers: headers.clone(),
@@ -801,6 +1480,148 @@ impl PubKeyEncryption {
     }
 }
 
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum MacContext {
+    Mac,
+    Mac0,
+}
+
+// The MAC is computed over this structure's to_bytes() serialization
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct MacStructure {
+    context: MacContext,
+    protected: ProtectedHeaderMap,
+    external_aad: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+to_from_bytes!(MacStructure);
+
+#[wasm_bindgen]
+impl MacStructure {
+    pub fn context(&self) -> MacContext {
+        self.context
+    }
+
+    pub fn protected(&self) -> ProtectedHeaderMap {
+        self.protected.clone()
+    }
+
+    pub fn external_aad(&self) -> Vec<u8> {
+        self.external_aad.clone()
+    }
+
+    pub fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+
+    pub fn new(context: MacContext, protected: &ProtectedHeaderMap, external_aad: Vec<u8>, payload: Vec<u8>) -> Self {
+        Self {
+            context,
+            protected: protected.clone(),
+            external_aad,
+            payload,
+        }
+    }
+}
+
+/// COSE_Mac0 (CBOR tag 17): a MAC'd message where the recipient already knows the key
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEMac0 {
+    headers: Headers,
+    payload: Option<Vec<u8>>,
+    tag: Vec<u8>,
+}
+
+tagged_to_from_bytes!(COSEMac0);
+
+#[wasm_bindgen]
+impl COSEMac0 {
+    pub fn headers(&self) -> Headers {
+        self.headers.clone()
+    }
+
+    pub fn payload(&self) -> Option<Vec<u8>> {
+        self.payload.clone()
+    }
+
+    pub fn tag(&self) -> Vec<u8> {
+        self.tag.clone()
+    }
+
+    /// For verifying, the MacStructure is reconstructed to recompute the tag over
+    /// # Arguments
+    /// * `external_aad` - External application data - see RFC 8152 section 4.3. Set to None if not using this.
+    /// * `external_payload` - the payload if it is detached, otherwise None
+    pub fn mac_structure(&self, external_aad: Option<Vec<u8>>, external_payload: Option<Vec<u8>>) -> Result<MacStructure, JsError> {
+        let payload = match external_payload {
+            Some(p) => p,
+            None => self.payload.clone().ok_or_else(|| JsError::from_str("Payload was not present but no external payload supplied"))?,
+        };
+        Ok(MacStructure::new(MacContext::Mac0, &self.headers.protected, external_aad.unwrap_or_default(), payload))
+    }
+
+    pub fn new(headers: &Headers, payload: Option<Vec<u8>>, tag: Vec<u8>) -> Self {
+        Self {
+            headers: headers.clone(),
+            payload,
+            tag,
+        }
+    }
+}
+
+/// COSE_Mac (CBOR tag 97): a MAC'd message with the MAC key distributed to each recipient
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEMac {
+    headers: Headers,
+    payload: Option<Vec<u8>>,
+    tag: Vec<u8>,
+    recipients: COSERecipients,
+}
+
+tagged_to_from_bytes!(COSEMac);
+
+#[wasm_bindgen]
+impl COSEMac {
+    pub fn headers(&self) -> Headers {
+        self.headers.clone()
+    }
+
+    pub fn payload(&self) -> Option<Vec<u8>> {
+        self.payload.clone()
+    }
+
+    pub fn tag(&self) -> Vec<u8> {
+        self.tag.clone()
+    }
+
+    pub fn recipients(&self) -> COSERecipients {
+        self.recipients.clone()
+    }
+
+    /// See `COSEMac0::mac_structure()`
+    pub fn mac_structure(&self, external_aad: Option<Vec<u8>>, external_payload: Option<Vec<u8>>) -> Result<MacStructure, JsError> {
+        let payload = match external_payload {
+            Some(p) => p,
+            None => self.payload.clone().ok_or_else(|| JsError::from_str("Payload was not present but no external payload supplied"))?,
+        };
+        Ok(MacStructure::new(MacContext::Mac, &self.headers.protected, external_aad.unwrap_or_default(), payload))
+    }
+
+    pub fn new(headers: &Headers, payload: Option<Vec<u8>>, tag: Vec<u8>, recipients: &COSERecipients) -> Self {
+        Self {
+            headers: headers.clone(),
+            payload,
+            tag,
+            recipients: recipients.clone(),
+        }
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Clone, Debug)]
 pub struct COSEKey {
@@ -919,6 +1740,79 @@ impl COSEKey {
     }
 }
 
+/// COSE_KeySet - RFC 8152 section 7. The CBOR form must hold at least one key so empty sets, which
+/// the filters can return, are rejected by parsing and `to_user_facing_encoding()` but not by `to_bytes()`.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct COSEKeySet(Vec<COSEKey>);
+
+to_from_bytes!(COSEKeySet);
+
+#[wasm_bindgen]
+impl COSEKeySet {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> COSEKey {
+        self.0[index].clone()
+    }
+
+    pub fn add(&mut self, elem: &COSEKey) {
+        self.0.push(elem.clone());
+    }
+
+    /// The first key with `key_id`
+    pub fn get_by_key_id(&self, key_id: Vec<u8>) -> Option<COSEKey> {
+        self.0.iter().find(|key| key.key_id.as_ref() == Some(&key_id)).cloned()
+    }
+
+    /// The keys that can be used with `algorithm_id` i.e. that have it or no algorithm set
+    pub fn filter_by_algorithm_id(&self, algorithm_id: &Label) -> COSEKeySet {
+        Self(self.0
+            .iter()
+            .filter(|key| !matches!(&key.algorithm_id, Some(alg) if alg != algorithm_id))
+            .cloned()
+            .collect())
+    }
+
+    /// The keys that can be used for `key_op` i.e. that have it in their key_ops or no key_ops set
+    pub fn filter_by_key_op(&self, key_op: &Label) -> COSEKeySet {
+        Self(self.0
+            .iter()
+            .filter(|key| !matches!(&key.key_ops, Some(ops) if !ops.0.contains(key_op)))
+            .cloned()
+            .collect())
+    }
+
+    /// "cks_" followed by the base64url CBOR and its checksum, as with `SignedMessage`'s "cms_" encoding
+    pub fn from_user_facing_encoding(s: &str) -> Result<COSEKeySet, JsError> {
+        let body_bytes = decode_user_facing_encoding(s, "cks_", "COSEKeySet")?;
+        Self::from_bytes(body_bytes).map_err(|e| JsError::from_str(&format!("Invalid body: {:?}", e)))
+    }
+
+    pub fn to_user_facing_encoding(&self) -> Result<String, JsError> {
+        if self.0.is_empty() {
+            return Err(JsError::from_str("COSEKeySet must contain at least one key"));
+        }
+        Ok(encode_user_facing_encoding("cks_", &self.to_bytes()))
+    }
+}
+
+impl Default for COSEKeySet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1003,4 +1897,163 @@ mod tests {
         assert_eq!(pad1.to_bytes(), pad2.to_bytes());
         assert_eq!(pad2.to_bytes(), pad3.to_bytes());
     }
-}
\ No newline at end of file
+
+    fn cose_messages() -> Vec<COSEMessage> {
+        let empty = Headers::new(&ProtectedHeaderMap::new_empty(), &HeaderMap::new());
+        let mut signatures = COSESignatures::new();
+        signatures.add(&COSESignature::new(&encryption::alg_headers(AlgorithmId::EdDSA), vec![0u8; 64]));
+        let mut recipients = COSERecipients::new();
+        recipients.add(&COSERecipient::new(&empty, Some(vec![1u8; 40])));
+        vec![
+            COSEMessage::new_cose_sign(&COSESign::new(&encryption::alg_headers(AlgorithmId::EdDSA), Some(vec![2u8]), &signatures)),
+            COSEMessage::new_cose_sign1(&COSESign1::new(&encryption::alg_headers(AlgorithmId::EdDSA), Some(vec![2u8]), vec![0u8; 64])),
+            COSEMessage::new_cose_encrypt(&COSEEncrypt::new(&encryption::alg_headers(AlgorithmId::A256GCM), Some(vec![3u8; 20]), &recipients)),
+            COSEMessage::new_cose_encrypt0(&COSEEncrypt0::new(&encryption::alg_headers(AlgorithmId::A256GCM), Some(vec![3u8; 20]))),
+            COSEMessage::new_cose_mac(&COSEMac::new(&encryption::alg_headers(AlgorithmId::HMAC256), Some(vec![2u8]), vec![4u8; 32], &recipients)),
+            COSEMessage::new_cose_mac0(&COSEMac0::new(&encryption::alg_headers(AlgorithmId::HMAC256), Some(vec![2u8]), vec![4u8; 32])),
+        ]
+    }
+
+    #[test]
+    fn cose_message_untagged() {
+        let kinds = [
+            COSEMessageKind::COSESIGN,
+            COSEMessageKind::COSESIGN1,
+            COSEMessageKind::COSEENCRYPT,
+            COSEMessageKind::COSEENCRYPT0,
+            COSEMessageKind::COSEMAC,
+            COSEMessageKind::COSEMAC0,
+        ];
+        for (message, kind) in cose_messages().iter().zip(kinds.iter()) {
+            assert_eq!(message.kind(), *kind);
+            let parsed = COSEMessage::from_bytes(message.to_bytes()).unwrap();
+            assert_eq!(parsed.kind(), *kind);
+            assert_eq!(parsed.to_bytes(), message.to_bytes());
+            let from_ufe = COSEMessage::from_user_facing_encoding(&message.to_user_facing_encoding()).unwrap();
+            assert_eq!(from_ufe.to_bytes(), message.to_bytes());
+        }
+        let messages = cose_messages();
+        assert!(messages[0].as_signed_message().is_some());
+        assert!(messages[2].as_signed_message().is_none());
+        assert!(messages[3].as_cose_encrypt0().is_some());
+        assert!(messages[3].as_cose_mac0().is_none());
+        assert!(messages[5].as_cose_mac0().is_some());
+        // signed messages have the same user facing encoding either way
+        let signed = messages[1].as_signed_message().unwrap();
+        let message = COSEMessage::from_user_facing_encoding(&signed.to_user_facing_encoding()).unwrap();
+        assert_eq!(message.kind(), COSEMessageKind::COSESIGN1);
+        assert!(COSEMessage::from_user_facing_encoding("hEChAzkD51gnQEBAQEBAQEBAQEBAQEBAQ").is_err());
+        // but encrypted and MAC'd messages have their own prefixes
+        let encrypted = messages[3].to_user_facing_encoding();
+        assert!(encrypted.starts_with("cme_"));
+        assert!(messages[5].to_user_facing_encoding().starts_with("cmm_"));
+        assert!(SignedMessage::from_user_facing_encoding(&encrypted).is_err());
+        assert!(COSEMessage::from_user_facing_encoding(&format!("cms_{}", &encrypted[4..])).is_err());
+        assert!(COSEMessage::from_user_facing_encoding(&format!("cmm_{}", &encrypted[4..])).is_err());
+    }
+
+    #[test]
+    fn cose_message_tagged() {
+        // a COSE_Sign1 body with an HMAC algorithm is still a COSE_Sign1 if tagged as one
+        let sign1 = COSESign1::new(&encryption::alg_headers(AlgorithmId::HMAC256), Some(vec![2u8]), vec![4u8; 32]);
+        let mut bytes = vec![0xd2];
+        bytes.extend(sign1.to_bytes());
+        assert_eq!(COSEMessage::from_bytes(bytes).unwrap().kind(), COSEMessageKind::COSESIGN1);
+        assert_eq!(COSEMessage::from_bytes(sign1.to_bytes()).unwrap().kind(), COSEMessageKind::COSEMAC0);
+        let mut bytes = vec![0xd1];
+        bytes.extend(sign1.to_bytes());
+        assert_eq!(COSEMessage::from_bytes(bytes).unwrap().kind(), COSEMessageKind::COSEMAC0);
+
+        let encrypt0 = COSEEncrypt0::new(&encryption::alg_headers(AlgorithmId::A256GCM), Some(vec![3u8; 20]));
+        let password = PasswordEncryption::new(&encrypt0);
+        assert_eq!(COSEMessage::from_bytes(password.to_bytes()).unwrap().kind(), COSEMessageKind::COSEENCRYPT0);
+        // the tag must agree with the structure
+        let mut bytes = vec![0xd8, 0x62];
+        bytes.extend(encrypt0.to_bytes());
+        assert!(COSEMessage::from_bytes(bytes).is_err());
+        let mut bytes = vec![0xd8, 0x63];
+        bytes.extend(encrypt0.to_bytes());
+        assert!(COSEMessage::from_bytes(bytes).is_err());
+    }
+
+    #[test]
+    fn cose_key_set() {
+        let mut verifying = builders::EdDSA25519Key::new(vec![1u8; 32]);
+        verifying.is_for_verifying();
+        let mut service_a = verifying.build();
+        service_a.set_key_id(b"service a".to_vec());
+        let mut service_b = builders::EdDSA25519Key::new(vec![2u8; 32]).build();
+        service_b.set_key_id(b"service b".to_vec());
+        let mut mac_key = builders::SymmetricKey::new(vec![3u8; 32]);
+        mac_key.set_algorithm_id(AlgorithmId::HMAC256);
+        mac_key.add_key_op(KeyOperation::MACVerify);
+        let mut keys = COSEKeySet::new();
+        keys.add(&service_a);
+        keys.add(&service_b);
+        keys.add(&mac_key.build().unwrap());
+
+        let keys = COSEKeySet::from_bytes(keys.to_bytes()).unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys.get_by_key_id(b"service b".to_vec()).unwrap().to_bytes(), service_b.to_bytes());
+        assert!(keys.get_by_key_id(b"service c".to_vec()).is_none());
+        assert_eq!(keys.filter_by_algorithm_id(&AlgorithmId::EdDSA.into()).len(), 2);
+        assert_eq!(keys.filter_by_algorithm_id(&AlgorithmId::HMAC256.into()).len(), 1);
+        // service b has no key_ops so can be used for anything
+        let verification_keys = keys.filter_by_key_op(&KeyOperation::Verify.into());
+        assert_eq!(verification_keys.len(), 2);
+        assert_eq!(verification_keys.get(0).key_id(), Some(b"service a".to_vec()));
+        assert_eq!(keys.filter_by_key_op(&KeyOperation::Sign.into()).len(), 1);
+
+        let user_facing_encoding = keys.to_user_facing_encoding().unwrap();
+        assert!(user_facing_encoding.starts_with("cks_"));
+        let from_ufe = COSEKeySet::from_user_facing_encoding(&user_facing_encoding).unwrap();
+        assert_eq!(from_ufe.to_bytes(), keys.to_bytes());
+        let as_message = format!("cms_{}", &user_facing_encoding[4..]);
+        assert!(COSEKeySet::from_user_facing_encoding(&as_message).is_err());
+        // indefinite length
+        let mut indefinite = vec![0x9f];
+        indefinite.extend(service_a.to_bytes());
+        indefinite.push(0xff);
+        assert_eq!(COSEKeySet::from_bytes(indefinite).unwrap().len(), 1);
+        assert!(COSEKeySet::from_bytes(vec![0x9f, 0xf6]).is_err());
+        // COSE_KeySet = [+COSE_Key]
+        assert!(COSEKeySet::from_bytes(vec![0x80]).is_err());
+        assert!(COSEKeySet::from_bytes(vec![0x9f, 0xff]).is_err());
+        let no_keys = keys.filter_by_algorithm_id(&AlgorithmId::A256GCM.into());
+        assert!(no_keys.is_empty() && no_keys.to_user_facing_encoding().is_err());
+    }
+
+    #[test]
+    fn validity_headers() {
+        let mut protected = HeaderMap::new();
+        protected.set_issued_at(&to_bignum(1000));
+        protected.set_not_before(&to_bignum(1100));
+        protected.set_expiration(&to_bignum(2000));
+        // tag 1 epoch times on the wire
+        assert_eq!(
+            protected.header(&label_str("exp")).unwrap().to_bytes(),
+            vec![0xc1, 0x19, 0x07, 0xd0]);
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        let cose_sign1 = COSESign1::from_bytes(COSESign1::new(&headers, None, vec![0u8; 64]).to_bytes()).unwrap();
+        let parsed = cose_sign1.headers().protected().deserialized_headers();
+        assert_eq!(parsed.issued_at(), Some(to_bignum(1000)));
+        assert_eq!(parsed.not_before(), Some(to_bignum(1100)));
+        assert_eq!(parsed.expiration(), Some(to_bignum(2000)));
+
+        assert!(cose_sign1.validate_time(&to_bignum(1500), &to_bignum(0)).is_ok());
+        assert_eq!(cose_sign1.check_validity(1050, 0), Err(ValidityError::NotYetValid { not_before: 1100, now: 1050 }));
+        assert_eq!(cose_sign1.check_validity(1050, 60), Ok(()));
+        assert_eq!(cose_sign1.check_validity(2000, 0), Err(ValidityError::Expired { expiration: 2000, now: 2000 }));
+        assert_eq!(cose_sign1.check_validity(2000, 30), Ok(()));
+        assert_eq!(cose_sign1.check_validity(900, 0), Err(ValidityError::IssuedInFuture { issued_at: 1000, now: 900 }));
+
+        let mut malformed = HeaderMap::new();
+        malformed.set_header(&label_str("exp"), &CBORValue::new_int(&Int::new_i32(2000))).unwrap();
+        assert_eq!(malformed.check_validity(1500, 0), Err(ValidityError::Malformed(String::from("exp"))));
+        assert_eq!(malformed.expiration(), None);
+
+        let headers = Headers::new(&ProtectedHeaderMap::new_empty(), &HeaderMap::new());
+        let cose_sign = COSESign::new(&headers, Some(vec![1u8]), &COSESignatures::new());
+        assert_eq!(cose_sign.check_validity(1500, 0), Err(ValidityError::MissingExpiration));
+    }
+}
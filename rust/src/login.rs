@@ -0,0 +1,341 @@
+// Sign-in-with-Cardano: a wallet login challenge/response on top of CIP-8/CIP-30 signData,
+// in the spirit of CIP-93. The server hands out a LoginChallenge, the wallet signs its payload
+// with the address it wants to log in with and the server verifies the returned DataSignature.
+
+use super::*;
+use std::collections::HashMap;
+
+const STATEMENT: &str = " wants you to sign in with your Cardano account.";
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoginChallenge {
+    domain: String,
+    nonce: Vec<u8>,
+    issued_at: BigNum,
+    expiration: BigNum,
+}
+
+#[wasm_bindgen]
+impl LoginChallenge {
+    /// # Arguments
+    /// * `domain` - the domain (RFC 3986 authority) requesting the login
+    /// * `nonce` - unpredictable value from the server, at least 8 bytes
+    /// * `issued_at` / `expiration` - POSIX times in seconds
+    pub fn new(domain: String, nonce: Vec<u8>, issued_at: &BigNum, expiration: &BigNum) -> Result<LoginChallenge, JsError> {
+        if domain.is_empty() || domain.contains(char::is_whitespace) {
+            return Err(JsError::from_str(&format!("Invalid domain: {:?}", domain)));
+        }
+        if nonce.len() < 8 {
+            return Err(JsError::from_str("Nonce must be at least 8 bytes"));
+        }
+        if expiration <= issued_at {
+            return Err(JsError::from_str("Expiration must be after issued at"));
+        }
+        Ok(Self {
+            domain,
+            nonce,
+            issued_at: *issued_at,
+            expiration: *expiration,
+        })
+    }
+
+    pub fn domain(&self) -> String {
+        self.domain.clone()
+    }
+
+    pub fn nonce(&self) -> Vec<u8> {
+        self.nonce.clone()
+    }
+
+    pub fn issued_at(&self) -> BigNum {
+        self.issued_at
+    }
+
+    pub fn expiration(&self) -> BigNum {
+        self.expiration
+    }
+
+    /// The payload the wallet should sign via CIP-30 signData().
+    /// This is human-readable text as wallets show the payload to the user before signing.
+    pub fn to_payload(&self) -> Vec<u8> {
+        format!(
+            "{}{}\n\nNonce: {}\nIssued At: {}\nExpiration Time: {}",
+            self.domain,
+            STATEMENT,
+            hex::encode(&self.nonce),
+            self.issued_at.to_str(),
+            self.expiration.to_str())
+            .into_bytes()
+    }
+
+    pub fn from_payload(payload: Vec<u8>) -> Result<LoginChallenge, JsError> {
+        let text = String::from_utf8(payload)
+            .map_err(|_| JsError::from_str("Login payload is not valid UTF-8"))?;
+        let mut lines = text.split('\n');
+        let mut next_line = |prefix: &str| -> Result<String, JsError> {
+            let line = lines.next().ok_or_else(|| JsError::from_str("Login payload ended early"))?;
+            line.strip_prefix(prefix)
+                .map(String::from)
+                .ok_or_else(|| JsError::from_str(&format!("Expected \"{}\" in login payload, found: {:?}", prefix, line)))
+        };
+        let domain = next_line("")?
+            .strip_suffix(STATEMENT)
+            .map(String::from)
+            .ok_or_else(|| JsError::from_str("Login payload is missing the sign-in statement"))?;
+        next_line("")?;
+        let nonce = hex::decode(next_line("Nonce: ")?)
+            .map_err(|e| JsError::from_str(&format!("Invalid nonce: {:?}", e)))?;
+        let issued_at = BigNum::from_str(&next_line("Issued At: ")?)?;
+        let expiration = BigNum::from_str(&next_line("Expiration Time: ")?)?;
+        let challenge = Self::new(domain, nonce, &issued_at, &expiration)?;
+        // must round-trip exactly so that nothing extra was signed
+        if challenge.to_payload() != text.as_bytes() {
+            return Err(JsError::from_str("Login payload is not in canonical form"));
+        }
+        Ok(challenge)
+    }
+
+    /// Verifies the CIP-30 DataSignature (COSESign1 + COSEKey) returned by the wallet.
+    /// Checks the signature, that it was for this domain, that the current time is within
+    /// issued at/expiration (with `clock_skew` seconds of leeway) and that the signing key owns
+    /// the address in the protected "address" header.
+    /// Replay protection is NOT done here - see `verify_login()` for that.
+    /// Returns the challenge that was signed so the caller can check its nonce was issued by them.
+    pub fn verify_signature(cose_sign1: &COSESign1, key: &COSEKey, expected_domain: &str, now: &BigNum, clock_skew: &BigNum) -> Result<LoginChallenge, JsError> {
+        Self::verify_response(cose_sign1, key, expected_domain, now, clock_skew).map(|(challenge, _address)| challenge)
+    }
+}
+
+impl LoginChallenge {
+    // returns the signed challenge and the verified address
+    fn verify_response(cose_sign1: &COSESign1, key: &COSEKey, expected_domain: &str, now: &BigNum, clock_skew: &BigNum) -> Result<(LoginChallenge, Vec<u8>), JsError> {
+        let protected = cose_sign1.headers.protected.deserialized_headers();
+        let hashed = cose_sign1.headers.unprotected
+            .header(&Label::new_text(String::from("hashed")))
+            .and_then(|h| h.as_special())
+            .and_then(|s| s.as_bool())
+            .unwrap_or(false);
+        if hashed {
+            return Err(JsError::from_str("Login payload must not be hashed"));
+        }
+        if !cose_sign1.verify(key, None, None)? {
+            return Err(JsError::from_str("Invalid signature"));
+        }
+        let payload = cose_sign1.payload
+            .clone()
+            .ok_or_else(|| JsError::from_str("Login signature must contain the payload"))?;
+        let challenge = Self::from_payload(payload)?;
+        if challenge.domain != expected_domain {
+            return Err(JsError::from_str(&format!("Login was for domain {}, expected {}", challenge.domain, expected_domain)));
+        }
+        let (now, clock_skew) = (from_bignum(now), from_bignum(clock_skew));
+        if from_bignum(&challenge.issued_at) > now.saturating_add(clock_skew) {
+            return Err(JsError::from_str("Login challenge was issued in the future"));
+        }
+        if now > from_bignum(&challenge.expiration).saturating_add(clock_skew) {
+            return Err(JsError::from_str("Login challenge has expired"));
+        }
+        let address = protected
+            .header(&Label::new_text(String::from("address")))
+            .and_then(|a| a.as_bytes())
+            .ok_or_else(|| JsError::from_str("Missing address header"))?;
+        let public_key = EdDSA25519Key::from_cose_key(key)?.public_key();
+        if !address_has_key_hash(&address, &crypto::blake2b224(&public_key)) {
+            return Err(JsError::from_str("Signing key does not own the address"));
+        }
+        Ok((challenge, address))
+    }
+}
+
+/// Storage of login nonces that have already been used, for replay protection.
+pub trait NonceStore {
+    /// Marks `nonce` as used. Returns false if it had already been used.
+    /// `expiration` (POSIX seconds) is when the nonce's challenge expires and can be forgotten.
+    fn use_nonce(&mut self, nonce: &[u8], expiration: u64) -> bool;
+}
+
+/// NonceStore that keeps used nonces in memory until their challenge expires.
+/// Only suitable for single-instance servers.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryNonceStore {
+    used: HashMap<Vec<u8>, u64>,
+}
+
+impl InMemoryNonceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets all nonces whose challenges expired before `now`
+    pub fn prune(&mut self, now: u64) {
+        self.used.retain(|_, expiration| *expiration >= now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.used.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used.is_empty()
+    }
+}
+
+impl NonceStore for InMemoryNonceStore {
+    fn use_nonce(&mut self, nonce: &[u8], expiration: u64) -> bool {
+        if self.used.contains_key(nonce) {
+            return false;
+        }
+        self.used.insert(nonce.to_vec(), expiration);
+        true
+    }
+}
+
+/// Full server-side login verification: `LoginChallenge::verify_signature()` followed by marking
+/// the nonce as used in `nonce_store`. The nonce is only consumed if everything else checked out.
+/// Returns the verified address bytes.
+pub fn verify_login<S: NonceStore>(
+    cose_sign1: &COSESign1,
+    key: &COSEKey,
+    expected_domain: &str,
+    now: u64,
+    clock_skew: u64,
+    nonce_store: &mut S,
+) -> Result<Vec<u8>, JsError> {
+    let (challenge, address) = LoginChallenge::verify_response(cose_sign1, key, expected_domain, &to_bignum(now), &to_bignum(clock_skew))?;
+    if !nonce_store.use_nonce(&challenge.nonce, from_bignum(&challenge.expiration).saturating_add(clock_skew)) {
+        return Err(JsError::from_str("Login nonce has already been used"));
+    }
+    Ok(address)
+}
+
+// Whether `key_hash` is the payment or stake key hash of a Shelley address.
+// Byron addresses can't be checked against a key hash so they never match.
+fn address_has_key_hash(address: &[u8], key_hash: &[u8]) -> bool {
+    let header = match address.first() {
+        Some(h) => h >> 4,
+        None => return false,
+    };
+    let has_expected_len = match header {
+        // base addresses
+        0b0000..=0b0011 => address.len() == 57,
+        // pointer addresses end in a variable length pointer
+        0b0100 | 0b0101 => address.len() >= 29,
+        // enterprise and reward addresses
+        0b0110 | 0b0111 | 0b1110 | 0b1111 => address.len() == 29,
+        _ => false,
+    };
+    if !has_expected_len {
+        return false;
+    }
+    // header | payment credential | stake credential
+    let payment = address.get(1..29);
+    let stake = address.get(29..57);
+    // header | stake credential
+    let reward_credential = address.get(1..29);
+    let candidates = match header {
+        // base address: payment key/script + stake key/script
+        0b0000 => vec![payment, stake],
+        0b0001 => vec![stake],
+        0b0010 => vec![payment],
+        // pointer and enterprise addresses with a payment key
+        0b0100 | 0b0110 => vec![payment],
+        // reward address with a stake key
+        0b1110 => vec![reward_credential],
+        _ => vec![],
+    };
+    candidates.into_iter().any(|hash| hash == Some(key_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOMAIN: &str = "app.example.com";
+
+    fn keypair() -> ([u8; 64], Vec<u8>) {
+        let (sk, pk) = cryptoxide::ed25519::keypair(&[42u8; 32]);
+        (sk, pk.to_vec())
+    }
+
+    fn enterprise_address(pk: &[u8]) -> Vec<u8> {
+        let mut address = vec![0x61u8];
+        address.extend_from_slice(&crypto::blake2b224(pk));
+        address
+    }
+
+    fn sign(challenge: &LoginChallenge, address: Vec<u8>) -> (COSESign1, COSEKey) {
+        let (sk, pk) = keypair();
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::EdDSA.into());
+        protected.set_header(&Label::new_text(String::from("address")), &CBORValue::new_bytes(address)).unwrap();
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        let builder = COSESign1Builder::new(&headers, challenge.to_payload(), false);
+        let signature = cryptoxide::ed25519::signature(&builder.make_data_to_sign().to_bytes(), &sk);
+        (builder.build(signature.to_vec()), EdDSA25519Key::new(pk).build())
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        let challenge = LoginChallenge::new(String::from(DOMAIN), vec![1, 2, 3, 4, 5, 6, 7, 8], &to_bignum(1000), &to_bignum(1300)).unwrap();
+        assert_eq!(LoginChallenge::from_payload(challenge.to_payload()).unwrap(), challenge);
+        let mut extended = challenge.to_payload();
+        extended.extend_from_slice(b"\nextra");
+        assert!(LoginChallenge::from_payload(extended).is_err());
+    }
+
+    #[test]
+    fn login() {
+        let (_, pk) = keypair();
+        let challenge = LoginChallenge::new(String::from(DOMAIN), vec![9; 16], &to_bignum(1000), &to_bignum(1300)).unwrap();
+        let (cose_sign1, key) = sign(&challenge, enterprise_address(&pk));
+        let mut store = InMemoryNonceStore::new();
+
+        assert!(verify_login(&cose_sign1, &key, "evil.example.com", 1100, 30, &mut store).is_err());
+        assert!(verify_login(&cose_sign1, &key, DOMAIN, 900, 30, &mut store).is_err());
+        assert!(verify_login(&cose_sign1, &key, DOMAIN, 1400, 30, &mut store).is_err());
+        // none of the failures above consumed the nonce
+        assert!(store.is_empty());
+        assert_eq!(verify_login(&cose_sign1, &key, DOMAIN, 1320, 30, &mut store).unwrap(), enterprise_address(&pk));
+        assert!(verify_login(&cose_sign1, &key, DOMAIN, 1100, 30, &mut store).is_err());
+        store.prune(2000);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn login_address_ownership() {
+        let challenge = LoginChallenge::new(String::from(DOMAIN), vec![9; 16], &to_bignum(1000), &to_bignum(1300)).unwrap();
+        let (cose_sign1, key) = sign(&challenge, enterprise_address(&[0u8; 32]));
+        assert!(LoginChallenge::verify_signature(&cose_sign1, &key, DOMAIN, &to_bignum(1100), &to_bignum(0)).is_err());
+
+        // stake key of a base address with a script payment part
+        let (_, pk) = keypair();
+        let mut base = vec![0x11u8];
+        base.extend_from_slice(&[0u8; 28]);
+        base.extend_from_slice(&crypto::blake2b224(&pk));
+        let (cose_sign1, key) = sign(&challenge, base);
+        assert!(LoginChallenge::verify_signature(&cose_sign1, &key, DOMAIN, &to_bignum(1100), &to_bignum(0)).is_ok());
+
+        // stake key of a (mainnet) reward address
+        let mut reward = vec![0xe1u8];
+        reward.extend_from_slice(&crypto::blake2b224(&pk));
+        let (cose_sign1, key) = sign(&challenge, reward.clone());
+        assert!(LoginChallenge::verify_signature(&cose_sign1, &key, DOMAIN, &to_bignum(1100), &to_bignum(0)).is_ok());
+        // a script reward address has no key to match
+        reward[0] = 0xf1;
+        let (cose_sign1, key) = sign(&challenge, reward);
+        assert!(LoginChallenge::verify_signature(&cose_sign1, &key, DOMAIN, &to_bignum(1100), &to_bignum(0)).is_err());
+
+        // trailing bytes after an otherwise matching address
+        let mut enterprise = enterprise_address(&pk);
+        enterprise.push(0);
+        let (cose_sign1, key) = sign(&challenge, enterprise);
+        assert!(LoginChallenge::verify_signature(&cose_sign1, &key, DOMAIN, &to_bignum(1100), &to_bignum(0)).is_err());
+        // but a pointer address ends in a variable length pointer
+        let mut pointer = vec![0x41u8];
+        pointer.extend_from_slice(&crypto::blake2b224(&pk));
+        pointer.extend_from_slice(&[0x81, 0x00, 0x02]);
+        let (cose_sign1, key) = sign(&challenge, pointer);
+        assert!(LoginChallenge::verify_signature(&cose_sign1, &key, DOMAIN, &to_bignum(1100), &to_bignum(0)).is_ok());
+    }
+}
@@ -0,0 +1,123 @@
+// HMAC (RFC 8152 section 9.1) for COSE_Mac0 and COSE_Mac - cheap symmetric authentication
+// e.g. between services that already share a key.
+
+use super::*;
+
+// The MAC algorithm from the protected headers of a COSE_Mac(0)
+pub (crate) fn mac_algorithm(headers: &Headers) -> Result<AlgorithmId, JsError> {
+    use std::convert::TryFrom;
+    let alg = headers.protected
+        .deserialized_headers()
+        .algorithm_id()
+        .ok_or_else(|| JsError::from_str("The algorithm must be set in the protected headers"))?;
+    match AlgorithmId::try_from(alg.clone()) {
+        Ok(alg @ AlgorithmId::HMAC256)
+        | Ok(alg @ AlgorithmId::HMAC384)
+        | Ok(alg @ AlgorithmId::HMAC512) => Ok(alg),
+        _ => Err(JsError::from_str(&format!("Unsupported MAC algorithm: {:?}", alg))),
+    }
+}
+
+// recommended key length in bytes i.e. the hash output size
+pub (crate) fn mac_key_len(alg: AlgorithmId) -> usize {
+    match alg {
+        AlgorithmId::HMAC384 => 48,
+        AlgorithmId::HMAC512 => 64,
+        _ => 32,
+    }
+}
+
+// the full (untruncated) HMAC of `data`
+pub (crate) fn compute_tag(alg: AlgorithmId, key: &[u8], data: &[u8]) -> Result<Vec<u8>, JsError> {
+    if key.is_empty() {
+        return Err(JsError::from_str("MAC key must not be empty"));
+    }
+    Ok(match alg {
+        AlgorithmId::HMAC384 => crypto::hmac_sha384(key, data),
+        AlgorithmId::HMAC512 => crypto::hmac_sha512(key, data),
+        _ => crypto::hmac_sha256(key, data),
+    })
+}
+
+fn verify_tag(headers: &Headers, key: &[u8], data: &MacStructure, tag: &[u8]) -> Result<bool, JsError> {
+    let expected = compute_tag(mac_algorithm(headers)?, key, &data.to_bytes())?;
+    Ok(crypto::constant_time_eq(&expected, tag))
+}
+
+#[wasm_bindgen]
+impl COSEMac0 {
+    /// Recomputes the tag with the symmetric `key` and compares it in constant time.
+    /// The algorithm (HMAC 256/256, 384/384 or 512/512) must be set in the protected headers.
+    /// See `mac_structure()` for the other arguments.
+    pub fn verify(&self, key: &COSEKey, external_aad: Option<Vec<u8>>, external_payload: Option<Vec<u8>>) -> Result<bool, JsError> {
+        let k = encryption::symmetric_key(key, mac_algorithm(&self.headers)?, KeyOperation::MACVerify)?;
+        verify_tag(&self.headers, &k, &self.mac_structure(external_aad, external_payload)?, &self.tag)
+    }
+}
+
+#[wasm_bindgen]
+impl COSEMac {
+    /// Recovers the MAC key from the recipient layer `key` opens, then verifies as `COSEMac0::verify()`.
+    /// See `COSEEncrypt::decrypt()` for the supported recipient keys. A key that opens no recipient
+    /// layer is a wrong key same as for `COSEMac0::verify()` so this returns false rather than failing.
+    pub fn verify(&self, key: &COSEKey, external_aad: Option<Vec<u8>>, external_payload: Option<Vec<u8>>) -> Result<bool, JsError> {
+        mac_algorithm(&self.headers)?;
+        let data = self.mac_structure(external_aad, external_payload)?;
+        match encryption::open_recipients(&self.recipients, key, KeyOperation::MACVerify)? {
+            Some((k, _)) => verify_tag(&self.headers, &k, &data, &self.tag),
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_rfc4231() {
+        // test case 2
+        let key = b"Jefe";
+        let data = b"what do ya want for nothing?";
+        assert_eq!(
+            hex::encode(crypto::hmac_sha256(key, data)),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert_eq!(
+            hex::encode(crypto::hmac_sha384(key, data)),
+            "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649");
+        assert_eq!(
+            hex::encode(crypto::hmac_sha512(key, data)),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737");
+    }
+
+    #[test]
+    fn mac_structure_serialization() {
+        let data = MacStructure::new(MacContext::Mac0, &ProtectedHeaderMap::new_empty(), vec![], vec![1u8]);
+        assert_eq!(hex::encode(data.to_bytes()), "84644d41433040404101");
+        let data = MacStructure::from_bytes(data.to_bytes()).unwrap();
+        assert_eq!(data.context(), MacContext::Mac0);
+        assert_eq!(data.payload(), vec![1u8]);
+    }
+
+    #[test]
+    fn cose_mac0_verify() {
+        let key = encryption::symmetric_cose_key(vec![7u8; 32]);
+        for alg in &[AlgorithmId::HMAC256, AlgorithmId::HMAC384, AlgorithmId::HMAC512] {
+            let headers = encryption::alg_headers(*alg);
+            let data = MacStructure::new(MacContext::Mac0, &headers.protected, vec![], b"webhook".to_vec());
+            let tag = compute_tag(*alg, &[7u8; 32], &data.to_bytes()).unwrap();
+            assert_eq!(tag.len(), mac_key_len(*alg));
+            let mac0 = COSEMac0::from_bytes(COSEMac0::new(&headers, Some(b"webhook".to_vec()), tag.clone()).to_bytes()).unwrap();
+            assert!(mac0.verify(&key, None, None).unwrap());
+            assert!(!mac0.verify(&key, Some(vec![1u8]), None).unwrap());
+            assert!(!mac0.verify(&encryption::symmetric_cose_key(vec![8u8; 32]), None, None).unwrap());
+            let mut truncated = tag;
+            truncated.pop();
+            assert!(!COSEMac0::new(&headers, Some(b"webhook".to_vec()), truncated).verify(&key, None, None).unwrap());
+        }
+        let mut wrong_alg = key;
+        wrong_alg.set_algorithm_id(&AlgorithmId::HMAC512.into());
+        assert!(COSEMac0::new(&encryption::alg_headers(AlgorithmId::HMAC256), Some(vec![]), vec![]).verify(&wrong_alg, None, None).is_err());
+        assert!(COSEMac0::new(&encryption::alg_headers(AlgorithmId::A128GCM), Some(vec![]), vec![]).verify(&wrong_alg, None, None).is_err());
+    }
+}
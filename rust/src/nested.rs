@@ -0,0 +1,436 @@
+// Nested messages for when both confidentiality and authenticity are needed: a signed message
+// encrypted as the plaintext of a COSE_Encrypt0 / COSE_Encrypt (sign-then-encrypt) or an encrypted
+// message signed as the payload of a COSE_Sign1 (encrypt-then-sign). Each outer layer's protected
+// content type (RFC 8152 section 3.1) says which COSE structure is inside it.
+
+use super::*;
+
+/// CoAP content formats of application/cose with the cose-type parameter - RFC 8152 section 16.10
+pub const CONTENT_FORMAT_COSE_ENCRYPT0: i32 = 16;
+pub const CONTENT_FORMAT_COSE_SIGN1: i32 = 18;
+pub const CONTENT_FORMAT_COSE_ENCRYPT: i32 = 96;
+pub const CONTENT_FORMAT_COSE_SIGN: i32 = 98;
+
+// how deep unwrap() goes before giving up on a message
+const MAX_NESTED_LAYERS: usize = 8;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum MessageLayerKind {
+    COSESIGN,
+    COSESIGN1,
+    COSEENCRYPT,
+    COSEENCRYPT0,
+}
+
+impl MessageLayerKind {
+    fn content_format(self) -> i32 {
+        match self {
+            MessageLayerKind::COSESIGN => CONTENT_FORMAT_COSE_SIGN,
+            MessageLayerKind::COSESIGN1 => CONTENT_FORMAT_COSE_SIGN1,
+            MessageLayerKind::COSEENCRYPT => CONTENT_FORMAT_COSE_ENCRYPT,
+            MessageLayerKind::COSEENCRYPT0 => CONTENT_FORMAT_COSE_ENCRYPT0,
+        }
+    }
+
+    // Accepts both the CoAP content formats and the media types e.g. application/cose; cose-type="cose-sign1"
+    fn from_content_type(content_type: &Label) -> Option<Self> {
+        let kinds = [
+            MessageLayerKind::COSESIGN,
+            MessageLayerKind::COSESIGN1,
+            MessageLayerKind::COSEENCRYPT,
+            MessageLayerKind::COSEENCRYPT0,
+        ];
+        if let Some(format) = content_type.as_int().and_then(|format| format.as_i32()) {
+            return kinds.iter().copied().find(|kind| kind.content_format() == format);
+        }
+        let media_type = content_type.as_text()?.to_ascii_lowercase().replace(' ', "");
+        let cose_type = media_type.strip_prefix("application/cose;cose-type=")?.trim_matches('"');
+        match cose_type {
+            "cose-sign" => Some(MessageLayerKind::COSESIGN),
+            "cose-sign1" => Some(MessageLayerKind::COSESIGN1),
+            "cose-encrypt" => Some(MessageLayerKind::COSEENCRYPT),
+            "cose-encrypt0" => Some(MessageLayerKind::COSEENCRYPT0),
+            _ => None,
+        }
+    }
+
+    // The outermost layer has no content type to go by so it's recognized from its tag or structure.
+    fn detect(message: &[u8]) -> Result<Self, JsError> {
+        let message = COSEMessage::from_bytes(message.to_vec())
+            .map_err(|_| JsError::from_str("Not a COSE_Sign, COSE_Sign1, COSE_Encrypt or COSE_Encrypt0 message"))?;
+        match message.kind() {
+            COSEMessageKind::COSESIGN => Ok(MessageLayerKind::COSESIGN),
+            COSEMessageKind::COSESIGN1 => Ok(MessageLayerKind::COSESIGN1),
+            COSEMessageKind::COSEENCRYPT => Ok(MessageLayerKind::COSEENCRYPT),
+            COSEMessageKind::COSEENCRYPT0 => Ok(MessageLayerKind::COSEENCRYPT0),
+            COSEMessageKind::COSEMAC | COSEMessageKind::COSEMAC0 => Err(JsError::from_str("COSE_Mac and COSE_Mac0 layers are not supported")),
+        }
+    }
+}
+
+// `headers` with the protected content type set to the format of `kind`
+fn with_content_type(headers: &Headers, kind: MessageLayerKind) -> Headers {
+    let mut protected = headers.protected.deserialized_headers();
+    protected.set_content_type(&Label::new_int(&Int::new_i32(kind.content_format())));
+    Headers::new(&ProtectedHeaderMap::new(&protected), &headers.unprotected)
+}
+
+fn signed_message_kind(signed: &SignedMessage) -> MessageLayerKind {
+    match signed.kind() {
+        SignedMessageKind::COSESIGN => MessageLayerKind::COSESIGN,
+        SignedMessageKind::COSESIGN1 => MessageLayerKind::COSESIGN1,
+    }
+}
+
+#[wasm_bindgen]
+impl COSEEncrypt0 {
+    /// Sign-then-encrypt: encrypts `signed` with the content type set accordingly in the protected headers.
+    /// See `encrypt()` for the other arguments.
+    pub fn encrypt_signed(headers: &Headers, signed: &SignedMessage, key: &COSEKey, external_aad: Option<Vec<u8>>) -> Result<COSEEncrypt0, JsError> {
+        Self::encrypt(&with_content_type(headers, signed_message_kind(signed)), signed.to_bytes(), key, external_aad)
+    }
+}
+
+#[wasm_bindgen]
+impl COSEEncrypt {
+    /// Sign-then-encrypt: encrypts `signed` with the content type set accordingly in the protected headers.
+    /// See `encrypt()` for the other arguments.
+    pub fn encrypt_signed(headers: &Headers, signed: &SignedMessage, recipients: &COSEKeySet, external_aad: Option<Vec<u8>>) -> Result<COSEEncrypt, JsError> {
+        Self::encrypt(&with_content_type(headers, signed_message_kind(signed)), signed.to_bytes(), recipients, external_aad)
+    }
+}
+
+#[wasm_bindgen]
+impl PubKeyEncryption {
+    /// Sign-then-encrypt: encrypts `signed` with ChaCha20/Poly1305 to all `recipients`. See `encrypt()`.
+    pub fn encrypt_signed(signed: &SignedMessage, recipients: &COSEKeySet) -> Result<PubKeyEncryption, JsError> {
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::ChaCha20Poly1305.into());
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        Ok(Self::new(&COSEEncrypt::encrypt_signed(&headers, signed, recipients, None)?))
+    }
+}
+
+#[wasm_bindgen]
+impl COSESign1Builder {
+    /// Encrypt-then-sign: a builder with `encrypted` as the (attached) payload and the content type set
+    /// accordingly in the protected headers
+    pub fn new_for_encrypt0(headers: &Headers, encrypted: &COSEEncrypt0) -> COSESign1Builder {
+        Self::new(&with_content_type(headers, MessageLayerKind::COSEENCRYPT0), encrypted.to_bytes(), false)
+    }
+
+    /// Encrypt-then-sign: same as `new_for_encrypt0()` for a COSE_Encrypt e.g. from `PubKeyEncryption`
+    pub fn new_for_encrypt(headers: &Headers, encrypted: &COSEEncrypt) -> COSESign1Builder {
+        Self::new(&with_content_type(headers, MessageLayerKind::COSEENCRYPT), encrypted.to_bytes(), false)
+    }
+}
+
+/// A layer peeled off by `MessageUnwrapper::unwrap()`: a signature layer that was verified
+/// or an encryption layer that was decrypted
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct MessageLayer {
+    kind: MessageLayerKind,
+    key_id: Option<Vec<u8>>,
+    content_type: Option<Label>,
+}
+
+#[wasm_bindgen]
+impl MessageLayer {
+    pub fn kind(&self) -> MessageLayerKind {
+        self.kind
+    }
+
+    pub fn is_verified(&self) -> bool {
+        matches!(self.kind, MessageLayerKind::COSESIGN | MessageLayerKind::COSESIGN1)
+    }
+
+    pub fn is_decrypted(&self) -> bool {
+        !self.is_verified()
+    }
+
+    /// The key id of the key that verified or decrypted this layer, if it has one
+    pub fn key_id(&self) -> Option<Vec<u8>> {
+        self.key_id.clone()
+    }
+
+    /// The content type of what this layer carries
+    pub fn content_type(&self) -> Option<Label> {
+        self.content_type.clone()
+    }
+}
+
+/// The layers peeled off a message, outermost first
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct MessageLayers(Vec<MessageLayer>);
+
+#[wasm_bindgen]
+impl MessageLayers {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> MessageLayer {
+        self.0[index].clone()
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct UnwrappedMessage {
+    payload: Vec<u8>,
+    layers: MessageLayers,
+}
+
+#[wasm_bindgen]
+impl UnwrappedMessage {
+    /// The innermost content
+    pub fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+
+    pub fn layers(&self) -> MessageLayers {
+        self.layers.clone()
+    }
+
+    /// Whether some layer was a verified signature i.e. the payload is authenticated
+    pub fn is_verified(&self) -> bool {
+        self.layers.0.iter().any(MessageLayer::is_verified)
+    }
+
+    /// Whether some layer was decrypted i.e. the payload was confidential
+    pub fn is_decrypted(&self) -> bool {
+        self.layers.0.iter().any(MessageLayer::is_decrypted)
+    }
+}
+
+/// Peels every layer off a nested message, verifying signature layers and decrypting encryption
+/// layers with the keys given, and following the content types down to the innermost payload.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct MessageUnwrapper {
+    verification_keys: COSEKeySet,
+    decryption_keys: COSEKeySet,
+}
+
+#[wasm_bindgen]
+impl MessageUnwrapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an EdDSA (Ed25519) public key for verifying COSE_Sign1 / COSE_Sign layers
+    pub fn add_verification_key(&mut self, key: &COSEKey) {
+        self.verification_keys.add(key);
+    }
+
+    /// Adds a key for decrypting COSE_Encrypt0 / COSE_Encrypt layers. See their `decrypt()` for the supported keys.
+    pub fn add_decryption_key(&mut self, key: &COSEKey) {
+        self.decryption_keys.add(key);
+    }
+
+    /// Fails if any layer can't be verified or decrypted with the keys given, so a successful
+    /// result means every layer was checked. Detached payloads/ciphertexts and external AAD are not supported.
+    pub fn unwrap(&self, message: Vec<u8>) -> Result<UnwrappedMessage, JsError> {
+        let mut kind = MessageLayerKind::detect(&message)?;
+        let mut content = message;
+        let mut layers = Vec::new();
+        loop {
+            if layers.len() == MAX_NESTED_LAYERS {
+                return Err(JsError::from_str(&format!("Messages can't be nested more than {} layers deep", MAX_NESTED_LAYERS)));
+            }
+            let (headers, inner, key_id) = self.open_layer(kind, content)?;
+            let content_type = encryption::header_param(&headers, HeaderMap::content_type);
+            let inner_kind = content_type.as_ref().and_then(MessageLayerKind::from_content_type);
+            layers.push(MessageLayer {
+                kind,
+                key_id,
+                content_type,
+            });
+            match inner_kind {
+                Some(inner_kind) => {
+                    kind = inner_kind;
+                    content = inner;
+                },
+                None => return Ok(UnwrappedMessage {
+                    payload: inner,
+                    layers: MessageLayers(layers),
+                }),
+            }
+        }
+    }
+}
+
+// (headers, content, key id of the key that opened it) of a single layer
+type OpenedLayer = (Headers, Vec<u8>, Option<Vec<u8>>);
+
+impl MessageUnwrapper {
+    fn open_layer(&self, kind: MessageLayerKind, message: Vec<u8>) -> Result<OpenedLayer, JsError> {
+        match kind {
+            MessageLayerKind::COSESIGN1 => {
+                let signed = COSESign1::from_bytes(message)?;
+                let payload = signed.payload.clone().ok_or_else(|| JsError::from_str("COSE_Sign1 layer has a detached payload"))?;
+                let key = self.verification_keys.0
+                    .iter()
+                    .find(|key| signed.verify(key, None, None).unwrap_or(false))
+                    .ok_or_else(|| JsError::from_str("COSE_Sign1 layer could not be verified with any of the keys"))?;
+                Ok((signed.headers, payload, key.key_id()))
+            },
+            MessageLayerKind::COSESIGN => {
+                let signed = COSESign::from_bytes(message)?;
+                let payload = signed.payload.clone().ok_or_else(|| JsError::from_str("COSE_Sign layer has a detached payload"))?;
+                let key = self.verification_keys.0
+                    .iter()
+                    .find(|key| signed.verify(key, None, None).unwrap_or(false))
+                    .ok_or_else(|| JsError::from_str("COSE_Sign layer could not be verified with any of the keys"))?;
+                Ok((signed.headers, payload, key.key_id()))
+            },
+            MessageLayerKind::COSEENCRYPT0 => {
+                let encrypted = COSEEncrypt0::from_bytes(message)?;
+                let (plaintext, key_id) = self.decryption_keys.0
+                    .iter()
+                    .find_map(|key| encrypted.decrypt(key, None).ok().map(|plaintext| (plaintext, key.key_id())))
+                    .ok_or_else(|| JsError::from_str("COSE_Encrypt0 layer could not be decrypted with any of the keys"))?;
+                Ok((encrypted.headers, plaintext, key_id))
+            },
+            MessageLayerKind::COSEENCRYPT => {
+                let encrypted = COSEEncrypt::from_bytes(message)?;
+                let (plaintext, key_id) = self.decryption_keys.0
+                    .iter()
+                    .find_map(|key| encrypted.decrypt(key, None).ok().map(|plaintext| (plaintext, key.key_id())))
+                    .ok_or_else(|| JsError::from_str("COSE_Encrypt layer could not be decrypted with any of the keys"))?;
+                Ok((encrypted.headers, plaintext, key_id))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eddsa_keypair(seed: u8) -> ([u8; 64], COSEKey) {
+        let (sk, pk) = cryptoxide::ed25519::keypair(&[seed; 32]);
+        (sk, EdDSA25519Key::new(pk.to_vec()).build())
+    }
+
+    fn sign1(builder: &COSESign1Builder, sk: &[u8; 64]) -> COSESign1 {
+        builder.build(cryptoxide::ed25519::signature(&builder.make_data_to_sign().to_bytes(), sk).to_vec())
+    }
+
+    fn empty_headers() -> Headers {
+        Headers::new(&ProtectedHeaderMap::new_empty(), &HeaderMap::new())
+    }
+
+    #[test]
+    fn sign_then_encrypt() {
+        let (sk, verification_key) = eddsa_keypair(1);
+        let (recipient, mut recipient_private) = encryption::x25519_keypair(2);
+        recipient_private.set_key_id(b"bob".to_vec());
+        let signed = sign1(&COSESign1Builder::new(&empty_headers(), b"meet at noon".to_vec(), false), &sk);
+        let mut recipients = COSEKeySet::new();
+        recipients.add(&recipient);
+        let encrypted = PubKeyEncryption::encrypt_signed(&SignedMessage::new_cose_sign1(&signed), &recipients).unwrap();
+
+        let mut unwrapper = MessageUnwrapper::new();
+        unwrapper.add_verification_key(&verification_key);
+        unwrapper.add_decryption_key(&recipient_private);
+        let unwrapped = unwrapper.unwrap(encrypted.to_bytes()).unwrap();
+        assert_eq!(unwrapped.payload(), b"meet at noon".to_vec());
+        assert!(unwrapped.is_verified() && unwrapped.is_decrypted());
+        let layers = unwrapped.layers();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers.get(0).kind(), MessageLayerKind::COSEENCRYPT);
+        assert_eq!(layers.get(0).key_id(), Some(b"bob".to_vec()));
+        assert_eq!(layers.get(0).content_type(), Some(Label::new_int(&Int::new_i32(CONTENT_FORMAT_COSE_SIGN1))));
+        assert_eq!(layers.get(1).kind(), MessageLayerKind::COSESIGN1);
+        assert!(layers.get(1).is_verified());
+
+        // every layer has to be opened
+        let mut decrypt_only = MessageUnwrapper::new();
+        decrypt_only.add_decryption_key(&recipient_private);
+        assert!(decrypt_only.unwrap(encrypted.to_bytes()).is_err());
+        let (_, other_key) = eddsa_keypair(3);
+        let mut wrong_signer = MessageUnwrapper::new();
+        wrong_signer.add_verification_key(&other_key);
+        wrong_signer.add_decryption_key(&recipient_private);
+        assert!(wrong_signer.unwrap(encrypted.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn encrypt_then_sign() {
+        let (sk, verification_key) = eddsa_keypair(4);
+        let key = encryption::symmetric_cose_key(vec![5u8; 32]);
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::ChaCha20Poly1305.into());
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        let encrypted = COSEEncrypt0::encrypt(&headers, b"for your eyes only".to_vec(), &key, None).unwrap();
+        let signed = sign1(&COSESign1Builder::new_for_encrypt0(&empty_headers(), &encrypted), &sk);
+        assert_eq!(
+            signed.headers().protected().deserialized_headers().content_type(),
+            Some(Label::new_int(&Int::new_i32(CONTENT_FORMAT_COSE_ENCRYPT0))));
+
+        let mut unwrapper = MessageUnwrapper::new();
+        unwrapper.add_verification_key(&verification_key);
+        unwrapper.add_decryption_key(&key);
+        let unwrapped = unwrapper.unwrap(signed.to_bytes()).unwrap();
+        assert_eq!(unwrapped.payload(), b"for your eyes only".to_vec());
+        let layers = unwrapped.layers();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers.get(0).kind(), MessageLayerKind::COSESIGN1);
+        assert_eq!(layers.get(1).kind(), MessageLayerKind::COSEENCRYPT0);
+        assert!(layers.get(1).is_decrypted());
+        assert_eq!(layers.get(1).content_type(), None);
+
+        // a plain signed message is a single verified layer
+        let plain = sign1(&COSESign1Builder::new(&empty_headers(), b"hello".to_vec(), false), &sk);
+        let unwrapped = unwrapper.unwrap(plain.to_bytes()).unwrap();
+        assert_eq!(unwrapped.layers().len(), 1);
+        assert!(unwrapped.is_verified() && !unwrapped.is_decrypted());
+    }
+
+    #[test]
+    fn cose_sign_in_encrypt0() {
+        let (sk, verification_key) = eddsa_keypair(6);
+        let builder = COSESignBuilder::new(&empty_headers(), b"multisig".to_vec(), false);
+        let mut sign_protected = HeaderMap::new();
+        sign_protected.set_algorithm_id(&AlgorithmId::EdDSA.into());
+        let sign_protected = ProtectedHeaderMap::new(&sign_protected);
+        let mut data = builder.make_data_to_sign();
+        data.set_sign_protected(&sign_protected);
+        let signature = cryptoxide::ed25519::signature(&data.to_bytes(), &sk).to_vec();
+        let mut signatures = COSESignatures::new();
+        signatures.add(&COSESignature::new(&Headers::new(&sign_protected, &HeaderMap::new()), signature));
+        let signed = builder.build(&signatures);
+        assert!(signed.verify(&verification_key, None, None).unwrap());
+        assert!(!signed.verify(&verification_key, Some(vec![1u8]), None).unwrap());
+
+        let key = encryption::symmetric_cose_key(vec![7u8; 16]);
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::A128GCM.into());
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        let encrypted = COSEEncrypt0::encrypt_signed(&headers, &SignedMessage::new_cose_sign(&signed), &key, None).unwrap();
+        let mut unwrapper = MessageUnwrapper::new();
+        unwrapper.add_verification_key(&verification_key);
+        unwrapper.add_decryption_key(&key);
+        let unwrapped = unwrapper.unwrap(encrypted.to_bytes()).unwrap();
+        assert_eq!(unwrapped.payload(), b"multisig".to_vec());
+        assert_eq!(unwrapped.layers().get(1).kind(), MessageLayerKind::COSESIGN);
+    }
+
+    #[test]
+    fn content_type_media_types() {
+        let media_type = Label::new_text(String::from("application/cose; cose-type=\"cose-sign1\""));
+        assert_eq!(MessageLayerKind::from_content_type(&media_type), Some(MessageLayerKind::COSESIGN1));
+        let media_type = Label::new_text(String::from("application/cose; cose-type=cose-encrypt"));
+        assert_eq!(MessageLayerKind::from_content_type(&media_type), Some(MessageLayerKind::COSEENCRYPT));
+        assert_eq!(MessageLayerKind::from_content_type(&Label::new_text(String::from("text/plain"))), None);
+        assert_eq!(MessageLayerKind::from_content_type(&Label::new_int(&Int::new_i32(0))), None);
+    }
+}
@@ -0,0 +1,340 @@
+// Selective Disclosure CWTs - draft-ietf-spice-sd-cwt
+// Only top-level claims of the claims set can be redacted.
+
+use super::*;
+use std::io::Seek;
+
+// header labels as requested in the draft
+const SD_CLAIMS: i32 = 17;
+const SD_ALG: i32 = 18;
+// simple(59) is the map key under which the hashes of the redacted claims are stored
+const REDACTED_CLAIM_KEYS: u8 = 59;
+const MIN_SALT_LEN: usize = 16;
+// SHA-256 is the only hash algorithm supported for disclosures
+const SHA_256: i32 = -16;
+
+fn redacted_claim_keys() -> CBORValue {
+    CBORValue::new_special(&CBORSpecial::new_unassigned(REDACTED_CLAIM_KEYS))
+}
+
+// claims the verifier relies on can't be redacted
+fn is_redactable(key: &CBORValue) -> bool {
+    let reserved = [1, 3, 4, 5, 6];
+    !reserved.iter().any(|claim| *key == CBORValue::new_int(&Int::new_i32(*claim)))
+        && *key != redacted_claim_keys()
+}
+
+/// A redacted claim: [salt, value, key]
+#[wasm_bindgen]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SDCWTDisclosure {
+    salt: Vec<u8>,
+    value: CBORValue,
+    key: CBORValue,
+}
+
+to_from_bytes!(SDCWTDisclosure);
+
+#[wasm_bindgen]
+impl SDCWTDisclosure {
+    pub fn salt(&self) -> Vec<u8> {
+        self.salt.clone()
+    }
+
+    pub fn value(&self) -> CBORValue {
+        self.value.clone()
+    }
+
+    pub fn key(&self) -> CBORValue {
+        self.key.clone()
+    }
+
+    /// SHA-256 of the encoded disclosure as found in the signed redacted claims array
+    pub fn hash(&self) -> Vec<u8> {
+        crypto::sha256(&self.to_bytes()).to_vec()
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct SDCWTDisclosures(Vec<SDCWTDisclosure>);
+
+#[wasm_bindgen]
+impl SDCWTDisclosures {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> SDCWTDisclosure {
+        self.0[index].clone()
+    }
+
+    pub fn add(&mut self, elem: &SDCWTDisclosure) {
+        self.0.push(elem.clone());
+    }
+}
+
+impl Default for SDCWTDisclosures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Moves the claim `key` out of `payload` into a disclosure and adds its hash to the redacted claims array
+pub (crate) fn redact_claim(payload: &mut CBORObject, key: &CBORValue, salt: Vec<u8>) -> Result<SDCWTDisclosure, JsError> {
+    if salt.len() < MIN_SALT_LEN {
+        return Err(JsError::from_str(&format!("Salt must be at least {} bytes, found {}", MIN_SALT_LEN, salt.len())));
+    }
+    if !is_redactable(key) {
+        return Err(JsError::from_str(&format!("Claim {:?} can't be redacted", key)));
+    }
+    let value = payload.remove(key).ok_or_else(|| JsError::from_str(&format!("Claim {:?} not found", key)))?;
+    let disclosure = SDCWTDisclosure {
+        salt,
+        value,
+        key: key.clone(),
+    };
+    let mut hashes = payload.get(&redacted_claim_keys())
+        .and_then(|hashes| hashes.as_array())
+        .unwrap_or_else(CBORArray::new);
+    hashes.add(&CBORValue::new_bytes(disclosure.hash()));
+    payload.insert(&redacted_claim_keys(), &CBORValue::new_array(&hashes));
+    Ok(disclosure)
+}
+
+/// Adds the sd_alg protected header and the sd_claims unprotected header holding `disclosures`
+pub (crate) fn set_disclosure_headers(headers: &mut Headers, disclosures: &SDCWTDisclosures) {
+    let mut protected = headers.protected.deserialized_headers();
+    protected.other_headers.insert(Label::new_int(&Int::new_i32(SD_ALG)), CBORValue::new_int(&Int::new_i32(SHA_256)));
+    headers.protected = ProtectedHeaderMap::new(&protected);
+    let sd_claims = disclosures.0
+        .iter()
+        .map(|disclosure| CBORValue::new_bytes(disclosure.to_bytes()))
+        .collect::<Vec<CBORValue>>();
+    headers.unprotected.other_headers.insert(Label::new_int(&Int::new_i32(SD_CLAIMS)), CBORValue::new_array(&sd_claims.into()));
+}
+
+/// A CWT with (possibly some of) its disclosures in the unprotected sd_claims header
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct SDCWT(CWT);
+
+to_from_bytes!(SDCWT);
+
+#[wasm_bindgen]
+impl SDCWT {
+    pub fn new(cwt: &CWT) -> Self {
+        Self(cwt.clone())
+    }
+
+    pub fn cwt(&self) -> CWT {
+        self.0.clone()
+    }
+
+    pub fn disclosures(&self) -> Result<SDCWTDisclosures, JsError> {
+        let disclosures = self.raw_disclosures()?
+            .into_iter()
+            .map(SDCWTDisclosure::from_bytes)
+            .collect::<Result<Vec<SDCWTDisclosure>, DeserializeError>>()?;
+        Ok(SDCWTDisclosures(disclosures))
+    }
+
+    /// Holder-side selection: keeps only the disclosures of the claims in `keys`.
+    /// The signature stays valid as the disclosures live in the unprotected headers.
+    pub fn select_disclosures(&self, keys: &CBORArray) -> Result<SDCWT, JsError> {
+        let disclosures = self.disclosures()?;
+        let mut selected = SDCWTDisclosures::new();
+        for key in keys.values.iter() {
+            let disclosure = disclosures.0
+                .iter()
+                .find(|disclosure| disclosure.key == *key)
+                .ok_or_else(|| JsError::from_str(&format!("No disclosure for claim {:?}", key)))?;
+            selected.add(disclosure);
+        }
+        let cose_sign1 = self.0.cose_sign1();
+        let mut headers = cose_sign1.headers();
+        headers.unprotected.other_headers.remove(&Label::new_int(&Int::new_i32(SD_CLAIMS)));
+        let sd_claims = selected.0
+            .iter()
+            .map(|disclosure| CBORValue::new_bytes(disclosure.to_bytes()))
+            .collect::<Vec<CBORValue>>();
+        headers.unprotected.other_headers.insert(Label::new_int(&Int::new_i32(SD_CLAIMS)), CBORValue::new_array(&sd_claims.into()));
+        Ok(Self(CWT::new(&COSESign1::new(&headers, cose_sign1.payload(), cose_sign1.signature()))))
+    }
+
+    /// Validates the CWT (see `CWT::validate()`) then checks every disclosure against the signed
+    /// redacted claims hashes and returns the claims set with the disclosed claims put back in.
+    /// Redacted claims that weren't disclosed stay hidden.
+    pub fn verify(&self, key: &COSEKey, audience: &str, now: &BigNum, clock_skew: &BigNum) -> Result<CWTClaims, JsError> {
+        let protected = self.0.cose_sign1().headers().protected().deserialized_headers();
+        if let Some(sd_alg) = protected.header(&Label::new_int(&Int::new_i32(SD_ALG))) {
+            if sd_alg != CBORValue::new_int(&Int::new_i32(SHA_256)) {
+                return Err(JsError::from_str(&format!("Unsupported sd_alg: {:?}", sd_alg)));
+            }
+        }
+        self.0.validate(key, audience, now, clock_skew)?;
+        let mut payload = CBORObject::from_bytes(self.0.cose_sign1().payload().unwrap())?;
+        let mut hashes = match payload.remove(&redacted_claim_keys()) {
+            Some(hashes) => hashes.as_array()
+                .ok_or_else(|| JsError::from_str("Redacted claims must be an array of hashes"))?
+                .values,
+            None => Vec::new(),
+        };
+        for raw in self.raw_disclosures()? {
+            let hash = CBORValue::new_bytes(crypto::sha256(&raw).to_vec());
+            // each hash can only be used once
+            let position = hashes.iter()
+                .position(|redacted| *redacted == hash)
+                .ok_or_else(|| JsError::from_str("Disclosure does not match any redacted claim"))?;
+            hashes.swap_remove(position);
+            let disclosure = SDCWTDisclosure::from_bytes(raw)?;
+            if !is_redactable(&disclosure.key) || payload.get(&disclosure.key).is_some() {
+                return Err(JsError::from_str(&format!("Disclosed claim {:?} clashes with a signed claim", disclosure.key)));
+            }
+            payload.insert(&disclosure.key, &disclosure.value);
+        }
+        Ok(CWTClaims::from_bytes(payload.to_bytes())?)
+    }
+}
+
+impl SDCWT {
+    // the encoded disclosures exactly as sent since that's what was hashed
+    fn raw_disclosures(&self) -> Result<Vec<Vec<u8>>, JsError> {
+        let sd_claims = match self.0.cose_sign1().headers().unprotected().header(&Label::new_int(&Int::new_i32(SD_CLAIMS))) {
+            Some(sd_claims) => sd_claims,
+            None => return Ok(Vec::new()),
+        };
+        sd_claims.as_array()
+            .ok_or_else(|| JsError::from_str("sd_claims must be an array"))?
+            .values
+            .iter()
+            .map(|disclosure| disclosure.as_bytes().ok_or_else(|| JsError::from_str("Disclosures must be byte strings")))
+            .collect()
+    }
+}
+
+// serialization
+
+impl cbor_event::se::Serialize for SDCWTDisclosure {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(3))?;
+        serializer.write_bytes(&self.salt)?;
+        self.value.serialize(serializer)?;
+        self.key.serialize(serializer)?;
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for SDCWTDisclosure {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(3)?;
+            let salt = raw.bytes().map_err(|e| DeserializeError::from(e).annotate("salt"))?;
+            let value = CBORValue::deserialize(raw).map_err(|e| e.annotate("value"))?;
+            let key = CBORValue::deserialize(raw).map_err(|e| e.annotate("key"))?;
+            match len {
+                cbor_event::Len::Len(_) => (),
+                cbor_event::Len::Indefinite => match raw.special()? {
+                    cbor_event::Special::Break => (),
+                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
+                },
+            }
+            Ok(SDCWTDisclosure {
+                salt,
+                value,
+                key,
+            })
+        })().map_err(|e| e.annotate("SDCWTDisclosure"))
+    }
+}
+
+impl cbor_event::se::Serialize for SDCWT {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Deserialize for SDCWT {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        Ok(Self(CWT::deserialize(raw)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> CBORValue {
+        CBORValue::new_text(String::from(s))
+    }
+
+    #[test]
+    fn issue_select_verify() {
+        let (sk, pk) = cryptoxide::ed25519::keypair(&[5u8; 32]);
+        let key = EdDSA25519Key::new(pk.to_vec()).build();
+        let mut claims = CWTClaims::new();
+        claims.set_issuer(String::from("did:example:issuer"));
+        claims.set_audience(String::from("https://verifier.example.com"));
+        claims.set_expiration(&to_bignum(2000));
+        claims.set_subject(String::from("alice"));
+        let mut custom = CBORObject::new();
+        custom.insert(&text("name"), &text("Alice"));
+        custom.insert(&text("age_over_18"), &CBORValue::new_special(&CBORSpecial::new_bool(true)));
+        custom.insert(&text("country"), &text("JP"));
+        claims.set_custom_claims(&custom).unwrap();
+
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&AlgorithmId::EdDSA.into());
+        let headers = Headers::new(&ProtectedHeaderMap::new(&protected), &HeaderMap::new());
+        let mut builder = SDCWTBuilder::new(&headers, &claims);
+        builder.redact_claim(&text("name"), vec![1u8; 16]).unwrap();
+        builder.redact_claim(&text("age_over_18"), vec![2u8; 16]).unwrap();
+        builder.redact_claim(&CBORValue::new_int(&Int::new_i32(2)), vec![3u8; 16]).unwrap();
+        assert!(builder.redact_claim(&CBORValue::new_int(&Int::new_i32(4)), vec![4u8; 16]).is_err());
+        assert!(builder.redact_claim(&text("country"), vec![4u8; 8]).is_err());
+        let signature = cryptoxide::ed25519::signature(&builder.make_data_to_sign().to_bytes(), &sk);
+        let issued = SDCWT::from_bytes(builder.build(signature.to_vec()).to_bytes()).unwrap();
+        assert_eq!(issued.disclosures().unwrap().len(), 3);
+
+        // nothing redacted is in the signed payload
+        let signed = CBORObject::from_bytes(issued.cwt().cose_sign1().payload().unwrap()).unwrap();
+        assert!(signed.get(&text("name")).is_none());
+        assert_eq!(signed.get(&redacted_claim_keys()).unwrap().as_array().unwrap().len(), 3);
+
+        let presented = issued.select_disclosures(&vec![text("age_over_18")].into()).unwrap();
+        let verified = presented.verify(&key, "https://verifier.example.com", &to_bignum(1000), &to_bignum(0)).unwrap();
+        let custom = verified.custom_claims();
+        assert_eq!(custom.get(&text("age_over_18")), Some(CBORValue::new_special(&CBORSpecial::new_bool(true))));
+        assert_eq!(custom.get(&text("country")), Some(text("JP")));
+        assert!(custom.get(&text("name")).is_none());
+        assert!(verified.subject().is_none());
+
+        let everything = issued.verify(&key, "https://verifier.example.com", &to_bignum(1000), &to_bignum(0)).unwrap();
+        assert_eq!(everything.subject(), Some(String::from("alice")));
+        assert_eq!(everything.custom_claims().get(&text("name")), Some(text("Alice")));
+
+        // a forged disclosure doesn't match any signed hash
+        let cose_sign1 = presented.cwt().cose_sign1();
+        let mut headers = cose_sign1.headers();
+        let forged = SDCWTDisclosure {
+            salt: vec![2u8; 16],
+            value: CBORValue::new_special(&CBORSpecial::new_bool(false)),
+            key: text("age_over_18"),
+        };
+        let mut disclosures = SDCWTDisclosures::new();
+        disclosures.add(&forged);
+        set_disclosure_headers(&mut headers, &disclosures);
+        let tampered = SDCWT::new(&CWT::new(&COSESign1::new(&headers, cose_sign1.payload(), cose_sign1.signature())));
+        assert!(tampered.verify(&key, "https://verifier.example.com", &to_bignum(1000), &to_bignum(0)).is_err());
+    }
+}
@@ -432,6 +432,7 @@ impl cbor_event::se::Serialize for COSESign1 {
 impl Deserialize for COSESign1 {
     fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
         (|| -> Result<_, DeserializeError> {
+            deserialize_optional_tag(raw, COSE_SIGN1_TAG)?;
             let len = raw.array()?;
             let mut read_len = CBORReadLen::new(len);
             read_len.read_elems(4)?;
@@ -488,6 +489,7 @@ impl cbor_event::se::Serialize for COSESign {
 impl Deserialize for COSESign {
     fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
         (|| -> Result<_, DeserializeError> {
+            deserialize_optional_tag(raw, COSE_SIGN_TAG)?;
             let len = raw.array()?;
             let mut read_len = CBORReadLen::new(len);
             read_len.read_elems(4)?;
@@ -543,6 +545,19 @@ impl Deserialize for SignedMessageEnum {
     fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
         (|| -> Result<_, DeserializeError> {
             let initial_position = raw.as_mut_ref().seek(SeekFrom::Current(0)).unwrap();
+            // no need to guess if it's tagged
+            if raw.cbor_type()? == cbor_event::Type::Tag {
+                let tag = raw.tag()?;
+                raw.as_mut_ref().seek(SeekFrom::Start(initial_position)).unwrap();
+                return match tag {
+                    COSE_SIGN_TAG => COSESign::deserialize(raw).map(SignedMessageEnum::COSESIGN),
+                    COSE_SIGN1_TAG => COSESign1::deserialize(raw).map(SignedMessageEnum::COSESIGN1),
+                    other => Err(DeserializeFailure::FixedValueMismatch{
+                        found: Key::Uint(other),
+                        expected: Key::Str(String::from("a COSE_Sign or COSE_Sign1 tag (18 or 98)")),
+                    }.into()),
+                };
+            }
             match (|raw: &mut Deserializer<_>| -> Result<_, DeserializeError> {
                 Ok(COSESign::deserialize(raw)?)
             })(raw)
@@ -658,6 +673,223 @@ impl Deserialize for SigStructure {
     }
 }
 
+impl cbor_event::se::Serialize for EncStructure {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(3))?;
+        let context_str = match self.context {
+            EncContext::Encrypt => "Encrypt",
+            EncContext::Encrypt0 => "Encrypt0",
+            EncContext::EncRecipient => "Enc_Recipient",
+            EncContext::MacRecipient => "Mac_Recipient",
+            EncContext::RecRecipient => "Rec_Recipient",
+        };
+        serializer.write_text(context_str)?;
+        self.protected.serialize(serializer)?;
+        serializer.write_bytes(&self.external_aad)?;
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for EncStructure {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(3)?;
+            let context = (|| -> Result<_, DeserializeError> {
+                match raw.text()?.as_str() {
+                    "Encrypt" => Ok(EncContext::Encrypt),
+                    "Encrypt0" => Ok(EncContext::Encrypt0),
+                    "Enc_Recipient" => Ok(EncContext::EncRecipient),
+                    "Mac_Recipient" => Ok(EncContext::MacRecipient),
+                    "Rec_Recipient" => Ok(EncContext::RecRecipient),
+                    other => Err(DeserializeFailure::FixedValueMismatch{
+                        found: Key::Str(String::from(other)),
+                        expected: Key::Str(String::from("Encrypt, Encrypt0, Enc_Recipient, Mac_Recipient or Rec_Recipient")),
+                    }.into())
+                }
+            })().map_err(|e| e.annotate("context"))?;
+            let protected = ProtectedHeaderMap::deserialize(raw).map_err(|e| e.annotate("protected"))?;
+            let external_aad = raw.bytes().map_err(|e| DeserializeError::from(e).annotate("external_aad"))?;
+            match len {
+                cbor_event::Len::Len(_) => (),
+                cbor_event::Len::Indefinite => match raw.special()? {
+                    cbor_event::Special::Break => (),
+                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
+                },
+            }
+            Ok(EncStructure {
+                context,
+                protected,
+                external_aad,
+            })
+        })().map_err(|e| e.annotate("EncStructure"))
+    }
+}
+
+// nil or bytes - used by the optional fields of the COSE_KDF_Context and detached MAC payloads
+fn serialize_nullable_bytes<'se, W: Write>(bytes: &Option<Vec<u8>>, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+    match bytes {
+        Some(bytes) => serializer.write_bytes(bytes),
+        None => serializer.write_special(cbor_event::Special::Null),
+    }
+}
+
+fn deserialize_nullable<R: BufRead + Seek, T>(raw: &mut Deserializer<R>, f: impl FnOnce(&mut Deserializer<R>) -> Result<T, DeserializeError>) -> Result<Option<T>, DeserializeError> {
+    match raw.cbor_type()? != cbor_event::Type::Special {
+        true => f(raw).map(Some),
+        false => {
+            if raw.special()? != cbor_event::Special::Null {
+                return Err(DeserializeFailure::ExpectedNull.into());
+            }
+            Ok(None)
+        }
+    }
+}
+
+impl cbor_event::se::Serialize for PartyInfo {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(3))?;
+        serialize_nullable_bytes(&self.identity, serializer)?;
+        match &self.nonce {
+            Some(nonce) => nonce.serialize(serializer),
+            None => serializer.write_special(cbor_event::Special::Null),
+        }?;
+        serialize_nullable_bytes(&self.other, serializer)
+    }
+}
+
+impl Deserialize for PartyInfo {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(3)?;
+            let identity = deserialize_nullable(raw, |raw| Ok(raw.bytes()?))
+                .map_err(|e| e.annotate("identity"))?;
+            let nonce = deserialize_nullable(raw, |raw| match raw.cbor_type()? {
+                cbor_event::Type::Bytes | cbor_event::Type::UnsignedInteger | cbor_event::Type::NegativeInteger => CBORValue::deserialize(raw),
+                _ => Err(DeserializeFailure::NoVariantMatched.into()),
+            }).map_err(|e| e.annotate("nonce"))?;
+            let other = deserialize_nullable(raw, |raw| Ok(raw.bytes()?))
+                .map_err(|e| e.annotate("other"))?;
+            match len {
+                cbor_event::Len::Len(_) => (),
+                cbor_event::Len::Indefinite => match raw.special()? {
+                    cbor_event::Special::Break => (),
+                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
+                },
+            }
+            Ok(PartyInfo {
+                identity,
+                nonce,
+                other,
+            })
+        })().map_err(|e| e.annotate("PartyInfo"))
+    }
+}
+
+impl cbor_event::se::Serialize for SuppPubInfo {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(if self.other.is_some() {3} else {2}))?;
+        serializer.write_unsigned_integer(self.key_data_length as u64)?;
+        self.protected.serialize(serializer)?;
+        if let Some(other) = &self.other {
+            serializer.write_bytes(other)?;
+        }
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for SuppPubInfo {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            use std::convert::TryFrom;
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(2)?;
+            let key_data_length = raw.unsigned_integer()
+                .map_err(DeserializeError::from)
+                .and_then(|length| u32::try_from(length).map_err(|_| DeserializeFailure::FixedValueMismatch{
+                    found: Key::Uint(length),
+                    expected: Key::Str(String::from("32-bit key data length")),
+                }.into()))
+                .map_err(|e| e.annotate("key_data_length"))?;
+            let protected = ProtectedHeaderMap::deserialize(raw).map_err(|e| e.annotate("protected"))?;
+            let other = match len {
+                cbor_event::Len::Len(2) => None,
+                cbor_event::Len::Len(_) => {
+                    read_len.read_elems(1)?;
+                    Some(raw.bytes().map_err(|e| DeserializeError::from(e).annotate("other"))?)
+                },
+                cbor_event::Len::Indefinite => match raw.cbor_type()? {
+                    cbor_event::Type::Special => None,
+                    _ => Some(raw.bytes().map_err(|e| DeserializeError::from(e).annotate("other"))?),
+                },
+            };
+            read_len.finish()?;
+            if len == cbor_event::Len::Indefinite && raw.special()? != cbor_event::Special::Break {
+                return Err(DeserializeFailure::EndingBreakMissing.into());
+            }
+            Ok(SuppPubInfo {
+                key_data_length,
+                protected,
+                other,
+            })
+        })().map_err(|e| e.annotate("SuppPubInfo"))
+    }
+}
+
+impl cbor_event::se::Serialize for COSEKDFContext {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(if self.supp_priv_info.is_some() {5} else {4}))?;
+        self.algorithm_id.serialize(serializer)?;
+        self.party_u_info.serialize(serializer)?;
+        self.party_v_info.serialize(serializer)?;
+        self.supp_pub_info.serialize(serializer)?;
+        if let Some(supp_priv_info) = &self.supp_priv_info {
+            serializer.write_bytes(supp_priv_info)?;
+        }
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for COSEKDFContext {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(4)?;
+            let algorithm_id = Label::deserialize(raw).map_err(|e| e.annotate("algorithm_id"))?;
+            let party_u_info = PartyInfo::deserialize(raw).map_err(|e| e.annotate("party_u_info"))?;
+            let party_v_info = PartyInfo::deserialize(raw).map_err(|e| e.annotate("party_v_info"))?;
+            let supp_pub_info = SuppPubInfo::deserialize(raw).map_err(|e| e.annotate("supp_pub_info"))?;
+            let supp_priv_info = match len {
+                cbor_event::Len::Len(4) => None,
+                cbor_event::Len::Len(_) => {
+                    read_len.read_elems(1)?;
+                    Some(raw.bytes().map_err(|e| DeserializeError::from(e).annotate("supp_priv_info"))?)
+                },
+                cbor_event::Len::Indefinite => match raw.cbor_type()? {
+                    cbor_event::Type::Special => None,
+                    _ => Some(raw.bytes().map_err(|e| DeserializeError::from(e).annotate("supp_priv_info"))?),
+                },
+            };
+            read_len.finish()?;
+            if len == cbor_event::Len::Indefinite && raw.special()? != cbor_event::Special::Break {
+                return Err(DeserializeFailure::EndingBreakMissing.into());
+            }
+            Ok(COSEKDFContext {
+                algorithm_id,
+                party_u_info,
+                party_v_info,
+                supp_pub_info,
+                supp_priv_info,
+            })
+        })().map_err(|e| e.annotate("COSEKDFContext"))
+    }
+}
+
 impl cbor_event::se::Serialize for COSEEncrypt0 {
     fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
         serializer.write_array(cbor_event::Len::Len(3))?;
@@ -675,6 +907,7 @@ impl cbor_event::se::Serialize for COSEEncrypt0 {
 impl Deserialize for COSEEncrypt0 {
     fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
         (|| -> Result<_, DeserializeError> {
+            deserialize_optional_tag(raw, COSE_ENCRYPT0_TAG)?;
             let len = raw.array()?;
             let mut read_len = CBORReadLen::new(len);
             read_len.read_elems(3)?;
@@ -722,6 +955,8 @@ impl Deserialize for PasswordEncryption {
         if tag != 16 {
             return Err(DeserializeError::new("PasswordEncryption", DeserializeFailure::TagMismatch{ found: tag, expected: 16 }));
         }
+        // the inner message would otherwise accept a second tag
+        check_tag_policy(raw, TagPolicy::Forbidden).map_err(|e| e.annotate("PasswordEncryption"))?;
         Ok(Self(COSEEncrypt0::deserialize(raw)?))
     }
 }
@@ -772,6 +1007,7 @@ impl cbor_event::se::Serialize for COSEEncrypt {
 impl Deserialize for COSEEncrypt {
     fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
         (|| -> Result<_, DeserializeError> {
+            deserialize_optional_tag(raw, COSE_ENCRYPT_TAG)?;
             let len = raw.array()?;
             let mut read_len = CBORReadLen::new(len);
             read_len.read_elems(4)?;
@@ -874,10 +1110,366 @@ impl Deserialize for PubKeyEncryption {
         if tag != 96 {
             return Err(DeserializeError::new("PubKeyEncryption", DeserializeFailure::TagMismatch{ found: tag, expected: 96 }));
         }
+        // the inner message would otherwise accept a second tag
+        check_tag_policy(raw, TagPolicy::Forbidden).map_err(|e| e.annotate("PubKeyEncryption"))?;
         Ok(Self(COSEEncrypt::deserialize(raw)?))
     }
 }
 
+impl cbor_event::se::Serialize for MacStructure {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(4))?;
+        let context_str = match self.context {
+            MacContext::Mac => "MAC",
+            MacContext::Mac0 => "MAC0",
+        };
+        serializer.write_text(context_str)?;
+        self.protected.serialize(serializer)?;
+        serializer.write_bytes(&self.external_aad)?;
+        serializer.write_bytes(&self.payload)?;
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for MacStructure {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(4)?;
+            let context = (|| -> Result<_, DeserializeError> {
+                match raw.text()?.as_str() {
+                    "MAC" => Ok(MacContext::Mac),
+                    "MAC0" => Ok(MacContext::Mac0),
+                    other => Err(DeserializeFailure::FixedValueMismatch{
+                        found: Key::Str(String::from(other)),
+                        expected: Key::Str(String::from("MAC or MAC0")),
+                    }.into())
+                }
+            })().map_err(|e| e.annotate("context"))?;
+            let protected = ProtectedHeaderMap::deserialize(raw).map_err(|e| e.annotate("protected"))?;
+            let external_aad = raw.bytes().map_err(|e| DeserializeError::from(e).annotate("external_aad"))?;
+            let payload = raw.bytes().map_err(|e| DeserializeError::from(e).annotate("payload"))?;
+            match len {
+                cbor_event::Len::Len(_) => (),
+                cbor_event::Len::Indefinite => match raw.special()? {
+                    cbor_event::Special::Break => (),
+                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
+                },
+            }
+            Ok(MacStructure {
+                context,
+                protected,
+                external_aad,
+                payload,
+            })
+        })().map_err(|e| e.annotate("MacStructure"))
+    }
+}
+
+// nil (detached) or bytes
+fn deserialize_nullable_payload<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Option<Vec<u8>>, DeserializeError> {
+    Ok(match raw.cbor_type()? != cbor_event::Type::Special {
+        true => Some(raw.bytes()?),
+        false => {
+            if raw.special()? != cbor_event::Special::Null {
+                return Err(DeserializeFailure::ExpectedNull.into());
+            }
+            None
+        }
+    })
+}
+
+impl cbor_event::se::Serialize for COSEMac0 {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(4))?;
+        self.headers.serialize_as_embedded_group(serializer)?;
+        serialize_nullable_bytes(&self.payload, serializer)?;
+        serializer.write_bytes(&self.tag)?;
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for COSEMac0 {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            deserialize_optional_tag(raw, COSE_MAC0_TAG)?;
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(4)?;
+            let headers = Headers::deserialize_as_embedded_group(raw, &mut read_len, len).map_err(|e| e.annotate("headers"))?;
+            let payload = deserialize_nullable_payload(raw).map_err(|e| e.annotate("payload"))?;
+            let tag = raw.bytes().map_err(|e| DeserializeError::from(e).annotate("tag"))?;
+            match len {
+                cbor_event::Len::Len(_) => (),
+                cbor_event::Len::Indefinite => match raw.special()? {
+                    cbor_event::Special::Break => (),
+                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
+                },
+            }
+            Ok(COSEMac0 {
+                headers,
+                payload,
+                tag,
+            })
+        })().map_err(|e| e.annotate("COSEMac0"))
+    }
+}
+
+impl cbor_event::se::Serialize for COSEMac {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(5))?;
+        self.headers.serialize_as_embedded_group(serializer)?;
+        serialize_nullable_bytes(&self.payload, serializer)?;
+        serializer.write_bytes(&self.tag)?;
+        self.recipients.serialize(serializer)?;
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for COSEMac {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        (|| -> Result<_, DeserializeError> {
+            deserialize_optional_tag(raw, COSE_MAC_TAG)?;
+            let len = raw.array()?;
+            let mut read_len = CBORReadLen::new(len);
+            read_len.read_elems(5)?;
+            let headers = Headers::deserialize_as_embedded_group(raw, &mut read_len, len).map_err(|e| e.annotate("headers"))?;
+            let payload = deserialize_nullable_payload(raw).map_err(|e| e.annotate("payload"))?;
+            let tag = raw.bytes().map_err(|e| DeserializeError::from(e).annotate("tag"))?;
+            let recipients = COSERecipients::deserialize(raw).map_err(|e| e.annotate("recipients"))?;
+            match len {
+                cbor_event::Len::Len(_) => (),
+                cbor_event::Len::Indefinite => match raw.special()? {
+                    cbor_event::Special::Break => (),
+                    _ => return Err(DeserializeFailure::EndingBreakMissing.into()),
+                },
+            }
+            Ok(COSEMac {
+                headers,
+                payload,
+                tag,
+                recipients,
+            })
+        })().map_err(|e| e.annotate("COSEMac"))
+    }
+}
+
+impl cbor_event::se::Serialize for COSEMessageEnum {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        match self {
+            COSEMessageEnum::Sign(x) => x.serialize(serializer),
+            COSEMessageEnum::Sign1(x) => x.serialize(serializer),
+            COSEMessageEnum::Encrypt(x) => x.serialize(serializer),
+            COSEMessageEnum::Encrypt0(x) => x.serialize(serializer),
+            COSEMessageEnum::Mac(x) => x.serialize(serializer),
+            COSEMessageEnum::Mac0(x) => x.serialize(serializer),
+        }
+    }
+}
+
+// parses a T, rewinding to `position` on failure so the next variant can be tried
+fn try_deserialize<T: Deserialize, R: BufRead + Seek>(raw: &mut Deserializer<R>, position: u64) -> Option<T> {
+    match T::deserialize(raw) {
+        Ok(variant) => Some(variant),
+        Err(_) => {
+            raw.as_mut_ref().seek(SeekFrom::Start(position)).unwrap();
+            None
+        },
+    }
+}
+
+impl COSEMessageEnum {
+    // the variant reads the tag itself
+    fn deserialize_tagged<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        let initial_position = raw.as_mut_ref().stream_position().unwrap();
+        let tag = raw.tag()?;
+        raw.as_mut_ref().seek(SeekFrom::Start(initial_position)).unwrap();
+        Ok(match tag {
+            COSE_SIGN_TAG => COSEMessageEnum::Sign(COSESign::deserialize(raw)?),
+            COSE_SIGN1_TAG => COSEMessageEnum::Sign1(COSESign1::deserialize(raw)?),
+            COSE_ENCRYPT_TAG => COSEMessageEnum::Encrypt(COSEEncrypt::deserialize(raw)?),
+            COSE_ENCRYPT0_TAG => COSEMessageEnum::Encrypt0(COSEEncrypt0::deserialize(raw)?),
+            COSE_MAC_TAG => COSEMessageEnum::Mac(COSEMac::deserialize(raw)?),
+            COSE_MAC0_TAG => COSEMessageEnum::Mac0(COSEMac0::deserialize(raw)?),
+            other => return Err(DeserializeFailure::FixedValueMismatch{
+                found: Key::Uint(other),
+                expected: Key::Str(String::from("a COSE message tag (16, 17, 18, 96, 97 or 98)")),
+            }.into()),
+        })
+    }
+
+    // untagged COSE_Sign1 and COSE_Mac0 are both [headers, payload, bstr] as are COSE_Sign and
+    // COSE_Encrypt [headers, bstr, [[headers, bstr]]] so those are told apart by the algorithm
+    fn deserialize_untagged<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        let initial_position = raw.as_mut_ref().stream_position().unwrap();
+        // the structs don't check for extra elements in definite length arrays so go by the length first
+        let definite_len = match raw.array()? {
+            cbor_event::Len::Len(n) => Some(n),
+            cbor_event::Len::Indefinite => None,
+        };
+        raw.as_mut_ref().seek(SeekFrom::Start(initial_position)).unwrap();
+        let has_len = |n: u64| !matches!(definite_len, Some(len) if len != n);
+        if has_len(3) {
+            if let Some(variant) = try_deserialize::<COSEEncrypt0, _>(raw, initial_position) {
+                return Ok(COSEMessageEnum::Encrypt0(variant));
+            }
+        }
+        if has_len(5) {
+            if let Some(variant) = try_deserialize::<COSEMac, _>(raw, initial_position) {
+                return Ok(COSEMessageEnum::Mac(variant));
+            }
+        }
+        if !has_len(4) {
+            return Err(DeserializeFailure::NoVariantMatched.into());
+        }
+        if let Some(variant) = try_deserialize::<COSESign1, _>(raw, initial_position) {
+            if mac::mac_algorithm(&variant.headers).is_err() {
+                return Ok(COSEMessageEnum::Sign1(variant));
+            }
+            raw.as_mut_ref().seek(SeekFrom::Start(initial_position)).unwrap();
+            if let Some(variant) = try_deserialize::<COSEMac0, _>(raw, initial_position) {
+                return Ok(COSEMessageEnum::Mac0(variant));
+            }
+        }
+        if let Some(variant) = try_deserialize::<COSEEncrypt, _>(raw, initial_position) {
+            if encryption::content_algorithm(&variant.headers).is_ok() {
+                return Ok(COSEMessageEnum::Encrypt(variant));
+            }
+            raw.as_mut_ref().seek(SeekFrom::Start(initial_position)).unwrap();
+        }
+        if let Some(variant) = try_deserialize::<COSESign, _>(raw, initial_position) {
+            return Ok(COSEMessageEnum::Sign(variant));
+        }
+        Err(DeserializeFailure::NoVariantMatched.into())
+    }
+}
+
+impl Deserialize for COSEMessageEnum {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        let result = match raw.cbor_type()? {
+            cbor_event::Type::Tag => Self::deserialize_tagged(raw),
+            _ => Self::deserialize_untagged(raw),
+        };
+        result.map_err(|e| e.annotate("COSEMessage"))
+    }
+}
+
+impl cbor_event::se::Serialize for COSEMessage {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Deserialize for COSEMessage {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        Ok(Self(COSEMessageEnum::deserialize(raw)?))
+    }
+}
+
+impl CBORTagged for COSESign1 {
+    fn cbor_tag(&self) -> u64 {
+        COSE_SIGN1_TAG
+    }
+}
+
+impl CBORTagged for COSESign {
+    fn cbor_tag(&self) -> u64 {
+        COSE_SIGN_TAG
+    }
+}
+
+impl CBORTagged for COSEEncrypt0 {
+    fn cbor_tag(&self) -> u64 {
+        COSE_ENCRYPT0_TAG
+    }
+}
+
+impl CBORTagged for COSEEncrypt {
+    fn cbor_tag(&self) -> u64 {
+        COSE_ENCRYPT_TAG
+    }
+}
+
+impl CBORTagged for COSEMac0 {
+    fn cbor_tag(&self) -> u64 {
+        COSE_MAC0_TAG
+    }
+}
+
+impl CBORTagged for COSEMac {
+    fn cbor_tag(&self) -> u64 {
+        COSE_MAC_TAG
+    }
+}
+
+impl CBORTagged for SignedMessage {
+    fn cbor_tag(&self) -> u64 {
+        match &self.0 {
+            SignedMessageEnum::COSESIGN(x) => x.cbor_tag(),
+            SignedMessageEnum::COSESIGN1(x) => x.cbor_tag(),
+        }
+    }
+}
+
+impl CBORTagged for COSEMessage {
+    fn cbor_tag(&self) -> u64 {
+        match &self.0 {
+            COSEMessageEnum::Sign(x) => x.cbor_tag(),
+            COSEMessageEnum::Sign1(x) => x.cbor_tag(),
+            COSEMessageEnum::Encrypt(x) => x.cbor_tag(),
+            COSEMessageEnum::Encrypt0(x) => x.cbor_tag(),
+            COSEMessageEnum::Mac(x) => x.cbor_tag(),
+            COSEMessageEnum::Mac0(x) => x.cbor_tag(),
+        }
+    }
+}
+
+// Reads the RFC 8152 tag if there is one, which must be `expected`, so tagged and untagged messages are both accepted
+fn deserialize_optional_tag<R: BufRead + Seek>(raw: &mut Deserializer<R>, expected: u64) -> Result<(), DeserializeError> {
+    if raw.cbor_type()? == cbor_event::Type::Tag {
+        let tag = raw.tag()?;
+        if tag != expected {
+            return Err(DeserializeFailure::TagMismatch{ found: tag, expected }.into());
+        }
+    }
+    Ok(())
+}
+
+impl cbor_event::se::Serialize for COSEKeySet {
+    fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
+        serializer.write_array(cbor_event::Len::Len(self.0.len() as u64))?;
+        for element in &self.0 {
+            element.serialize(serializer)?;
+        }
+        Ok(serializer)
+    }
+}
+
+impl Deserialize for COSEKeySet {
+    fn deserialize<R: BufRead + Seek>(raw: &mut Deserializer<R>) -> Result<Self, DeserializeError> {
+        let mut arr = Vec::new();
+        (|| -> Result<_, DeserializeError> {
+            let len = raw.array()?;
+            while match len { cbor_event::Len::Len(n) => arr.len() < n as usize, cbor_event::Len::Indefinite => true, } {
+                if raw.cbor_type()? == cbor_event::Type::Special {
+                    if raw.special()? != cbor_event::Special::Break {
+                        return Err(DeserializeFailure::EndingBreakMissing.into());
+                    }
+                    break;
+                }
+                arr.push(COSEKey::deserialize(raw)?);
+            }
+            // COSE_KeySet = [+COSE_Key]
+            if arr.is_empty() {
+                return Err(cbor_event::Error::CustomError(String::from("COSE_KeySet must contain at least one key")).into());
+            }
+            Ok(())
+        })().map_err(|e| e.annotate("COSEKeySet"))?;
+        Ok(Self(arr))
+    }
+}
+
 impl cbor_event::se::Serialize for COSEKey {
     fn serialize<'se, W: Write>(&self, serializer: &'se mut Serializer<W>) -> cbor_event::Result<&'se mut Serializer<W>> {
         serializer.write_map(cbor_event::Len::Len(self.other_headers.len() as u64 + 1 + match &self.key_id { Some(_) => 1, None => 0 } + match &self.algorithm_id { Some(_) => 1, None => 0 } + match &self.key_ops { Some(_) => 1, None => 0 } + match &self.base_init_vector { Some(_) => 1, None => 0 }))?;
@@ -1188,6 +1780,72 @@ mod tests {
         deser_test(no_payload);
     }
 
+    #[test]
+    fn tagged_messages() {
+        let headers = Headers::new(&ProtectedHeaderMap::new_empty(), &HeaderMap::new());
+        let mut sigs = COSESignatures::new();
+        sigs.add(&COSESignature::new(&headers, vec![57u8; 37]));
+        let sign = COSESign::new(&headers, Some(vec![64u8; 39]), &sigs);
+        let sign1 = COSESign1::new(&headers, Some(vec![64u8; 39]), vec![1u8, 2u8, 100u8]);
+
+        let tagged_sign = sign.to_tagged_bytes();
+        let tagged_sign1 = sign1.to_tagged_bytes();
+        assert_eq!(tagged_sign[..2], [0xd8, 0x62]);
+        assert_eq!(tagged_sign[2..], sign.to_bytes()[..]);
+        assert_eq!(tagged_sign1[0], 0xd2);
+        assert_eq!(tagged_sign1[1..], sign1.to_bytes()[..]);
+        assert_eq!(COSESign::from_bytes(tagged_sign.clone()).unwrap().to_bytes(), sign.to_bytes());
+        assert_eq!(COSESign1::from_bytes(tagged_sign1.clone()).unwrap().to_bytes(), sign1.to_bytes());
+
+        let signed = SignedMessage::from_bytes(tagged_sign.clone()).unwrap();
+        assert_eq!(signed.kind(), SignedMessageKind::COSESIGN);
+        assert_eq!(signed.to_tagged_bytes(), tagged_sign);
+        let signed = SignedMessage::from_bytes(tagged_sign1.clone()).unwrap();
+        assert_eq!(signed.kind(), SignedMessageKind::COSESIGN1);
+        assert_eq!(COSEMessage::from_bytes(signed.to_tagged_bytes()).unwrap().to_tagged_bytes(), tagged_sign1);
+
+        // the tag must match the structure
+        let mismatched = COSESign1::from_bytes(tagged_sign.clone()).unwrap_err();
+        assert!(matches!(mismatched.failure, DeserializeFailure::TagMismatch{ found: 98, expected: 18 }));
+        let mut sign1_as_sign = vec![0xd8, 0x62];
+        sign1_as_sign.extend(sign1.to_bytes());
+        assert!(SignedMessage::from_bytes(sign1_as_sign).is_err());
+        let mut sign1_as_mac0 = vec![0xd1];
+        sign1_as_mac0.extend(sign1.to_bytes());
+        assert!(SignedMessage::from_bytes(sign1_as_mac0).is_err());
+
+        assert!(COSESign1::from_bytes_with_tag_policy(tagged_sign1.clone(), TagPolicy::Required).is_ok());
+        assert!(COSESign1::from_bytes_with_tag_policy(sign1.to_bytes(), TagPolicy::Forbidden).is_ok());
+        assert!(SignedMessage::from_bytes_with_tag_policy(sign.to_bytes(), TagPolicy::Optional).is_ok());
+        let missing = COSESign1::from_bytes_with_tag_policy(sign1.to_bytes(), TagPolicy::Required).unwrap_err();
+        assert!(matches!(missing.failure, DeserializeFailure::MissingTag));
+        let forbidden = SignedMessage::from_bytes_with_tag_policy(tagged_sign, TagPolicy::Forbidden).unwrap_err();
+        assert!(matches!(forbidden.failure, DeserializeFailure::UnexpectedTag(98)));
+
+        // only one tag
+        let mut doubly_tagged_sign1 = vec![0xd2];
+        doubly_tagged_sign1.extend(&tagged_sign1);
+        assert!(COSESign1::from_bytes(doubly_tagged_sign1.clone()).is_err());
+        assert!(SignedMessage::from_bytes(doubly_tagged_sign1.clone()).is_err());
+        assert!(COSEMessage::from_bytes(doubly_tagged_sign1).is_err());
+        let encrypt0 = COSEEncrypt0::new(&headers, Some(vec![3u8; 20]));
+        let password = PasswordEncryption::new(&encrypt0);
+        assert_eq!(password.to_bytes()[0], 0xd0);
+        assert!(PasswordEncryption::from_bytes(password.to_bytes()).is_ok());
+        let mut doubly_tagged_encrypt0 = vec![0xd0];
+        doubly_tagged_encrypt0.extend(password.to_bytes());
+        assert!(PasswordEncryption::from_bytes(doubly_tagged_encrypt0.clone()).is_err());
+        assert!(COSEEncrypt0::from_bytes(doubly_tagged_encrypt0.clone()).is_err());
+        assert!(COSEMessage::from_bytes(doubly_tagged_encrypt0).is_err());
+        let mut recipients = COSERecipients::new();
+        recipients.add(&COSERecipient::new(&headers, Some(vec![1u8; 40])));
+        let pub_key = PubKeyEncryption::new(&COSEEncrypt::new(&headers, Some(vec![3u8; 20]), &recipients));
+        assert!(PubKeyEncryption::from_bytes(pub_key.to_bytes()).is_ok());
+        let mut doubly_tagged_encrypt = vec![0xd8, 0x60];
+        doubly_tagged_encrypt.extend(pub_key.to_bytes());
+        assert!(PubKeyEncryption::from_bytes(doubly_tagged_encrypt).is_err());
+    }
+
     #[test]
     fn sig_structure_sign() {
         let mut sig_struct = SigStructure::new(
@@ -1220,6 +1878,53 @@ mod tests {
         deser_test(sig_struct);
     }
 
+    #[test]
+    fn enc_structure() {
+        let mut header_map = HeaderMap::new();
+        header_map.set_algorithm_id(&label_int(24));
+        for context in [EncContext::Encrypt, EncContext::Encrypt0, EncContext::EncRecipient, EncContext::MacRecipient, EncContext::RecRecipient].iter() {
+            let enc_struct = EncStructure::new(*context, &ProtectedHeaderMap::new(&header_map), vec![5u8; 9]);
+            deser_test(enc_struct);
+        }
+        let enc_struct = EncStructure::new(EncContext::EncRecipient, &ProtectedHeaderMap::new_empty(), vec![]);
+        assert_eq!(enc_struct.to_bytes(), hex::decode("836d456e635f526563697069656e744040").unwrap());
+    }
+
+    #[test]
+    fn kdf_context() {
+        let mut protected = HeaderMap::new();
+        protected.set_algorithm_id(&label_int(-31));
+        protected.set_salt(vec![9u8; 16]);
+        protected.set_party_u_identity(b"u".to_vec());
+        protected.set_party_u_nonce(&CBORValue::new_int(&Int::new_i32(5))).unwrap();
+        protected.set_party_v_other(vec![]);
+        assert!(protected.set_party_v_nonce(&CBORValue::new_text(String::from("nonce"))).is_err());
+        let parsed = HeaderMap::from_bytes(protected.to_bytes()).unwrap();
+        assert_eq!(parsed.salt(), Some(vec![9u8; 16]));
+        assert_eq!(parsed.header(&label_int(-21)), Some(CBORValue::new_bytes(b"u".to_vec())));
+        assert_eq!(parsed.party_u_nonce().and_then(|nonce| nonce.as_int()).and_then(|nonce| nonce.as_i32()), Some(5));
+        assert_eq!(parsed.party_v_other(), Some(vec![]));
+        assert_eq!(parsed.party_v_identity(), None);
+        let mut party_u = PartyInfo::new();
+        party_u.set_identity(vec![1u8; 4]);
+        party_u.set_nonce(&CBORValue::new_int(&Int::new_i32(-7))).unwrap();
+        let mut party_v = PartyInfo::new();
+        party_v.set_nonce(&CBORValue::new_bytes(vec![2u8; 8])).unwrap();
+        party_v.set_other(vec![]);
+        assert!(party_v.set_nonce(&CBORValue::new_text(String::from("nonce"))).is_err());
+        let mut supp_pub_info = SuppPubInfo::new(256, &ProtectedHeaderMap::new(&protected));
+        deser_test(supp_pub_info.clone());
+        supp_pub_info.set_other(vec![3u8; 2]);
+        let mut context = COSEKDFContext::new(&label_int(-5), &party_u, &party_v, &supp_pub_info);
+        deser_test(context.clone());
+        context.set_supp_priv_info(vec![4u8; 5]);
+        deser_test(context);
+
+        // [-5, [nil, nil, nil], [nil, nil, nil], [256, h'']]
+        let context = COSEKDFContext::new(&label_int(-5), &PartyInfo::new(), &PartyInfo::new(), &SuppPubInfo::new(256, &ProtectedHeaderMap::new_empty()));
+        assert_eq!(context.to_bytes(), hex::decode("842483f6f6f683f6f6f682190100 40".replace(' ', "")).unwrap());
+    }
+
     #[test]
     fn cose_key() {
         let mut cose_key = COSEKey::new(&label_int(8));
@@ -0,0 +1,364 @@
+// Chunked streaming encryption for payloads too large to hold in memory, following the STREAM
+// construction (Hoang, Reyhanitabar, Rogaway and Vizár - "Online Authenticated-Encryption and its
+// Nonce-Reuse Misuse-Resistance"): the plaintext is split into fixed-size segments that are each
+// encrypted with the content algorithm under a nonce derived from the IV, the segment counter and
+// a flag marking the final segment. Moving, dropping or appending segments changes the nonce they're
+// decrypted with, so any reordering or truncation makes decryption fail.
+//
+// The stream starts with a COSE_Encrypt0 with a detached ciphertext whose headers describe the
+// encryption, prefixed by its length as a 4-byte big-endian integer:
+// [len][COSE_Encrypt0][segment 0]..[segment n]
+// where every segment but the final one has chunk_size bytes of plaintext plus the tag.
+
+use super::*;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read};
+
+/// Protected header with the plaintext length of every segment but the final one
+pub const STREAM_CHUNK_SIZE: &str = "chunk_size";
+pub const DEFAULT_STREAM_CHUNK_SIZE: u32 = 64 * 1024;
+pub const MAX_STREAM_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+const TAG_LEN: usize = 16;
+// the leading COSE_Encrypt0 is small - this only guards against allocating from a corrupt length
+const MAX_HEADER_LEN: u32 = 64 * 1024;
+
+fn chunk_size_label() -> Label {
+    Label::new_text(String::from(STREAM_CHUNK_SIZE))
+}
+
+fn check_chunk_size(chunk_size: u32) -> Result<(), JsError> {
+    if chunk_size == 0 || chunk_size > MAX_STREAM_CHUNK_SIZE {
+        return Err(JsError::from_str(&format!("Chunk size must be between 1 and {} bytes, found {}", MAX_STREAM_CHUNK_SIZE, chunk_size)));
+    }
+    Ok(())
+}
+
+// IV XOR (counter || final flag) with the 32-bit big-endian counter before the last byte
+fn segment_nonce(iv: &[u8], counter: u32, is_final: bool) -> Vec<u8> {
+    let mut nonce = iv.to_vec();
+    let len = nonce.len();
+    for (byte, counter_byte) in nonce[len - 5..len - 1].iter_mut().zip(counter.to_be_bytes().iter()) {
+        *byte ^= counter_byte;
+    }
+    nonce[len - 1] ^= is_final as u8;
+    nonce
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn to_io_error(e: JsError) -> io::Error {
+    invalid_data(&e.as_string().unwrap_or_default())
+}
+
+// the key material shared by both directions
+struct SegmentCipher {
+    alg: AlgorithmId,
+    key: Vec<u8>,
+    iv: Vec<u8>,
+    aad: Vec<u8>,
+    chunk_size: usize,
+    counter: u32,
+}
+
+impl SegmentCipher {
+    fn new(headers: &Headers, key: &COSEKey, op: KeyOperation, external_aad: Option<Vec<u8>>) -> Result<Self, JsError> {
+        let alg = encryption::content_algorithm(headers)?;
+        let k = encryption::symmetric_key(key, alg, op)?;
+        encryption::check_content_key(alg, &k)?;
+        let iv = encryption::layer_init_vector(headers, key.base_init_vector())?
+            .ok_or_else(|| JsError::from_str("Missing IV"))?;
+        encryption::check_init_vector(&iv)?;
+        let chunk_size = headers.protected
+            .deserialized_headers()
+            .header(&chunk_size_label())
+            .and_then(|size| size.as_int())
+            .and_then(|size| size.as_i32())
+            .filter(|size| *size > 0)
+            .ok_or_else(|| JsError::from_str("Missing or invalid chunk size in the protected headers"))?;
+        check_chunk_size(chunk_size as u32)?;
+        Ok(Self {
+            alg,
+            key: k,
+            iv,
+            aad: EncStructure::new(EncContext::Encrypt0, &headers.protected, external_aad.unwrap_or_default()).to_bytes(),
+            chunk_size: chunk_size as usize,
+            counter: 0,
+        })
+    }
+
+    fn next_nonce(&mut self, is_final: bool) -> Result<Vec<u8>, JsError> {
+        let nonce = segment_nonce(&self.iv, self.counter, is_final);
+        self.counter = self.counter
+            .checked_add(1)
+            .ok_or_else(|| JsError::from_str("Too many segments - use a larger chunk size"))?;
+        Ok(nonce)
+    }
+}
+
+/// Encrypts everything written to it to `inner` as a stream of segments.
+/// `finish()` must be called to write the final segment - a stream that was never finished is
+/// rejected as truncated when decrypting.
+pub struct StreamEncryptor<W: Write> {
+    inner: W,
+    cipher: SegmentCipher,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> StreamEncryptor<W> {
+    /// Writes the header to `inner`. The algorithm (AES-GCM or ChaCha20/Poly1305) must be set in the
+    /// protected headers of `headers`. The chunk size is put in the protected headers and a random IV is
+    /// generated as for `COSEEncrypt0::encrypt()`.
+    /// # Arguments
+    /// * `key` - symmetric COSE key for the content algorithm
+    /// * `chunk_size` - plaintext bytes per segment. See `DEFAULT_STREAM_CHUNK_SIZE`.
+    /// * `external_aad` - External application data - see RFC 8152 section 4.3. Set to None if not using this.
+    pub fn new(mut inner: W, headers: &Headers, key: &COSEKey, chunk_size: u32, external_aad: Option<Vec<u8>>) -> Result<Self, JsError> {
+        check_chunk_size(chunk_size)?;
+        let mut protected = headers.protected.deserialized_headers();
+        protected.set_header(&chunk_size_label(), &CBORValue::new_int(&Int::new_i32(chunk_size as i32)))?;
+        let mut headers = Headers::new(&ProtectedHeaderMap::new(&protected), &headers.unprotected);
+        encryption::generate_init_vector(&mut headers)?;
+        let cipher = SegmentCipher::new(&headers, key, KeyOperation::Encrypt, external_aad)?;
+        let header = COSEEncrypt0::new(&headers, None).to_bytes();
+        inner.write_u32::<BigEndian>(header.len() as u32)
+            .and_then(|_| inner.write_all(&header))
+            .map_err(|e| JsError::from_str(&format!("Could not write the stream header: {}", e)))?;
+        Ok(Self {
+            inner,
+            buffer: Vec::with_capacity(cipher.chunk_size),
+            cipher,
+        })
+    }
+
+    /// Writes the final segment with whatever is buffered and returns the inner writer
+    pub fn finish(mut self) -> Result<W, JsError> {
+        self.write_segment(true)
+            .and_then(|_| self.inner.flush())
+            .map_err(|e| JsError::from_str(&format!("Could not write the final segment: {}", e)))?;
+        Ok(self.inner)
+    }
+
+    fn write_segment(&mut self, is_final: bool) -> io::Result<()> {
+        let nonce = self.cipher.next_nonce(is_final).map_err(to_io_error)?;
+        let segment = encryption::aead_encrypt(self.cipher.alg, &self.cipher.key, &nonce, &self.cipher.aad, &self.buffer)
+            .map_err(to_io_error)?;
+        self.buffer.clear();
+        self.inner.write_all(&segment)
+    }
+}
+
+impl<W: Write> Write for StreamEncryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut remaining = buf;
+        while !remaining.is_empty() {
+            // a full chunk is only written once more data arrives as it could be the final one
+            if self.buffer.len() == self.cipher.chunk_size {
+                self.write_segment(false)?;
+            }
+            let take = remaining.len().min(self.cipher.chunk_size - self.buffer.len());
+            self.buffer.extend_from_slice(&remaining[..take]);
+            remaining = &remaining[take..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a stream written by `StreamEncryptor`. Every segment is authenticated before any of its
+/// plaintext is returned, but as the plaintext is returned as it is read the whole stream must be
+/// read until the end (`read()` returning 0) without an error before the plaintext can be trusted
+/// to be complete. Truncated, reordered or tampered streams give an `InvalidData` error.
+pub struct StreamDecryptor<R: Read> {
+    inner: R,
+    header: COSEEncrypt0,
+    cipher: SegmentCipher,
+    plaintext: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> StreamDecryptor<R> {
+    /// Reads the header from `inner`. See `StreamEncryptor::new()` for the arguments.
+    pub fn new(mut inner: R, key: &COSEKey, external_aad: Option<Vec<u8>>) -> Result<Self, JsError> {
+        let header_len = inner.read_u32::<BigEndian>()
+            .map_err(|e| JsError::from_str(&format!("Could not read the stream header: {}", e)))?;
+        if header_len > MAX_HEADER_LEN {
+            return Err(JsError::from_str(&format!("Stream header too long: {} bytes", header_len)));
+        }
+        let mut header = vec![0u8; header_len as usize];
+        inner.read_exact(&mut header)
+            .map_err(|e| JsError::from_str(&format!("Could not read the stream header: {}", e)))?;
+        let header = COSEEncrypt0::from_bytes(header)?;
+        if header.ciphertext.is_some() {
+            return Err(JsError::from_str("The stream header must have a detached ciphertext"));
+        }
+        let cipher = SegmentCipher::new(&header.headers, key, KeyOperation::Decrypt, external_aad)?;
+        Ok(Self {
+            inner,
+            header,
+            cipher,
+            plaintext: Vec::new(),
+            position: 0,
+            finished: false,
+        })
+    }
+
+    /// The COSE_Encrypt0 (without ciphertext) describing the stream e.g. to look at its key id
+    pub fn header(&self) -> COSEEncrypt0 {
+        self.header.clone()
+    }
+
+    // reads until `buf` is full or the end of the stream, returning how much was read
+    fn read_full(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.inner.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    }
+
+    fn read_segment(&mut self) -> io::Result<()> {
+        let mut segment = vec![0u8; self.cipher.chunk_size + TAG_LEN];
+        let len = self.read_full(&mut segment)?;
+        if len < TAG_LEN {
+            return Err(invalid_data("Stream truncated: the final segment is missing"));
+        }
+        segment.truncate(len);
+        let cipher = &self.cipher;
+        let decrypt = |is_final: bool| {
+            let nonce = segment_nonce(&cipher.iv, cipher.counter, is_final);
+            encryption::aead_decrypt(cipher.alg, &cipher.key, &nonce, &cipher.aad, &segment)
+        };
+        // a full segment is usually not the final one but can be if the plaintext was a multiple of the chunk size
+        let (plaintext, is_final) = match len == cipher.chunk_size + TAG_LEN {
+            true => match decrypt(false) {
+                Some(plaintext) => (Some(plaintext), false),
+                None => (decrypt(true), true),
+            },
+            false => (decrypt(true), true),
+        };
+        let plaintext = plaintext.ok_or_else(|| invalid_data(
+            "Segment could not be decrypted: the stream was truncated, reordered or tampered with or the key is wrong"))?;
+        self.cipher.next_nonce(is_final).map_err(to_io_error)?;
+        if is_final {
+            let mut trailing = [0u8; 1];
+            if self.read_full(&mut trailing)? != 0 {
+                return Err(invalid_data("Unexpected data after the final segment"));
+            }
+            self.finished = true;
+        }
+        self.plaintext = plaintext;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StreamDecryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.read_segment()?;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt(plaintext: &[u8], key: &COSEKey, chunk_size: u32) -> Vec<u8> {
+        let mut encryptor = StreamEncryptor::new(Vec::new(), &encryption::alg_headers(AlgorithmId::ChaCha20Poly1305), key, chunk_size, None).unwrap();
+        // uneven writes to exercise the buffering
+        for part in plaintext.chunks(7) {
+            encryptor.write_all(part).unwrap();
+        }
+        encryptor.finish().unwrap()
+    }
+
+    fn decrypt(stream: &[u8], key: &COSEKey) -> io::Result<Vec<u8>> {
+        let mut decryptor = StreamDecryptor::new(stream, key, None).map_err(to_io_error)?;
+        let mut plaintext = Vec::new();
+        decryptor.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = encryption::symmetric_cose_key(vec![1u8; 32]);
+        let plaintext: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        // final segment partial, exactly full and empty
+        for (len, chunk_size) in &[(1000, 64), (960, 64), (0, 64), (1000, 1000), (1, 1)] {
+            let stream = encrypt(&plaintext[..*len], &key, *chunk_size);
+            assert_eq!(decrypt(&stream, &key).unwrap(), plaintext[..*len].to_vec());
+        }
+        let stream = encrypt(&plaintext, &key, 100);
+        let decryptor = StreamDecryptor::new(&stream[..], &key, None).unwrap();
+        let protected = decryptor.header().headers().protected().deserialized_headers();
+        assert_eq!(protected.header(&chunk_size_label()).unwrap().as_int().unwrap().as_i32(), Some(100));
+        assert!(decrypt(&stream, &encryption::symmetric_cose_key(vec![2u8; 32])).is_err());
+
+        let key = encryption::symmetric_cose_key(vec![3u8; 16]);
+        let mut encryptor = StreamEncryptor::new(Vec::new(), &encryption::alg_headers(AlgorithmId::A128GCM), &key, 10, Some(b"backup".to_vec())).unwrap();
+        encryptor.write_all(&plaintext).unwrap();
+        let stream = encryptor.finish().unwrap();
+        let mut decryptor = StreamDecryptor::new(&stream[..], &key, Some(b"backup".to_vec())).unwrap();
+        let mut decrypted = Vec::new();
+        decryptor.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+        assert!(decrypt(&stream, &key).is_err());
+    }
+
+    #[test]
+    fn truncation_and_reordering() {
+        let key = encryption::symmetric_cose_key(vec![4u8; 32]);
+        let plaintext = vec![5u8; 250];
+        let stream = encrypt(&plaintext, &key, 100);
+        let header_len = 4 + u32::from_be_bytes([stream[0], stream[1], stream[2], stream[3]]) as usize;
+        let segment_len = 100 + TAG_LEN;
+        let (header, segments) = stream.split_at(header_len);
+        assert_eq!(segments.len(), 2 * segment_len + 50 + TAG_LEN);
+
+        // dropping the final segment, or everything after the header
+        assert!(decrypt(&stream[..header_len + 2 * segment_len], &key).is_err());
+        assert!(decrypt(header, &key).is_err());
+        // cutting into a segment
+        assert!(decrypt(&stream[..stream.len() - 1], &key).is_err());
+        // swapping the first two segments
+        let mut swapped = header.to_vec();
+        swapped.extend_from_slice(&segments[segment_len..2 * segment_len]);
+        swapped.extend_from_slice(&segments[..segment_len]);
+        swapped.extend_from_slice(&segments[2 * segment_len..]);
+        assert!(decrypt(&swapped, &key).is_err());
+        // a non-final segment passed off as the final one
+        let mut early_end = header.to_vec();
+        early_end.extend_from_slice(&segments[..segment_len]);
+        assert!(decrypt(&early_end, &key).is_err());
+        // trailing data
+        let mut trailing = stream.clone();
+        trailing.push(0);
+        assert!(decrypt(&trailing, &key).is_err());
+        // an unfinished stream
+        let mut encryptor = StreamEncryptor::new(Vec::new(), &encryption::alg_headers(AlgorithmId::ChaCha20Poly1305), &key, 100, None).unwrap();
+        encryptor.write_all(&plaintext).unwrap();
+        encryptor.flush().unwrap();
+        assert!(decrypt(&encryptor.inner, &key).is_err());
+        assert!(StreamEncryptor::new(Vec::new(), &encryption::alg_headers(AlgorithmId::ChaCha20Poly1305), &key, 0, None).is_err());
+    }
+}
@@ -79,6 +79,79 @@ macro_rules! to_from_bytes {
     }
 }
 
+/// Whether the CBOR tag (RFC 8152 section 2) of a COSE message may, must or must not be present
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum TagPolicy {
+    Optional,
+    Required,
+    Forbidden,
+}
+
+pub trait FromBytesWithTagPolicy {
+    fn from_bytes_with_tag_policy(data: Vec<u8>, policy: TagPolicy) -> Result<Self, DeserializeError> where Self: Sized;
+}
+
+// The tag a COSE message is serialized with by to_tagged_bytes()
+pub (crate) trait CBORTagged {
+    fn cbor_tag(&self) -> u64;
+}
+
+// Checks `policy` against the next item without consuming anything
+pub (crate) fn check_tag_policy<R: BufRead + Seek>(raw: &mut Deserializer<R>, policy: TagPolicy) -> Result<(), DeserializeError> {
+    let is_tagged = raw.cbor_type()? == cbor_event::Type::Tag;
+    match policy {
+        TagPolicy::Required if !is_tagged => Err(DeserializeFailure::MissingTag.into()),
+        TagPolicy::Forbidden if is_tagged => {
+            let initial_position = raw.as_mut_ref().stream_position().unwrap();
+            let tag = raw.tag()?;
+            raw.as_mut_ref().seek(std::io::SeekFrom::Start(initial_position)).unwrap();
+            Err(DeserializeFailure::UnexpectedTag(tag).into())
+        },
+        _ => Ok(()),
+    }
+}
+
+// to_bytes()/from_bytes() plus to_tagged_bytes() and from_bytes_with_tag_policy() for the COSE messages.
+// As with from_bytes!() the latter is a trait outside of wasm.
+#[macro_export]
+macro_rules! tagged_to_from_bytes {
+    ($name:ident) => {
+        to_from_bytes!($name);
+
+        #[wasm_bindgen]
+        impl $name {
+            /// Serializes with the RFC 8152 CBOR tag for this message type. `to_bytes()` is untagged.
+            pub fn to_tagged_bytes(&self) -> Vec<u8> {
+                let mut buf = Serializer::new_vec();
+                buf.write_tag(self.cbor_tag()).unwrap();
+                self.serialize(&mut buf).unwrap();
+                buf.finalize()
+            }
+        }
+
+        #[cfg(all(target_arch = "wasm32", not(target_os = "emscripten")))]
+        #[wasm_bindgen]
+        impl $name {
+            /// `from_bytes()` which also checks whether the message is tagged according to `policy`
+            pub fn from_bytes_with_tag_policy(bytes: Vec<u8>, policy: TagPolicy) -> Result<$name, JsError> {
+                let mut raw = Deserializer::from(std::io::Cursor::new(bytes));
+                check_tag_policy(&mut raw, policy).map_err(|e| e.annotate(stringify!($name)))?;
+                Ok(Self::deserialize(&mut raw)?)
+            }
+        }
+
+        #[cfg(not(all(target_arch = "wasm32", not(target_os = "emscripten"))))]
+        impl FromBytesWithTagPolicy for $name {
+            fn from_bytes_with_tag_policy(bytes: Vec<u8>, policy: TagPolicy) -> Result<$name, DeserializeError> {
+                let mut raw = Deserializer::from(std::io::Cursor::new(bytes));
+                check_tag_policy(&mut raw, policy).map_err(|e| e.annotate(stringify!($name)))?;
+                Self::deserialize(&mut raw)
+            }
+        }
+    }
+}
+
 #[macro_export]
 // Several of our label enums require negative values which are unsupported by wasm_bindgen
 // as it uses an u32 internal representation for enums so we instead provide Label::from_*